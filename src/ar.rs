@@ -0,0 +1,149 @@
+use super::*;
+
+/// The letters selecting an llvm-ar operation; everything else in the
+/// operation string is a modifier.
+const AR_OPERATIONS: &str = "dmpqrstx";
+
+/// The parts of an ar command line wasixcc cares about.
+#[derive(Debug, PartialEq, Eq)]
+struct ArArgs {
+    // Index of the operation string (e.g. `rcs` or `-rcs`) in the args
+    operation_index: usize,
+    thin: bool,
+    archive: Option<String>,
+    members: Vec<String>,
+}
+
+pub(crate) fn run(mut args: Vec<String>, user_settings: UserSettings) -> Result<()> {
+    if let Some(ar_args) = parse_ar_args(&args) {
+        if user_settings.ar_deterministic {
+            make_deterministic(&mut args[ar_args.operation_index]);
+        }
+
+        if ar_args.thin {
+            let archive = ar_args.archive.as_deref().unwrap_or("archive");
+            for member in unrelocatable_thin_members(&ar_args) {
+                print_warning(&format!(
+                    "Thin archive {archive} references {member} by absolute path; \
+                    the archive will break if it's moved to another machine or \
+                    the member is deleted"
+                ));
+            }
+        }
+    }
+
+    let mut command = Command::new(user_settings.get_tool_path("llvm-ar"));
+    command.args(args);
+    run_command(command, &user_settings)
+}
+
+fn parse_ar_args(args: &[String]) -> Option<ArArgs> {
+    let is_operation = |arg: &str| {
+        let letters = arg.strip_prefix('-').unwrap_or(arg);
+        !arg.starts_with("--")
+            && !letters.is_empty()
+            && letters.chars().all(|ch| ch.is_ascii_alphabetic())
+            && letters.chars().any(|ch| AR_OPERATIONS.contains(ch))
+    };
+
+    let operation_index = args.iter().position(|arg| is_operation(arg))?;
+    let operation = &args[operation_index];
+
+    let mut thin = operation.contains('T') || args.iter().any(|arg| arg == "--thin");
+
+    let mut positional = args[operation_index + 1..]
+        .iter()
+        .filter(|arg| {
+            if *arg == "--thin" {
+                thin = true;
+            }
+            !arg.starts_with("--") && !arg.starts_with('@')
+        })
+        .cloned();
+
+    // a/b/i take a relative position member, N takes a count
+    if operation.contains(['a', 'b', 'i']) {
+        positional.next();
+    }
+    if operation.contains('N') {
+        positional.next();
+    }
+
+    let archive = positional.next();
+    let members = positional.collect();
+
+    Some(ArArgs {
+        operation_index,
+        thin,
+        archive,
+        members,
+    })
+}
+
+pub(crate) fn make_deterministic(operation: &mut String) {
+    // U explicitly asks for real timestamps and uids, so leave it be
+    if !operation.contains(['D', 'U']) {
+        operation.push('D');
+    }
+}
+
+/// Thin archives only store paths to their members, so absolute paths (such as
+/// objects in a temp dir) won't survive the archive being relocated.
+fn unrelocatable_thin_members(ar_args: &ArArgs) -> Vec<&str> {
+    ar_args
+        .members
+        .iter()
+        .filter(|member| Path::new(member).is_absolute())
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_ar_args() {
+        let parsed = parse_ar_args(&args(&["--format=gnu", "rcs", "lib.a", "a.o", "b.o"])).unwrap();
+        assert_eq!(
+            parsed,
+            ArArgs {
+                operation_index: 1,
+                thin: false,
+                archive: Some("lib.a".to_string()),
+                members: args(&["a.o", "b.o"]),
+            }
+        );
+
+        let parsed = parse_ar_args(&args(&["-rbT", "a.o", "lib.a", "/tmp/b.o"])).unwrap();
+        assert!(parsed.thin);
+        assert_eq!(parsed.archive, Some("lib.a".to_string()));
+        assert_eq!(parsed.members, args(&["/tmp/b.o"]));
+        assert_eq!(unrelocatable_thin_members(&parsed), vec!["/tmp/b.o"]);
+
+        let parsed = parse_ar_args(&args(&["qc", "--thin", "lib.a", "a.o"])).unwrap();
+        assert!(parsed.thin);
+        assert!(unrelocatable_thin_members(&parsed).is_empty());
+
+        assert_eq!(parse_ar_args(&args(&["--version"])), None);
+    }
+
+    #[test]
+    fn test_make_deterministic() {
+        let mut operation = "rcs".to_string();
+        make_deterministic(&mut operation);
+        assert_eq!(operation, "rcsD");
+
+        let mut operation = "-rcD".to_string();
+        make_deterministic(&mut operation);
+        assert_eq!(operation, "-rcD");
+
+        let mut operation = "rcU".to_string();
+        make_deterministic(&mut operation);
+        assert_eq!(operation, "rcU");
+    }
+}
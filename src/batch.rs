@@ -0,0 +1,341 @@
+//! `wasixcc batch <manifest.json>` runs many independent compile and link jobs
+//! in one process, so settings, the config file and the toolchain are only
+//! looked up once. The manifest lists the jobs:
+//!
+//! ```json
+//! {"jobs": [
+//!     {"name": "hello", "args": ["-O2", "hello.c", "-o", "hello.wasm"]},
+//!     {"name": "shapes", "tool": "c++", "args": ["-shared", "shapes.cpp", "-o", "libshapes.so"]},
+//!     {"tool": "ld", "args": ["-sMODULE_KIND=shared-library", "a.o", "-o", "liba.so"]}
+//! ]}
+//! ```
+//!
+//! `tool` is `cc` (the default), `c++` or `ld`, and `name` defaults to the
+//! job's index. `-s` settings in a job's args apply on top of the batch's own.
+//! With `-sJOBS=N`, up to N jobs run at a time. Jobs all run in the batch's
+//! working directory, so relative paths resolve against it, and they share its
+//! stdout and stderr: the output of e.g. `--print-effective-command` from jobs
+//! running at the same time can interleave, and `-o -` is rejected. Each job's outcome goes to
+//! `<manifest>.results.json`, or the file given with `--results`:
+//!
+//! ```json
+//! {"jobs":[{"name":"hello","status":"ok","seconds":0.412},
+//!  {"name":"shapes","status":"failed","seconds":0.027,"error":"..."}]}
+//! ```
+
+use serde_json::Value;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::*;
+
+const USAGE: &str = "Usage: wasixcc batch <manifest.json> [--results <results.json>]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Cc,
+    Cxx,
+    Ld,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Job {
+    name: String,
+    tool: Tool,
+    args: Vec<String>,
+}
+
+#[derive(Debug)]
+struct JobResult {
+    name: String,
+    duration: Duration,
+    error: Option<String>,
+}
+
+/// What every job starts from.
+struct BatchSettings {
+    user_settings: UserSettings,
+    settings_args: Vec<String>,
+    config: HashMap<String, String>,
+}
+
+impl BatchSettings {
+    fn for_job(&self, settings_args: &[String]) -> Result<UserSettings> {
+        if settings_args.is_empty() {
+            return Ok(self.user_settings.clone());
+        }
+        // The first value found wins, so the job's own settings go first
+        let args = [settings_args, &self.settings_args].concat();
+        gather_user_settings_with_config(&args, &self.config)
+    }
+}
+
+pub(crate) fn run(args: Vec<String>, settings_args: Vec<String>) -> Result<()> {
+    let mut manifest = None;
+    let mut results_path = None;
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--results" {
+            results_path = Some(PathBuf::from(iter.next().context(USAGE)?));
+        } else if manifest.is_none() && !arg.starts_with('-') {
+            manifest = Some(PathBuf::from(arg));
+        } else {
+            bail!("{USAGE}");
+        }
+    }
+    let manifest = manifest.context(USAGE)?;
+    let results_path = results_path.unwrap_or_else(|| manifest.with_extension("results.json"));
+
+    let contents = std::fs::read_to_string(&manifest)
+        .with_context(|| format!("Failed to read batch manifest {manifest:?}"))?;
+    let jobs = parse_manifest(&contents)
+        .with_context(|| format!("Invalid batch manifest {manifest:?}"))?;
+
+    let config = config::load_config_file()?;
+    let user_settings = gather_user_settings_with_config(&settings_args, &config)?;
+    let parallel_jobs = user_settings.jobs;
+    let settings = BatchSettings {
+        user_settings,
+        settings_args,
+        config,
+    };
+
+    let results = run_jobs(&jobs, &settings, parallel_jobs);
+
+    std::fs::write(&results_path, results_json(&results))
+        .with_context(|| format!("Failed to write batch results to {results_path:?}"))?;
+
+    let failed = results
+        .iter()
+        .filter(|result| result.error.is_some())
+        .count();
+    if failed > 0 {
+        bail!(
+            "{failed} of {} job(s) failed; see {results_path:?}",
+            results.len()
+        );
+    }
+    Ok(())
+}
+
+/// Runs the jobs on up to `parallel_jobs` threads, returning their results in
+/// manifest order. A failed job doesn't stop the others.
+fn run_jobs(jobs: &[Job], settings: &BatchSettings, parallel_jobs: usize) -> Vec<JobResult> {
+    let next_job = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::with_capacity(jobs.len()));
+
+    std::thread::scope(|scope| {
+        for _ in 0..parallel_jobs.min(jobs.len()) {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else {
+                    break;
+                };
+                let result = run_job(job, settings);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn run_job(job: &Job, settings: &BatchSettings) -> JobResult {
+    let _span = tracing::info_span!("job", name = job.name).entered();
+    let start = Instant::now();
+
+    let (settings_args, args) = separate_user_settings_args(job.args.clone());
+    let result = settings
+        .for_job(&settings_args)
+        .and_then(|user_settings| match job.tool {
+            Tool::Cc => compiler::run(args, user_settings, false),
+            Tool::Cxx => compiler::run(args, user_settings, true),
+            Tool::Ld => compiler::link_only(args, user_settings),
+        });
+
+    JobResult {
+        name: job.name.clone(),
+        duration: start.elapsed(),
+        error: result.err().map(|error| format!("{error:#}")),
+    }
+}
+
+fn parse_manifest(contents: &str) -> Result<Vec<Job>> {
+    let manifest = serde_json::from_str::<Value>(contents)?;
+    let Some(jobs) = manifest.get("jobs").and_then(Value::as_array) else {
+        bail!("Expected an object with a \"jobs\" array");
+    };
+
+    let mut result = Vec::with_capacity(jobs.len());
+    for (index, job) in jobs.iter().enumerate() {
+        result.push(parse_job(index, job).with_context(|| format!("Invalid job {index}"))?);
+    }
+    Ok(result)
+}
+
+fn parse_job(index: usize, job: &Value) -> Result<Job> {
+    let Some(fields) = job.as_object() else {
+        bail!("Expected an object");
+    };
+    if let Some(key) = fields
+        .keys()
+        .find(|key| !["name", "tool", "args"].contains(&key.as_str()))
+    {
+        bail!("Unknown key {key:?}; expected name, tool or args");
+    }
+
+    let name = match fields.get("name") {
+        Some(Value::String(name)) => name.clone(),
+        Some(_) => bail!("name must be a string"),
+        None => index.to_string(),
+    };
+
+    let tool = match fields.get("tool") {
+        Some(Value::String(tool)) => match tool.as_str() {
+            "cc" => Tool::Cc,
+            "c++" => Tool::Cxx,
+            "ld" => Tool::Ld,
+            _ => bail!("Unknown tool {tool}; expected one of cc, c++ or ld"),
+        },
+        Some(_) => bail!("tool must be a string"),
+        None => Tool::Cc,
+    };
+
+    let Some(args) = fields.get("args").and_then(Value::as_array) else {
+        bail!("Expected an args array");
+    };
+    let args = args
+        .iter()
+        .map(|arg| match arg {
+            Value::String(arg) => Ok(arg.clone()),
+            _ => bail!("args must all be strings"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // All jobs write to the same stdout
+    if args
+        .windows(2)
+        .any(|pair| pair[0] == "-o" && pair[1] == "-")
+    {
+        bail!("Jobs can't write their output to stdout (-o -)");
+    }
+
+    Ok(Job { name, tool, args })
+}
+
+fn results_json(results: &[JobResult]) -> String {
+    let jobs = results
+        .iter()
+        .map(|result| {
+            let mut fields = vec![
+                format!("\"name\":{}", diagnostics::json_string(&result.name)),
+                format!(
+                    "\"status\":\"{}\"",
+                    if result.error.is_some() {
+                        "failed"
+                    } else {
+                        "ok"
+                    }
+                ),
+                format!("\"seconds\":{:.3}", result.duration.as_secs_f64()),
+            ];
+            if let Some(error) = &result.error {
+                fields.push(format!("\"error\":{}", diagnostics::json_string(error)));
+            }
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect::<Vec<_>>();
+    format!("{{\"jobs\":[{}]}}\n", jobs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let jobs = parse_manifest(
+            r#"{
+                "jobs": [
+                    {"name": "hello", "args": ["-O2", "hello.c", "-o", "hello.wasm"]},
+                    {"tool": "c++", "args": ["-DNAME=\"a\tb\"", "café.cpp"]},
+                    {"tool": "ld", "args": []}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            jobs,
+            vec![
+                Job {
+                    name: "hello".to_owned(),
+                    tool: Tool::Cc,
+                    args: vec!["-O2", "hello.c", "-o", "hello.wasm"]
+                        .into_iter()
+                        .map(String::from)
+                        .collect(),
+                },
+                Job {
+                    name: "1".to_owned(),
+                    tool: Tool::Cxx,
+                    args: vec!["-DNAME=\"a\tb\"".to_owned(), "café.cpp".to_owned()],
+                },
+                Job {
+                    name: "2".to_owned(),
+                    tool: Tool::Ld,
+                    args: vec![],
+                },
+            ]
+        );
+
+        assert!(parse_manifest("[]").is_err());
+        assert!(parse_manifest(r#"{"jobs": [{"args": ["a.c"]}"#).is_err());
+        assert!(parse_manifest(r#"{"jobs": [{"tool": "ar", "args": []}]}"#).is_err());
+        assert!(parse_manifest(r#"{"jobs": [{"args": ["a.c"], "dir": "x"}]}"#).is_err());
+        assert!(parse_manifest(r#"{"jobs": [{"args": [1]}]}"#).is_err());
+        assert!(parse_manifest(r#"{"jobs": []} trailing"#).is_err());
+        let error = parse_manifest(r#"{"jobs": [{"args": ["a.c", "-o", "-"]}]}"#).unwrap_err();
+        assert!(format!("{error:#}").contains("(-o -)"), "{error:#}");
+    }
+
+    #[test]
+    fn test_run_jobs() {
+        let settings = BatchSettings {
+            user_settings: crate::compiler::tests::test_user_settings(),
+            settings_args: vec![],
+            config: HashMap::new(),
+        };
+        let job = |name: &str, args: &[&str]| Job {
+            name: name.to_owned(),
+            tool: Tool::Cc,
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        };
+        let jobs = vec![
+            job("a", &["--print-effective-command=compile", "a.c"]),
+            job("b", &["--print-effective-command=bogus", "b.c"]),
+            job("c", &["--print-effective-command=compile", "c.c"]),
+        ];
+
+        let results = run_jobs(&jobs, &settings, 2);
+        let names = results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["a", "b", "c"]);
+        assert!(results[0].error.is_none() && results[2].error.is_none());
+        assert!(results[1]
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("Unknown stage bogus"));
+
+        let json = results_json(&results);
+        assert!(json.starts_with(r#"{"jobs":[{"name":"a","status":"ok","seconds":"#));
+        assert!(json.contains(r#"{"name":"b","status":"failed","seconds":"#));
+        assert!(json.contains(r#""error":"Unknown stage bogus"#));
+    }
+}
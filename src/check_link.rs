@@ -0,0 +1,222 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::*;
+
+const USAGE: &str = "Usage: wasixcc check-link <main.wasm> <side.wasm>...";
+
+/// Imports the dynamic linker provides itself rather than resolving them
+/// against another module's exports.
+const LINKER_PROVIDED_SYMBOLS: &[&str] = &[
+    "memory",
+    "__indirect_function_table",
+    "__stack_pointer",
+    "__memory_base",
+    "__table_base",
+    "__tls_base",
+];
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ModuleSymbols {
+    defined: BTreeSet<String>,
+    undefined: BTreeSet<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum LinkProblem {
+    // A side module imports a symbol no other module exports
+    Unresolved {
+        module: PathBuf,
+        symbol: String,
+    },
+    // More than one side module exports the same symbol; which one wins
+    // depends on load order
+    DuplicateDefinition {
+        symbol: String,
+        modules: Vec<PathBuf>,
+    },
+}
+
+pub(crate) fn run(args: Vec<String>, user_settings: UserSettings) -> Result<()> {
+    if args.len() < 2 || args.iter().any(|arg| arg.starts_with('-')) {
+        bail!("{USAGE}");
+    }
+
+    let mut modules = Vec::with_capacity(args.len());
+    for path in args {
+        let path = PathBuf::from(path);
+        let symbols = read_module_symbols(&user_settings, &path)?;
+        modules.push((path, symbols));
+    }
+
+    let problems = find_link_problems(&modules);
+    let mut unresolved = 0;
+    for problem in &problems {
+        match problem {
+            LinkProblem::Unresolved { module, symbol } => {
+                unresolved += 1;
+                eprintln!("Error: {module:?} imports {symbol}, which no other module exports");
+            }
+            LinkProblem::DuplicateDefinition { symbol, modules } => print_warning(&format!(
+                "{symbol} is exported by more than one side module: {modules:?}"
+            )),
+        }
+    }
+
+    if unresolved > 0 {
+        bail!("Found {unresolved} unresolved symbol(s) across the given modules");
+    }
+
+    println!("All imports of the side modules are resolved");
+    Ok(())
+}
+
+fn read_module_symbols(user_settings: &UserSettings, path: &Path) -> Result<ModuleSymbols> {
+    let symbols = list_symbols_with_settings(path, user_settings)?;
+    Ok(module_symbols(&symbols))
+}
+
+/// Sorts out the global symbols a module exports and the ones it needs from
+/// other modules.
+fn module_symbols(symbols: &[Symbol]) -> ModuleSymbols {
+    let mut result = ModuleSymbols::default();
+
+    for symbol in symbols {
+        if symbol.kind == 'U' {
+            result.undefined.insert(symbol.name.clone());
+        } else if symbol.defined && symbol.kind.is_ascii_uppercase() && symbol.kind != 'N' {
+            // Weak undefined symbols are allowed to stay unresolved, and
+            // lowercase kinds are local
+            result.defined.insert(symbol.name.clone());
+        }
+    }
+
+    result
+}
+
+/// The first module is the DynamicMain; its own imports come from the host,
+/// so only the side modules' imports are checked.
+fn find_link_problems(modules: &[(PathBuf, ModuleSymbols)]) -> Vec<LinkProblem> {
+    let mut problems = vec![];
+
+    for (index, (module, symbols)) in modules.iter().enumerate().skip(1) {
+        for symbol in &symbols.undefined {
+            let resolved = LINKER_PROVIDED_SYMBOLS.contains(&symbol.as_str())
+                || modules
+                    .iter()
+                    .enumerate()
+                    .any(|(other, (_, s))| other != index && s.defined.contains(symbol));
+            if !resolved {
+                problems.push(LinkProblem::Unresolved {
+                    module: module.clone(),
+                    symbol: symbol.clone(),
+                });
+            }
+        }
+    }
+
+    let mut definitions: BTreeMap<&str, Vec<PathBuf>> = BTreeMap::new();
+    for (module, symbols) in modules.iter().skip(1) {
+        for symbol in &symbols.defined {
+            definitions.entry(symbol).or_default().push(module.clone());
+        }
+    }
+    for (symbol, modules) in definitions {
+        if modules.len() > 1 {
+            problems.push(LinkProblem::DuplicateDefinition {
+                symbol: symbol.to_owned(),
+                modules,
+            });
+        }
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_nm_output(output: &str) -> ModuleSymbols {
+        module_symbols(&parse_nm_symbols(output))
+    }
+
+    #[test]
+    fn test_module_symbols() {
+        let symbols = parse_nm_output(
+            "main T 0 0\n\
+            printf U\n\
+            optional_hook w\n\
+            local_helper t 10 4\n\
+            counter D 20 4\n",
+        );
+        assert_eq!(
+            symbols.defined,
+            BTreeSet::from(["counter".to_string(), "main".to_string()])
+        );
+        assert_eq!(symbols.undefined, BTreeSet::from(["printf".to_string()]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_link_reports_mismatched_modules() {
+        // Prints the symbol listing stored next to the module
+        let tmp = crate::compiler::tests::fake_toolchain(&[(
+            "llvm-nm",
+            "for last; do :; done\ncat \"$last.nm\"\n",
+        )]);
+
+        let main = tmp.path().join("main.wasm");
+        let side = tmp.path().join("side.wasm");
+        std::fs::write(
+            tmp.path().join("main.wasm.nm"),
+            "main T 0 0\nmalloc T 0 0\nfd_write U\n",
+        )
+        .unwrap();
+        std::fs::write(
+            tmp.path().join("side.wasm.nm"),
+            "side_fn T 0 0\nmalloc U\nmissing_fn U\n__memory_base U\n",
+        )
+        .unwrap();
+
+        let mut user_settings = crate::compiler::tests::test_user_settings();
+        user_settings.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+
+        let modules = [main.clone(), side.clone()]
+            .into_iter()
+            .map(|path| {
+                let symbols = read_module_symbols(&user_settings, &path).unwrap();
+                (path, symbols)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            find_link_problems(&modules),
+            vec![LinkProblem::Unresolved {
+                module: side.clone(),
+                symbol: "missing_fn".to_string(),
+            }]
+        );
+
+        let args = vec![
+            main.to_str().unwrap().to_owned(),
+            side.to_str().unwrap().to_owned(),
+        ];
+        assert!(run(args, user_settings).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_side_module_definitions() {
+        let side = |name: &str| (PathBuf::from(name), parse_nm_output("shared_fn T 0 0\n"));
+        let modules = vec![
+            (PathBuf::from("main.wasm"), ModuleSymbols::default()),
+            side("a.wasm"),
+            side("b.wasm"),
+        ];
+        assert_eq!(
+            find_link_problems(&modules),
+            vec![LinkProblem::DuplicateDefinition {
+                symbol: "shared_fn".to_string(),
+                modules: vec![PathBuf::from("a.wasm"), PathBuf::from("b.wasm")],
+            }]
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use super::*;
+use crate::version_script::{read_version_script, VersionScript};
 
 static CLANG_FLAGS_WITH_ARGS: LazyLock<HashSet<&str>> = LazyLock::new(|| {
     [
@@ -11,6 +12,7 @@ static CLANG_FLAGS_WITH_ARGS: LazyLock<HashSet<&str>> = LazyLock::new(|| {
         "-o",
         "-x",
         "-Xpreprocessor",
+        "-Xassembler",
         "-include",
         "-imacros",
         "-idirafter",
@@ -41,8 +43,59 @@ static CLANG_FLAGS_WITH_ARGS: LazyLock<HashSet<&str>> = LazyLock::new(|| {
     .into()
 });
 
-static WASM_LD_FLAGS_WITH_ARGS: LazyLock<HashSet<&str>> =
-    LazyLock::new(|| ["-o", "-mllvm", "-L", "-l", "-m", "-O", "-y", "-z"].into());
+static CXX_SOURCE_EXTENSIONS: LazyLock<HashSet<&str>> = LazyLock::new(|| {
+    [
+        "cc", "cpp", "cxx", "C", "c++", "hh", "hpp", "hxx", "H", "h++",
+    ]
+    .into()
+});
+
+/// Compiling only headers gives a precompiled header rather than an object.
+static HEADER_EXTENSIONS: LazyLock<HashSet<&str>> =
+    LazyLock::new(|| ["h", "hh", "hpp", "hxx", "H", "h++"].into());
+
+/// LLVM IR, as text and bitcode, from clang -emit-llvm or another frontend.
+static IR_EXTENSIONS: LazyLock<HashSet<&str>> = LazyLock::new(|| ["ll", "bc"].into());
+
+/// Objective-C and Objective-C++ sources, which clang could compile but nothing
+/// in a WASIX sysroot could run, lacking an Objective-C runtime.
+static OBJC_EXTENSIONS: LazyLock<HashSet<&str>> = LazyLock::new(|| ["m", "mm", "M"].into());
+
+static OBJC_LANGUAGES: LazyLock<HashSet<&str>> = LazyLock::new(|| {
+    [
+        "objective-c",
+        "objective-c++",
+        "objective-c-header",
+        "objective-c++-header",
+    ]
+    .into()
+});
+
+/// CPUs clang knows about for wasm32; anything else passed via `-mcpu` is from a
+/// native build script.
+static WASM_CPUS: LazyLock<HashSet<&str>> =
+    LazyLock::new(|| ["generic", "mvp", "bleeding-edge", "lime1"].into());
+
+/// --save-temps leaves intermediate files in `<output>.wasixcc-temps`, so
+/// builds of different outputs in the same directory don't share one.
+const SAVE_TEMPS_DIR_SUFFIX: &str = ".wasixcc-temps";
+
+static WASM_LD_FLAGS_WITH_ARGS: LazyLock<HashSet<&str>> = LazyLock::new(|| {
+    [
+        "-o",
+        "-mllvm",
+        "-L",
+        "-l",
+        "-m",
+        "-O",
+        "-y",
+        "-z",
+        "--wrap",
+        "-wrap",
+        "--version-script",
+    ]
+    .into()
+});
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum ModuleKind {
@@ -50,6 +103,13 @@ pub(crate) enum ModuleKind {
     DynamicMain,
     SharedLibrary,
     ObjectFile,
+    // A static archive of the objects compiled from the inputs
+    Archive,
+    // One object merged from the inputs with wasm-ld -r, to be linked later
+    Relocatable,
+    // StaticMain or Relocatable, depending on whether the compiled inputs
+    // define an entry point; see resolve_auto_module_kind
+    Auto,
 }
 
 impl ModuleKind {
@@ -67,6 +127,25 @@ impl ModuleKind {
     pub fn is_executable(&self) -> bool {
         matches!(self, ModuleKind::StaticMain | ModuleKind::DynamicMain)
     }
+
+    /// Whether each input is compiled to its own object, to be linked or
+    /// archived afterwards.
+    pub fn is_built_from_objects(&self) -> bool {
+        self.is_binary()
+            || matches!(
+                self,
+                ModuleKind::Archive | ModuleKind::Relocatable | ModuleKind::Auto
+            )
+    }
+}
+
+/// What the extension of an output file says about the module being built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputKind {
+    Module(ModuleKind),
+    // An executable or shared library; the module kind comes from other flags
+    Binary,
+    Archive,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -80,6 +159,22 @@ pub(crate) enum OptLevel {
     Oz,
 }
 
+impl OptLevel {
+    /// Parses the part after `-O`, e.g. `2` or `z`.
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "0" => Some(OptLevel::O0),
+            "1" => Some(OptLevel::O1),
+            "2" => Some(OptLevel::O2),
+            "3" => Some(OptLevel::O3),
+            "4" => Some(OptLevel::O4),
+            "s" => Some(OptLevel::Os),
+            "z" => Some(OptLevel::Oz),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum DebugLevel {
     None,
@@ -89,12 +184,40 @@ pub(crate) enum DebugLevel {
     G3,
 }
 
+impl DebugLevel {
+    /// Whether clang emits DWARF sections at this level, which wasm-opt must be
+    /// told to preserve.
+    pub fn emits_dwarf(&self) -> bool {
+        matches!(self, DebugLevel::G1 | DebugLevel::G2 | DebugLevel::G3)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BuildStage {
+    Compile,
+    Link,
+    Opt,
+    // Encoding a COMPONENT=1 module with wasm-tools
+    Component,
+}
+
 /// Settings derived strictly from compiler flags.
 #[derive(Debug)]
 pub(crate) struct BuildSettings {
     opt_level: OptLevel,
     debug_level: DebugLevel,
     use_wasm_opt: bool,
+    // Set by --print-effective-command; nothing is executed in this mode
+    print_stage: Option<BuildStage>,
+    // Set by -###, which prints every command the way clang does instead
+    print_commands: bool,
+}
+
+impl BuildSettings {
+    /// Whether commands are only printed, not run.
+    fn dry_run(&self) -> bool {
+        self.print_stage.is_some() || self.print_commands
+    }
 }
 
 #[derive(Debug)]
@@ -102,10 +225,33 @@ pub(crate) struct PreparedArgs {
     compiler_args: Vec<String>,
     linker_args: Vec<String>,
     compiler_inputs: Vec<PathBuf>,
+    // The `-x` language in effect for each of compiler_inputs, if any
+    compiler_input_languages: Vec<Option<String>>,
+    // How many of compiler_args came before each of compiler_inputs
+    compiler_input_arg_counts: Vec<usize>,
     linker_inputs: Vec<PathBuf>,
     output: Option<PathBuf>,
 }
 
+impl PreparedArgs {
+    fn has_arg(&self, name: &str) -> bool {
+        self.compiler_args
+            .iter()
+            .chain(linker_flags(&self.linker_args))
+            .any(|arg| arg == name)
+    }
+
+    /// libc and friends, and libc++ for C++, as with clang's -nodefaultlibs
+    fn links_default_libs(&self) -> bool {
+        !self.has_arg("-nostdlib") && !self.has_arg("-nodefaultlibs")
+    }
+
+    /// crt1.o or scrt1.o, as with clang's -nostartfiles
+    fn links_startfiles(&self) -> bool {
+        !self.has_arg("-nostdlib") && !self.has_arg("-nostartfiles")
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct State {
     user_settings: UserSettings,
@@ -113,41 +259,241 @@ pub(crate) struct State {
     args: PreparedArgs,
     cxx: bool,
     temp_dir: PathBuf,
+    // Whether the inputs were compiled with -matomics, recorded so the link
+    // step can make sure it agrees on using shared memory.
+    compiled_with_atomics: Option<bool>,
+    // Shell lines reproducing every step we ran, for REPRO_SCRIPT and
+    // --print-effective-command.
+    script_lines: Vec<(BuildStage, String)>,
+    // How long each command we ran took, for TIMINGS
+    stage_times: Vec<(BuildStage, std::time::Duration)>,
+    // Read from VERSION_SCRIPT when linking
+    version_script: Option<VersionScript>,
+    // For -o -, which builds into the temp dir and then copies the output to
+    // stdout; the tools' own stdout goes to stderr meanwhile
+    output_to_stdout: bool,
 }
 
 pub(crate) fn run(args: Vec<String>, mut user_settings: UserSettings, run_cxx: bool) -> Result<()> {
     let original_args = args.clone();
 
-    let (args, build_settings) = prepare_compiler_args(args, &mut user_settings)?;
+    let (mut args, build_settings) = prepare_compiler_args(args, &mut user_settings)?;
 
     tracing::info!("Compiler settings: {user_settings:?}");
 
+    if user_settings.print_config {
+        return print_config(args, build_settings, user_settings, run_cxx);
+    }
+
     if args.compiler_inputs.is_empty() && args.linker_inputs.is_empty() {
+        if build_settings.print_stage.is_some() {
+            bail!("--print-effective-command requires input files");
+        }
+
         // If there are no inputs, just pass everything through to clang.
         // This lets us support invocations such as `wasixcc -dumpmachine`.
-        let mut command = Command::new(user_settings.llvm_location.get_tool_path(if run_cxx {
-            "clang++"
-        } else {
-            "clang"
-        }));
-        command.args(original_args);
-        return run_command(command);
+        return run_command(
+            passthrough_command(original_args, &user_settings, run_cxx),
+            &user_settings,
+        );
     }
 
-    let temp_dir = tempfile::TempDir::new().context("Failed to create temporary directory")?;
+    if user_settings.module_kind().is_built_from_objects() {
+        remove_duplicate_compiler_inputs(&mut args);
+    }
+
+    let run_cxx = run_cxx || has_cxx_inputs(&args);
+
+    check_output_not_input(&args)?;
+
+    if !build_settings.dry_run()
+        && user_settings.analyze.is_none()
+        && user_settings.module_kind().is_binary()
+    {
+        check_sysroot(&user_settings, &args)?;
+    }
+
+    if let Some(profile) = &user_settings.pgo_use {
+        if !profile.is_file() {
+            bail!(
+                "The profile {profile:?} given in PGO_USE doesn't exist; merge the raw \
+                profiles from a PGO_GEN=1 build with llvm-profdata first"
+            );
+        }
+    }
+
+    let (temp_dir, _temp_dir_guard) = create_temp_dir(&user_settings, !build_settings.dry_run())?;
+    let output_to_stdout = redirect_stdout_output(&mut args, &user_settings, &temp_dir)?;
+
+    let mut state = State {
+        user_settings,
+        build_settings,
+        args,
+        cxx: run_cxx,
+        temp_dir,
+        compiled_with_atomics: None,
+        script_lines: Vec::new(),
+        stage_times: Vec::new(),
+        version_script: None,
+        output_to_stdout,
+    };
+
+    let result = compile_and_link(&mut state);
+    // Written even if the build failed, since that's when it's most useful
+    write_repro_script(&state)?;
+    write_saved_commands(&state)?;
+    result?;
+
+    if state.output_to_stdout && !state.build_settings.dry_run() {
+        copy_output_to_stdout(&state)?;
+    }
+
+    if state.user_settings.timings && !state.build_settings.dry_run() {
+        eprintln!("Timings: {}", timings_summary(&state.stage_times));
+    }
+
+    if let Some(stage) = state.build_settings.print_stage {
+        for line in effective_command_lines(&state, stage) {
+            println!("{line}");
+        }
+    }
+
+    tracing::info!("Done");
+    Ok(())
+}
+
+/// Our sysroot and target are added unless the user gave their own, so queries
+/// such as `-print-file-name=libc.a` answer for the WASIX sysroot.
+fn passthrough_command(
+    original_args: Vec<String>,
+    user_settings: &UserSettings,
+    run_cxx: bool,
+) -> Command {
+    let mut command =
+        Command::new(user_settings.get_tool_path(if run_cxx { "clang++" } else { "clang" }));
+
+    let has_arg = |prefix: &str| original_args.iter().any(|arg| arg.starts_with(prefix));
+    if let Some(sysroot) = &user_settings.sysroot_location {
+        if !has_arg("--sysroot") {
+            command.arg("--sysroot").arg(sysroot);
+        }
+    }
+    if !has_arg("--target") && !has_arg("-target") {
+        command.arg(format!("--target={}", user_settings.target));
+    }
+
+    command.args(original_args);
+    command
+}
+
+fn print_config(
+    mut args: PreparedArgs,
+    mut build_settings: BuildSettings,
+    user_settings: UserSettings,
+    run_cxx: bool,
+) -> Result<()> {
+    // Go through a dry run of the build to get the exact commands, using a
+    // placeholder input if none were given
+    if args.compiler_inputs.is_empty() && args.linker_inputs.is_empty() {
+        args.compiler_inputs
+            .push(PathBuf::from(if run_cxx { "input.cpp" } else { "input.c" }));
+        args.compiler_input_languages.push(None);
+        args.compiler_input_arg_counts
+            .push(args.compiler_args.len());
+    }
+    build_settings.print_stage = Some(BuildStage::Compile);
 
+    let run_cxx = run_cxx || has_cxx_inputs(&args);
+    let temp_dir = tempfile::TempDir::new().context("Failed to create temporary directory")?;
     let mut state = State {
         user_settings,
         build_settings,
         args,
         cxx: run_cxx,
         temp_dir: temp_dir.path().to_owned(),
+        compiled_with_atomics: None,
+        script_lines: Vec::new(),
+        stage_times: Vec::new(),
+        version_script: None,
+        output_to_stdout: false,
+    };
+
+    if state.user_settings.sysroot_location.is_some() {
+        compile_and_link(&mut state)?;
+    }
+
+    print!("{}", config_report(&state));
+    Ok(())
+}
+
+fn config_report(state: &State) -> String {
+    let user_settings = &state.user_settings;
+    let mut report = String::new();
+
+    let mut line = |key: &str, value: &dyn std::fmt::Display| {
+        report.push_str(&format!("{key}: {value}\n"));
     };
 
-    compile_inputs(&mut state)?;
+    for tool in [
+        "clang",
+        "clang++",
+        "wasm-ld",
+        "llvm-ar",
+        "llvm-nm",
+        "llvm-ranlib",
+    ] {
+        let path = resolve_program(user_settings.get_tool_path(tool).as_os_str());
+        line(tool, &path.display());
+    }
+    line(
+        "wasm-opt",
+        &resolve_program(OsStr::new("wasm-opt")).display(),
+    );
+    match &user_settings.sysroot_location {
+        Some(sysroot) => line("sysroot", &sysroot.display()),
+        None => line("sysroot", &"(not set)"),
+    }
+    line("module kind", &format!("{:?}", user_settings.module_kind()));
+    line("C++", &state.cxx);
+
+    for (stage, name) in [
+        (BuildStage::Compile, "compile command"),
+        (BuildStage::Link, "link command"),
+        (BuildStage::Opt, "wasm-opt command"),
+        (BuildStage::Component, "component command"),
+    ] {
+        for command in effective_command_lines(state, stage) {
+            line(name, &command);
+        }
+    }
+
+    report
+}
+
+fn compile_and_link(state: &mut State) -> Result<()> {
+    compile_inputs(state)?;
+
+    if state.user_settings.analyze.is_some() {
+        // Nothing was compiled, so there's nothing to link
+        return Ok(());
+    }
+
+    if state.user_settings.module_kind() == ModuleKind::Auto {
+        let print_only = state.build_settings.dry_run();
+        resolve_auto_module_kind(&mut state.user_settings, &state.args, print_only)?;
+        // Skipped in run() while the module kind wasn't known yet
+        if state.user_settings.module_kind().is_binary() {
+            check_sysroot(&state.user_settings, &state.args)?;
+        }
+    }
 
     if state.user_settings.module_kind().is_binary() {
-        link_inputs(&state)?;
+        compile_thread_stack_size_stub(state)?;
+        link_inputs(state)?;
+    } else if state.user_settings.module_kind() == ModuleKind::Archive {
+        archive_objects(state)?;
+    } else if state.user_settings.module_kind() == ModuleKind::Relocatable {
+        link_relocatable(state)?;
     }
 
     // Run wasm-opt if:
@@ -162,19 +508,39 @@ pub(crate) fn run(args: Vec<String>, mut user_settings: UserSettings, run_cxx: b
             (_, Some(true)) | (true, None)
         )
     {
-        run_wasm_opt(&state)?;
+        run_wasm_opt(state)?;
+    }
+
+    if state.user_settings.module_kind().is_binary() && state.user_settings.emit_wat {
+        emit_wat(state);
+    }
+
+    if state.user_settings.module_kind().is_binary() && state.user_settings.component {
+        encode_component(state)?;
     }
 
-    tracing::info!("Done");
     Ok(())
 }
 
-pub(crate) fn link_only(args: Vec<String>, mut user_settings: UserSettings) -> Result<()> {
+pub(crate) fn link_only(mut args: Vec<String>, mut user_settings: UserSettings) -> Result<()> {
+    // wasm-ld has no -###, so it's never passed on
+    let print_commands = args.iter().any(|arg| arg == "-###");
+    args.retain(|arg| arg != "-###");
     let original_args = args.clone();
 
-    let args = prepare_linker_args(args, &mut user_settings)?;
+    let mut args = prepare_linker_args(args, &mut user_settings)?;
+
+    if user_settings.module_kind() == ModuleKind::Auto {
+        if args.linker_inputs.is_empty() {
+            // Passed through to wasm-ld below
+            user_settings.module_kind = None;
+        } else {
+            resolve_auto_module_kind(&mut user_settings, &args, false)?;
+        }
+    }
 
-    if !user_settings.module_kind().is_binary() {
+    let relocatable = user_settings.module_kind() == ModuleKind::Relocatable;
+    if !user_settings.module_kind().is_binary() && !relocatable {
         bail!(
             "Only binaries can be linked, current module kind is: {:?}",
             user_settings.module_kind()
@@ -185,659 +551,5266 @@ pub(crate) fn link_only(args: Vec<String>, mut user_settings: UserSettings) -> R
 
     if args.linker_inputs.is_empty() {
         // If there are no inputs, just pass everything through to wasm-ld.
-        let mut command = Command::new(user_settings.llvm_location.get_tool_path("wasm-ld"));
+        let mut command = Command::new(user_settings.get_tool_path("wasm-ld"));
         command.args(original_args);
-        return run_command(command);
+        return run_command(command, &user_settings);
+    }
+
+    check_output_not_input(&args)?;
+    if !relocatable && !print_commands {
+        check_sysroot(&user_settings, &args)?;
     }
 
     let build_settings = BuildSettings {
         opt_level: OptLevel::O0,
         debug_level: DebugLevel::G0,
         use_wasm_opt: user_settings.run_wasm_opt.unwrap_or(true),
+        print_stage: None,
+        print_commands,
     };
 
-    let state = State {
+    // Only used if we need to compile stubs
+    let (temp_dir, _temp_dir_guard) = create_temp_dir(&user_settings, true)?;
+    let output_to_stdout = redirect_stdout_output(&mut args, &user_settings, &temp_dir)?;
+
+    let mut state = State {
         user_settings,
         build_settings,
         args,
         // TODO: is there a way to figure this out automatically?
         cxx: false,
-        // Not used for linking
-        temp_dir: PathBuf::from("."),
+        temp_dir,
+        compiled_with_atomics: None,
+        script_lines: Vec::new(),
+        stage_times: Vec::new(),
+        version_script: None,
+        output_to_stdout,
     };
 
-    link_inputs(&state)?;
+    let result = if relocatable {
+        link_relocatable(&mut state)
+    } else {
+        link_and_optimize(&mut state)
+    };
+    write_repro_script(&state)?;
+    write_saved_commands(&state)?;
+    result?;
 
-    if state.build_settings.use_wasm_opt {
-        run_wasm_opt(&state)?;
+    if state.output_to_stdout && !state.build_settings.dry_run() {
+        copy_output_to_stdout(&state)?;
+    }
+
+    if state.user_settings.timings && !state.build_settings.dry_run() {
+        eprintln!("Timings: {}", timings_summary(&state.stage_times));
     }
 
     tracing::info!("Done");
     Ok(())
 }
 
-fn output_path(state: &State) -> &Path {
-    if let Some(output) = &state.args.output {
-        output.as_path()
-    } else {
-        match state.user_settings.module_kind() {
-            ModuleKind::StaticMain | ModuleKind::DynamicMain | ModuleKind::SharedLibrary => {
-                Path::new("a.out")
-            }
-            ModuleKind::ObjectFile => Path::new("a.o"),
+/// E.g. `compile: 3.2s, link: 0.8s, wasm-opt: 1.1s`, leaving out stages that
+/// didn't run.
+fn timings_summary(stage_times: &[(BuildStage, std::time::Duration)]) -> String {
+    [
+        (BuildStage::Compile, "compile"),
+        (BuildStage::Link, "link"),
+        (BuildStage::Opt, "wasm-opt"),
+        (BuildStage::Component, "component"),
+    ]
+    .into_iter()
+    .filter(|(stage, _)| stage_times.iter().any(|(s, _)| s == stage))
+    .map(|(stage, name)| {
+        let total: std::time::Duration = stage_times
+            .iter()
+            .filter(|(s, _)| *s == stage)
+            .map(|(_, time)| *time)
+            .sum();
+        format!("{name}: {:.1}s", total.as_secs_f64())
+    })
+    .collect::<Vec<_>>()
+    .join(", ")
+}
+
+/// With SAVE_TEMPS (or --save-temps), intermediate files go to that directory
+/// and are left there; otherwise to a temp dir that's deleted along with the
+/// returned guard. That one is created under TEMP_DIR if set, or the system
+/// temp dir ($TMPDIR) if not, since the latter is sometimes a small tmpfs.
+fn create_temp_dir(
+    user_settings: &UserSettings,
+    save_temps: bool,
+) -> Result<(PathBuf, Option<tempfile::TempDir>)> {
+    match &user_settings.save_temps {
+        Some(dir) if save_temps => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create SAVE_TEMPS directory {dir:?}"))?;
+            let dir = std::path::absolute(dir)
+                .with_context(|| format!("Failed to get absolute path of {dir:?}"))?;
+            Ok((dir, None))
+        }
+        _ => {
+            let temp_dir = match &user_settings.temp_dir {
+                Some(base) => {
+                    std::fs::create_dir_all(base)
+                        .with_context(|| format!("Failed to create TEMP_DIR directory {base:?}"))?;
+                    tempfile::TempDir::new_in(base).with_context(|| {
+                        format!("Failed to create temporary directory in {base:?}")
+                    })?
+                }
+                None => tempfile::TempDir::new().context("Failed to create temporary directory")?,
+            };
+            Ok((temp_dir.path().to_owned(), Some(temp_dir)))
         }
     }
 }
 
-fn compile_inputs(state: &mut State) -> Result<()> {
-    let compiler_path = state
-        .user_settings
-        .llvm_location
-        .get_tool_path(if state.cxx { "clang++" } else { "clang" });
-
-    let mut command_args: Vec<&OsStr> = vec![
-        OsStr::new("--sysroot"),
-        state.user_settings.sysroot_location().as_os_str(),
-        OsStr::new("--target=wasm32-wasi"),
-        OsStr::new("-c"),
-        OsStr::new("-matomics"),
-        OsStr::new("-mbulk-memory"),
-        OsStr::new("-mmutable-globals"),
-        OsStr::new("-pthread"),
-        OsStr::new("-mthread-model"),
-        OsStr::new("posix"),
-        OsStr::new("-fno-trapping-math"),
-        OsStr::new("-D_WASI_EMULATED_MMAN"),
-        OsStr::new("-D_WASI_EMULATED_SIGNAL"),
-        OsStr::new("-D_WASI_EMULATED_PROCESS_CLOCKS"),
-    ];
-
-    if state.user_settings.wasm_exceptions {
-        command_args.push(OsStr::new("-fwasm-exceptions"));
+/// Points `-o -` at a file in the temp dir, returning whether it did. Files
+/// that would be named after the output would end up in there too, so those
+/// have to be named some other way.
+fn redirect_stdout_output(
+    args: &mut PreparedArgs,
+    user_settings: &UserSettings,
+    temp_dir: &Path,
+) -> Result<bool> {
+    if args.output.as_deref() != Some(Path::new("-")) {
+        return Ok(false);
     }
 
-    if state.user_settings.module_kind().requires_pic() || state.user_settings.pic {
-        command_args.push(OsStr::new("-fPIC"));
-        command_args.push(OsStr::new("-ftls-model=global-dynamic"));
-        command_args.push(OsStr::new("-fvisibility=default"));
-    } else {
-        command_args.push(OsStr::new("-ftls-model=local-exec"));
+    if user_settings.emit_wat {
+        bail!("EMIT_WAT can't be used with -o -, since the .wat file is named after the output");
     }
-
-    if state.cxx {
-        // C++ exceptions aren't supported in WASIX yet
-        command_args.push(OsStr::new("-fno-exceptions"));
+    let has_arg = |name: &str| args.compiler_args.iter().any(|arg| arg == name);
+    let names_dependency_file = args
+        .compiler_args
+        .iter()
+        .any(|arg| arg.starts_with("-Wp,-MD,") || arg.starts_with("-Wp,-MMD,"))
+        || has_arg("-MF");
+    let names_dependency_target = has_arg("-MT") || has_arg("-MQ");
+    if (has_arg("-MD") || has_arg("-MMD") || names_dependency_file)
+        && !(names_dependency_file && names_dependency_target)
+    {
+        bail!(
+            "-MD and -MMD need -MF and -MT when used with -o -, since the dependency file \
+            and its target are otherwise named after the output"
+        );
     }
 
-    if state.build_settings.debug_level != DebugLevel::None {
-        command_args.push(OsStr::new("-g"));
-    }
+    args.output = Some(temp_dir.join("stdout-output"));
+    Ok(true)
+}
 
-    for arg in &state.args.compiler_args {
-        command_args.push(OsStr::new(arg.as_str()));
-    }
+/// Only once every stage is done, so a failed build writes nothing to stdout.
+fn copy_output_to_stdout(state: &State) -> Result<()> {
+    use std::io::Write;
 
-    if state.user_settings.module_kind().is_binary() {
-        // If we're linking later, we should compile each input separately
+    let path = output_path(state);
+    let mut output =
+        std::fs::File::open(path).with_context(|| format!("Failed to open output {path:?}"))?;
+    let mut stdout = std::io::stdout().lock();
+    std::io::copy(&mut output, &mut stdout)
+        .and_then(|_| stdout.flush())
+        .context("Failed to write the output to stdout")
+}
 
-        let mut filename_counter = HashMap::new();
+fn link_and_optimize(state: &mut State) -> Result<()> {
+    compile_thread_stack_size_stub(state)?;
+    link_inputs(state)?;
 
-        for input in &state.args.compiler_inputs {
-            let mut command = Command::new(&compiler_path);
+    if state.build_settings.use_wasm_opt {
+        run_wasm_opt(state)?;
+    }
 
-            command.args(&command_args);
+    if state.user_settings.emit_wat {
+        emit_wat(state);
+    }
 
-            command.arg(input);
+    if state.user_settings.component {
+        encode_component(state)?;
+    }
 
-            let output_path = {
-                let input_name = input.file_name().unwrap_or_else(|| OsStr::new("output"));
-                let counter = filename_counter.entry(input_name.to_owned()).or_insert(0);
-                let mut output_name = input_name.to_owned();
-                output_name.push(format!(".{}.o", counter));
-                *counter += 1;
-                state.temp_dir.join(output_name)
-            };
+    Ok(())
+}
 
-            command.arg("-o").arg(&output_path);
-            state.args.linker_inputs.push(output_path);
+/// With ASSERTIONS=1, `WASIXCC_ASSERTIONS` is defined and `NDEBUG` undefined;
+/// with ASSERTIONS=0, `NDEBUG` is defined. If unset, optimized builds (-O1 and
+/// above) default to ASSERTIONS=0 and -O0 builds are left alone. These go before
+/// the user's flags, so their own -D/-U flags still take priority.
+fn assertion_args(state: &State) -> &'static [&'static str] {
+    let assertions = state
+        .user_settings
+        .assertions
+        .or((state.build_settings.opt_level != OptLevel::O0).then_some(false));
 
-            run_command(command)?;
-        }
-    } else {
-        // If we're not linking, just push all inputs to clang to get one output
+    match assertions {
+        Some(true) => &["-DWASIXCC_ASSERTIONS=1", "-UNDEBUG"],
+        Some(false) => &["-DNDEBUG"],
+        None => &[],
+    }
+}
 
-        let mut command = Command::new(&compiler_path);
+/// Generated build files sometimes list a source twice, which would otherwise
+/// fail at link time with confusing duplicate symbol errors.
+fn remove_duplicate_compiler_inputs(args: &mut PreparedArgs) {
+    let mut seen = HashSet::new();
+    let mut inputs = vec![];
+    let mut languages = vec![];
+    let mut arg_counts = vec![];
 
-        command.args(&command_args);
-        command.args(&state.args.compiler_inputs);
-        if let Some(output_path) = state.args.output.as_ref() {
-            command.arg("-o").arg(output_path);
+    for ((input, language), arg_count) in args
+        .compiler_inputs
+        .drain(..)
+        .zip(args.compiler_input_languages.drain(..))
+        .zip(args.compiler_input_arg_counts.drain(..))
+    {
+        let key = std::fs::canonicalize(&input).unwrap_or_else(|_| input.clone());
+        if seen.insert(key) {
+            inputs.push(input);
+            languages.push(language);
+            arg_counts.push(arg_count);
+        } else {
+            print_warning(&format!(
+                "Input file {input:?} was given more than once; compiling it only once"
+            ));
         }
+    }
 
-        run_command(command)?;
+    args.compiler_inputs = inputs;
+    args.compiler_input_languages = languages;
+    args.compiler_input_arg_counts = arg_counts;
+}
+
+fn is_header_input(input: &Path, language: Option<&str>) -> bool {
+    match language {
+        Some(language) => language.ends_with("-header"),
+        None => input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| HEADER_EXTENSIONS.contains(ext)),
     }
+}
 
-    Ok(())
+fn is_ir_input(input: &Path, language: Option<&str>) -> bool {
+    match language {
+        Some(language) => language == "ir",
+        None => input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IR_EXTENSIONS.contains(ext)),
+    }
 }
 
-fn link_inputs(state: &State) -> Result<()> {
-    let linker_path = state.user_settings.llvm_location.get_tool_path("wasm-ld");
+fn is_objc_input(input: &Path, language: Option<&str>) -> bool {
+    match language {
+        Some(language) => OBJC_LANGUAGES.contains(language),
+        None => input
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| OBJC_EXTENSIONS.contains(ext)),
+    }
+}
 
-    let sysroot_lib_path = state.user_settings.sysroot_location().join("lib");
-    let sysroot_lib_wasm32_path = sysroot_lib_path.join("wasm32-wasi");
+/// Preprocessor and language flags, which clang warns about as unused when
+/// compiling IR. Those in the first list take their value as the next argument
+/// when given on their own, as in `-I include`.
+const SOURCE_ONLY_ARGS_WITH_VALUES: &[&str] = &[
+    "-D",
+    "-U",
+    "-I",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-Xpreprocessor",
+];
+const SOURCE_ONLY_ARG_PREFIXES: &[&str] = &["-std=", "-stdlib=", "-Wp,"];
+const SOURCE_ONLY_ARGS: &[&str] = &[
+    "-MD",
+    "-MMD",
+    "-MP",
+    "-nostdinc",
+    "-nostdinc++",
+    "-fexceptions",
+    "-fno-exceptions",
+    "-fcxx-exceptions",
+    "-fno-cxx-exceptions",
+    "-frtti",
+    "-fno-rtti",
+];
 
-    let mut command = Command::new(linker_path);
+/// IR is compiled like sources are, so it still gets the target, features,
+/// PIC and optimization flags, but not the preprocessor and language flags
+/// meant for sources (ours and the user's CFLAGS alike).
+fn ir_input_args<S: AsRef<OsStr>>(args: &[S]) -> Vec<&OsStr> {
+    let mut result = vec![];
+    let mut iter = args.iter().map(AsRef::as_ref);
+    while let Some(arg) = iter.next() {
+        let Some(arg_str) = arg.to_str() else {
+            result.push(arg);
+            continue;
+        };
+        if SOURCE_ONLY_ARGS_WITH_VALUES.contains(&arg_str) {
+            iter.next();
+        } else if !(SOURCE_ONLY_ARGS.contains(&arg_str)
+            || SOURCE_ONLY_ARG_PREFIXES
+                .iter()
+                .chain(SOURCE_ONLY_ARGS_WITH_VALUES)
+                .any(|prefix| arg_str.starts_with(prefix)))
+        {
+            result.push(arg);
+        }
+    }
+    result
+}
 
-    command.args(&state.args.linker_args);
+/// Like other compiler drivers, we switch to C++ mode when given C++ sources,
+/// either by extension or through `-x c++`. An explicit `-x` for another
+/// language overrides the extension.
+fn has_cxx_inputs(args: &PreparedArgs) -> bool {
+    args.compiler_inputs
+        .iter()
+        .zip(&args.compiler_input_languages)
+        .any(|(input, language)| match language {
+            Some(language) => language.starts_with("c++"),
+            None => input
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| CXX_SOURCE_EXTENSIONS.contains(ext)),
+        })
+}
 
-    command.args([
-        "--extra-features=atomics",
-        "--extra-features=bulk-memory",
-        "--extra-features=mutable-globals",
-        "--shared-memory",
-        "--max-memory=4294967296", // TODO: make configurable
-        "--import-memory",
-        "--export-dynamic",
-        "--export=__wasm_call_ctors",
-    ]);
+/// Bundles the compiled objects (and any objects given as inputs) into the
+/// output archive, in place of linking.
+fn archive_objects(state: &mut State) -> Result<()> {
+    if let Some(archive) = state
+        .args
+        .linker_inputs
+        .iter()
+        .find(|input| deduce_output_kind(input) == Some(OutputKind::Archive))
+    {
+        bail!("Can't add the archive {archive:?} to another archive; link against it instead");
+    }
 
-    command.args(&state.user_settings.extra_linker_flags);
+    let output = output_path(state).to_owned();
 
-    if state.user_settings.wasm_exceptions {
-        command.args(["-mllvm", "--wasm-enable-sjlj"]);
+    // llvm-ar r would otherwise keep any stale members of an existing archive
+    if !state.build_settings.dry_run() && output.exists() {
+        std::fs::remove_file(&output)
+            .with_context(|| format!("Failed to remove existing archive {output:?}"))?;
     }
 
-    let module_kind = state.user_settings.module_kind();
+    let mut operation = "rcs".to_owned();
+    if state.user_settings.ar_deterministic {
+        ar::make_deterministic(&mut operation);
+    }
 
-    command.args([
-        "--export=__wasm_init_tls",
-        "--export=__wasm_signal",
-        "--export=__tls_size",
-        "--export=__tls_align",
-        "--export=__tls_base",
-    ]);
+    let mut command = Command::new(state.user_settings.get_tool_path("llvm-ar"));
+    command.arg(operation);
+    command.arg(&output);
+    command.args(&state.args.linker_inputs);
 
-    if module_kind.is_executable() {
-        command.args([
-            "--export-if-defined=__stack_pointer",
-            "--export-if-defined=__heap_base",
-            "--export-if-defined=__data_end",
-        ]);
+    run_step(state, BuildStage::Link, command)
+}
+
+/// Symbols that make the inputs a program rather than part of one. For WASI,
+/// clang renames main to __main_argc_argv or __main_void.
+const ENTRY_POINT_SYMBOLS: &[&str] = &["main", "__main_argc_argv", "__main_void", "_start"];
+
+/// For MODULE_KIND=auto, once the inputs are compiled: links an executable if
+/// any of the objects defines an entry point, and merges them into a
+/// relocatable object otherwise.
+fn resolve_auto_module_kind(
+    user_settings: &mut UserSettings,
+    args: &PreparedArgs,
+    print_only: bool,
+) -> Result<()> {
+    if print_only {
+        bail!(
+            "MODULE_KIND=auto is decided from the compiled objects, so it can't be used \
+            with --print-effective-command or --print-config; set MODULE_KIND explicitly"
+        );
     }
 
-    if matches!(module_kind, ModuleKind::DynamicMain) {
-        command.args(["--whole-archive", "--export-all"]);
+    let mut has_entry_point = false;
+    for input in &args.linker_inputs {
+        let symbols = list_symbols_with_settings(input, user_settings)?;
+        if symbols
+            .iter()
+            .any(|symbol| symbol.defined && ENTRY_POINT_SYMBOLS.contains(&symbol.name.as_str()))
+        {
+            has_entry_point = true;
+            break;
+        }
     }
 
-    if module_kind.is_executable() {
-        let mut lib_arg = OsString::new();
-        lib_arg.push("-L");
-        lib_arg.push(&sysroot_lib_path);
-        command.arg(lib_arg);
+    let module_kind = if has_entry_point {
+        ModuleKind::StaticMain
+    } else {
+        ModuleKind::Relocatable
+    };
+    tracing::info!("MODULE_KIND=auto resolved to {module_kind:?}");
+    user_settings.module_kind = Some(module_kind);
+    Ok(())
+}
 
-        let mut lib_arg = OsString::new();
-        lib_arg.push("-L");
-        lib_arg.push(&sysroot_lib_wasm32_path);
-        command.arg(lib_arg);
+/// Merges the inputs into one relocatable object. Nothing from the sysroot
+/// (crt1.o, libc etc.) is added; that happens when the object gets linked.
+fn link_relocatable(state: &mut State) -> Result<()> {
+    let mut command = Command::new(state.user_settings.get_tool_path("wasm-ld"));
+    command.arg("-r");
+    command.args(
+        state
+            .args
+            .linker_args
+            .iter()
+            .filter(|arg| !is_relocatable_arg(arg)),
+    );
+    command.args(&state.user_settings.extra_linker_flags);
+    command.args(&state.args.linker_inputs);
+    command.arg("-o").arg(output_path(state));
 
-        // Hack: we're linking libclang_rt into libc, so no need to link that here
-        command.args([
-            "-lwasi-emulated-mman",
-            "-lc",
-            "-lresolv",
-            "-lrt",
-            "-lm",
-            "-lpthread",
-            "-lutil",
-        ]);
+    run_step(state, BuildStage::Link, command)
+}
 
-        if state.cxx {
-            command.args(["-lc++", "-lc++abi"]);
+/// The linker args minus the values of options such as -mllvm, so a value
+/// that happens to look like a flag isn't taken for one.
+fn linker_flags(linker_args: &[String]) -> impl Iterator<Item = &String> {
+    let mut iter = linker_args.iter();
+    std::iter::from_fn(move || {
+        let arg = iter.next()?;
+        if WASM_LD_FLAGS_WITH_ARGS.contains(arg.as_str()) {
+            iter.next();
+        }
+        Some(arg)
+    })
+}
+
+/// wasm-ld has no --version-script, so one given as a linker flag is taken
+/// over as VERSION_SCRIPT instead.
+fn take_version_script_arg(linker_args: &mut Vec<String>, user_settings: &mut UserSettings) {
+    let mut remaining = Vec::with_capacity(linker_args.len());
+    let mut iter = std::mem::take(linker_args).into_iter();
+    while let Some(arg) = iter.next() {
+        let path = match arg.strip_prefix("--version-script") {
+            Some(path) if path.starts_with('=') => PathBuf::from(&path[1..]),
+            Some("") => match iter.next() {
+                Some(path) => PathBuf::from(path),
+                None => continue,
+            },
+            _ => {
+                let has_next_arg = WASM_LD_FLAGS_WITH_ARGS.contains(arg.as_str());
+                remaining.push(arg);
+                if has_next_arg {
+                    remaining.extend(iter.next());
+                }
+                continue;
+            }
+        };
+
+        if let Some(previous) = &user_settings.version_script {
+            if *previous != path {
+                print_warning(&format!(
+                    "--version-script {path:?} overrides the VERSION_SCRIPT {previous:?}"
+                ));
+            }
         }
+        user_settings.version_script = Some(path);
     }
+    *linker_args = remaining;
+}
 
-    if matches!(module_kind, ModuleKind::DynamicMain) {
-        command.args(["--no-whole-archive"]);
+fn is_relocatable_arg(arg: &str) -> bool {
+    let arg = arg.strip_prefix("-Wl,").unwrap_or(arg);
+    arg == "-r" || arg == "--relocatable"
+}
+
+/// Compiling and linking must agree on this, or the module fails to load.
+fn uses_pic(state: &State) -> bool {
+    state.user_settings.module_kind().requires_pic() || state.user_settings.pic
+}
+
+fn output_path(state: &State) -> &Path {
+    if let Some(output) = &state.args.output {
+        output.as_path()
+    } else {
+        match state.user_settings.module_kind() {
+            ModuleKind::StaticMain
+            | ModuleKind::DynamicMain
+            | ModuleKind::SharedLibrary
+            | ModuleKind::Auto => Path::new("a.out"),
+            ModuleKind::ObjectFile | ModuleKind::Relocatable => Path::new("a.o"),
+            ModuleKind::Archive => Path::new("a.a"),
+        }
     }
+}
 
-    if state.user_settings.module_kind().requires_pic() {
-        command.args([
-            "--experimental-pic",
-            "--export-if-defined=__wasm_apply_data_relocs",
-        ]);
+/// We leave out our own -fno-exceptions when wasm exceptions are on, but the
+/// user may still pass one, which clang takes over -fwasm-exceptions; `throw`
+/// then fails to compile, or traps in code that was built before.
+fn check_cxx_exceptions_flags(state: &State) -> Result<()> {
+    if !state.cxx || state.user_settings.exceptions_mode != ExceptionsMode::Wasm {
+        return Ok(());
     }
 
-    match module_kind {
-        ModuleKind::StaticMain => {
-            // TODO: make configurable
-            command.args(["-z", "stack-size=8388608"]);
-        }
+    let last_exceptions_flag = state.args.compiler_args.iter().rev().find(|arg| {
+        matches!(
+            arg.as_str(),
+            "-fexceptions" | "-fno-exceptions" | "-fcxx-exceptions" | "-fno-cxx-exceptions"
+        )
+    });
+    if let Some(flag) = last_exceptions_flag.filter(|flag| flag.starts_with("-fno-")) {
+        bail!(
+            "{flag} turns C++ exceptions off, but wasm exceptions were turned on with \
+            -fwasm-exceptions, WASM_EXCEPTIONS or CXX_EXCEPTIONS; drop one of the two, \
+            or use EXCEPTIONS_MODE=sjlj if only setjmp/longjmp is needed"
+        );
+    }
+    Ok(())
+}
 
-        ModuleKind::DynamicMain => {
-            command.args(["-pie", "-lcommon-tag-stubs"]);
-        }
+/// Objects with atomics can only be linked with shared memory, which THREADS=0
+/// turns off, so a -matomics from the user would otherwise fail the link.
+fn check_user_atomics_flag(state: &State) -> Result<()> {
+    if state.user_settings.threads {
+        return Ok(());
+    }
 
-        ModuleKind::SharedLibrary => {
-            command.args([
-                "-shared",
-                "--no-entry",
-                "--unresolved-symbols=import-dynamic",
+    let last_atomics_flag = state
+        .args
+        .compiler_args
+        .iter()
+        .rfind(|arg| *arg == "-matomics" || *arg == "-mno-atomics");
+    if last_atomics_flag.is_some_and(|arg| arg == "-matomics") {
+        bail!(
+            "-matomics needs shared memory, but threads are disabled (THREADS=0 or \
+            ABI_PROFILE); drop -matomics, or enable THREADS to link with shared memory"
+        );
+    }
+    Ok(())
+}
+
+fn compile_inputs(state: &mut State) -> Result<()> {
+    let _span = tracing::info_span!("compile").entered();
+
+    check_cxx_exceptions_flags(state)?;
+    check_user_atomics_flag(state)?;
+
+    let compiler_path =
+        state
+            .user_settings
+            .get_tool_path(if state.cxx { "clang++" } else { "clang" });
+
+    let target_arg = OsString::from(format!("--target={}", state.user_settings.target));
+    let mut command_args: Vec<&OsStr> = vec![
+        OsStr::new("--sysroot"),
+        state.user_settings.sysroot_location().as_os_str(),
+        &target_arg,
+    ];
+
+    match state.user_settings.analyze {
+        // Diagnostics go to stderr; --analyze would otherwise write .plist files
+        Some(mode) => {
+            command_args.extend([
+                OsStr::new("--analyze"),
+                OsStr::new("--analyzer-output"),
+                OsStr::new("text"),
             ]);
+            if mode == AnalyzeMode::Strict {
+                command_args.push(OsStr::new("--analyzer-Werror"));
+            }
         }
+        None => command_args.push(OsStr::new("-c")),
+    }
+    let required_arg_count = command_args.len();
+
+    command_args.extend([
+        OsStr::new("-fno-trapping-math"),
+        OsStr::new("-D_WASI_EMULATED_MMAN"),
+        OsStr::new("-D_WASI_EMULATED_SIGNAL"),
+        OsStr::new("-D_WASI_EMULATED_PROCESS_CLOCKS"),
+    ]);
+
+    let feature_flags = state
+        .user_settings
+        .compile_features()
+        .iter()
+        .map(|feature| OsString::from(format!("-m{feature}")))
+        .collect::<Vec<_>>();
+    command_args.extend(feature_flags.iter().map(OsString::as_os_str));
 
-        ModuleKind::ObjectFile => panic!("Internal error: object files can't be linked"),
+    if state.user_settings.threads {
+        command_args.extend([
+            OsStr::new("-pthread"),
+            OsStr::new("-mthread-model"),
+            OsStr::new("posix"),
+        ]);
+    } else {
+        command_args.extend([OsStr::new("-mthread-model"), OsStr::new("single")]);
     }
 
-    command.args(&state.args.linker_inputs);
+    if state.user_settings.exceptions_mode == ExceptionsMode::Wasm {
+        command_args.push(OsStr::new("-fwasm-exceptions"));
+    }
+    if state.user_settings.setjmp {
+        command_args.extend([OsStr::new("-mllvm"), OsStr::new("-wasm-enable-sjlj")]);
+    }
 
-    if module_kind.is_executable() {
-        command.arg(sysroot_lib_wasm32_path.join("crt1.o"));
+    // PIC code (shared libraries, dynamic executables and PIC objects) gets
+    // default visibility so its symbols can be linked against dynamically;
+    // anything else keeps clang's default. Either way, a -fvisibility= from the
+    // user decides instead.
+    let user_visibility = state
+        .args
+        .compiler_args
+        .iter()
+        .any(|arg| arg.starts_with("-fvisibility="));
+    if uses_pic(state) {
+        command_args.push(OsStr::new("-fPIC"));
+        command_args.push(OsStr::new("-ftls-model=global-dynamic"));
+        if !user_visibility {
+            command_args.push(OsStr::new("-fvisibility=default"));
+        }
     } else {
-        command.arg(sysroot_lib_wasm32_path.join("scrt1.o"));
+        command_args.push(OsStr::new("-ftls-model=local-exec"));
     }
 
-    command.arg("-o");
-    command.arg(output_path(state));
+    if state.cxx && state.user_settings.exceptions_mode != ExceptionsMode::Wasm {
+        // C++ exceptions need wasm exceptions (see CXX_EXCEPTIONS), so they're
+        // off by default
+        command_args.push(OsStr::new("-fno-exceptions"));
+    }
 
-    run_command(command)
-}
+    if state.build_settings.debug_level != DebugLevel::None {
+        command_args.push(OsStr::new("-g"));
+    }
 
-fn run_wasm_opt(state: &State) -> Result<()> {
-    let mut command = Command::new("wasm-opt");
+    command_args.extend(assertion_args(state).iter().map(OsStr::new));
 
-    if state.user_settings.wasm_exceptions {
-        command.arg("--experimental-new-eh");
+    // Before the user's flags, so an explicit -fstrict-aliasing still wins
+    if !state.user_settings.strict_aliasing {
+        command_args.push(OsStr::new("-fno-strict-aliasing"));
     }
 
-    match state.build_settings.opt_level {
-        // -O0 does nothing, no need to specify it
-        OptLevel::O0 => (),
-        OptLevel::O1 => {
-            command.arg("-O1");
-        }
-        OptLevel::O2 => {
-            command.arg("-O2");
-        }
-        OptLevel::O3 => {
-            command.arg("-O3");
-        }
-        OptLevel::O4 => {
-            command.arg("-O4");
-        }
-        OptLevel::Os => {
-            command.arg("-Os");
-        }
-        OptLevel::Oz => {
-            command.arg("-Oz");
-        }
+    // Also before the user's flags, so e.g. -fno-sanitize=alignment can narrow it
+    if let Some(sanitizer) = state.user_settings.sanitizer {
+        command_args.push(OsStr::new(sanitizer.compile_flag()));
     }
 
-    command.args(&state.user_settings.wasm_opt_flags);
+    if state.user_settings.uses_profile_runtime() {
+        command_args.push(OsStr::new("-fprofile-instr-generate"));
+    }
+    if state.user_settings.coverage {
+        command_args.push(OsStr::new("-fcoverage-mapping"));
+    }
 
-    if command.get_args().next().is_none() {
-        tracing::info!("Skipping wasm-opt as no passes were specified or needed");
-        return Ok(());
+    let profile_use_arg = state
+        .user_settings
+        .pgo_use
+        .as_ref()
+        .map(|profile| OsString::from(format!("-fprofile-instr-use={}", profile.display())));
+    if let Some(arg) = &profile_use_arg {
+        command_args.push(arg);
+    }
+
+    // Makes clang keep the preprocessed source, bitcode and assembly next to
+    // each object
+    if state.user_settings.save_temps.is_some() {
+        command_args.push(OsStr::new("-save-temps=obj"));
+    }
+
+    // Colors and source snippets would get in the way of parsing clang's output
+    if state.user_settings.json_diagnostics {
+        command_args.extend([
+            OsStr::new("-fno-color-diagnostics"),
+            OsStr::new("-fno-caret-diagnostics"),
+        ]);
     }
 
-    match state.build_settings.debug_level {
-        DebugLevel::None | DebugLevel::G0 => (),
-        DebugLevel::G1 | DebugLevel::G2 | DebugLevel::G3 => {
-            command.arg("-g");
+    // Keeps the build directory out of debug info and __FILE__, so builds on
+    // different machines match. wasm-ld itself writes no timestamps or paths.
+    let prefix_map = match std::env::current_dir() {
+        Ok(cwd) if state.user_settings.reproducible => {
+            let mut prefix_map = OsString::from("-ffile-prefix-map=");
+            prefix_map.push(cwd);
+            prefix_map.push("=.");
+            Some(prefix_map)
         }
+        _ => None,
+    };
+    if let Some(prefix_map) = &prefix_map {
+        command_args.push(prefix_map);
     }
 
-    let output_path = output_path(state);
-    command.arg(output_path);
-    command.arg("-o");
-    command.arg(output_path);
+    // Also before the user's flags, so -fno-function-sections etc. still work
+    if state.user_settings.gc_sections {
+        command_args.extend([
+            OsStr::new("-ffunction-sections"),
+            OsStr::new("-fdata-sections"),
+        ]);
+    }
 
-    run_command(command)
-}
+    if state.user_settings.lto_cache.is_some() && !state.user_settings.no_default_flags {
+        let lto_arg = state
+            .args
+            .compiler_args
+            .iter()
+            .rfind(|arg| arg.starts_with("-flto") || arg.starts_with("-fno-lto"));
+        match lto_arg.map(String::as_str) {
+            None => command_args.push(OsStr::new("-flto=thin")),
+            Some("-flto=thin") => (),
+            Some(arg) => print_warning(&format!(
+                "LTO_CACHE only applies to ThinLTO, but {arg} was given; \
+                the cache won't be used for these inputs"
+            )),
+        }
+    }
 
-fn prepare_compiler_args(
-    args: Vec<String>,
-    user_settings: &mut UserSettings,
-) -> Result<(PreparedArgs, BuildSettings)> {
-    let mut result = PreparedArgs {
-        compiler_args: Vec::new(),
-        linker_args: Vec::new(),
-        compiler_inputs: Vec::new(),
-        linker_inputs: Vec::new(),
-        output: None,
-    };
-    let mut build_settings = BuildSettings {
-        opt_level: OptLevel::O0,
-        debug_level: DebugLevel::G0,
-        use_wasm_opt: true,
-    };
+    // NO_DEFAULT_FLAGS leaves only --sysroot, --target and -c (or --analyze);
+    // the inputs, -x, -o and the -MD fix-ups in dependency_file_args are still
+    // added per input
+    if state.user_settings.no_default_flags {
+        command_args.truncate(required_arg_count);
+    }
 
-    let mut extra_flags = vec![];
-    std::mem::swap(&mut extra_flags, &mut user_settings.extra_compiler_flags);
+    let compiler_args = &state.args.compiler_args;
+    // Only our own flags count; a -matomics from the user was already checked
+    // against THREADS in check_user_atomics_flag
+    state.compiled_with_atomics = Some(command_args.contains(&OsStr::new("-matomics")));
 
-    // Since we used to do CC="clang --flag1 --flag2", it seems putting the extra flags
-    // first has worked for us, so we keep that behavior.
-    let mut iter = extra_flags.into_iter().chain(args);
+    let analyze = state.user_settings.analyze.is_some();
 
-    while let Some(arg) = iter.next() {
-        if let Some(arg) = arg.strip_prefix("-Wl,") {
-            match arg.split_once(',') {
-                Some((x, y)) => {
-                    result.linker_args.push(x.to_owned());
-                    result.linker_args.push(y.to_owned());
-                }
-                None => {
-                    result.linker_args.push(arg.to_owned());
-                }
-            }
-        } else if arg == "-Xlinker" {
-            let Some(next_arg) = iter.next() else {
-                bail!("Expected argument after -Xlinker");
-            };
-            result.linker_args.push(next_arg);
-        } else if arg == "-z" {
-            let Some(next_arg) = iter.next() else {
-                bail!("Expected argument after -z");
-            };
-            result.linker_args.push("-z".to_owned());
-            result.linker_args.push(next_arg);
-        } else if arg == "-o" {
-            let Some(next_arg) = iter.next() else {
-                bail!("Expected argument after -o");
-            };
-            let output = PathBuf::from(next_arg);
-            if user_settings.module_kind.is_none() {
-                if let Some(module_kind) = output.extension().and_then(deduce_module_kind) {
-                    user_settings.module_kind = Some(module_kind);
-                }
+    if state.user_settings.module_kind().is_built_from_objects() || analyze {
+        // If we're linking or archiving later, we should compile each input separately.
+        // The analyzer also wants one input at a time, since it can't write
+        // one output for all of them.
+
+        let mut filename_counter = HashMap::new();
+        let mut commands = vec![];
+        let mut objects = vec![];
+        let mut dependency_file_names = HashSet::new();
+
+        // Archive members keep the names the objects get here, so tools such as
+        // `ar t` and nm show a.o for a.c, like with a native build
+        let archive = state.user_settings.module_kind() == ModuleKind::Archive && !analyze;
+        let mut member_names = state
+            .args
+            .linker_inputs
+            .iter()
+            .filter_map(|input| input.file_name().map(OsStr::to_owned))
+            .collect::<HashSet<_>>();
+
+        // Flags apply to the inputs after them, like -x does. Flags after the
+        // last input apply to all of them, so `wasixcc a.c b.c -O2` still works.
+        let trailing_args = state
+            .args
+            .compiler_input_arg_counts
+            .last()
+            .map_or(&compiler_args[..], |&count| &compiler_args[count..]);
+
+        for ((input, language), &arg_count) in state
+            .args
+            .compiler_inputs
+            .iter()
+            .zip(&state.args.compiler_input_languages)
+            .zip(&state.args.compiler_input_arg_counts)
+        {
+            let mut command = Command::new(&compiler_path);
+            set_reproducible_env(state, &mut command);
+
+            let is_ir = is_ir_input(input, language.as_deref());
+            if is_ir {
+                command.args(ir_input_args(&command_args));
+                command.args(ir_input_args(&compiler_args[..arg_count]));
+                command.args(ir_input_args(trailing_args));
+            } else {
+                command.args(&command_args);
+                command.args(&compiler_args[..arg_count]);
+                command.args(trailing_args);
             }
-            result.output = Some(output);
-        } else if arg.starts_with('-') {
-            if update_build_settings_from_arg(&arg, &mut build_settings, user_settings)? {
-                let has_next_arg = CLANG_FLAGS_WITH_ARGS.contains(&arg[..]);
-                result.compiler_args.push(arg);
-                if has_next_arg {
-                    if let Some(next_arg) = iter.next() {
-                        result.compiler_args.push(next_arg);
-                    }
-                }
+
+            // IR has no headers to depend on
+            if !is_ir {
+                command.args(dependency_file_args(
+                    state,
+                    input,
+                    &mut dependency_file_names,
+                ));
             }
-        } else {
-            // Assume it's an input file
-            let input = PathBuf::from(&arg);
-            match input.extension().and_then(|ext| ext.to_str()) {
-                Some("a") | Some("o") | Some("obj") => {
-                    result.linker_inputs.push(PathBuf::from(arg));
-                }
-                _ => {
-                    result.compiler_inputs.push(PathBuf::from(arg));
-                }
+
+            if let Some(language) = language {
+                command.arg("-x").arg(language);
             }
+            command.arg(input);
+
+            let output_path = state.temp_dir.join(if archive {
+                archive_member_name(input, &mut member_names, &state.user_settings.object_suffix)
+            } else {
+                object_file_name(
+                    input,
+                    &mut filename_counter,
+                    if analyze {
+                        ".plist"
+                    } else {
+                        &state.user_settings.object_suffix
+                    },
+                )
+            });
+
+            command.arg("-o").arg(&output_path);
+            objects.push(output_path);
+            commands.push(command);
         }
-    }
 
-    if user_settings.module_kind.is_none() {
-        for arg in &result.compiler_args {
-            if arg == "-shared" {
-                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
-                break;
-            } else if arg == "-c" || arg == "-S" || arg == "-E" {
-                user_settings.module_kind = Some(ModuleKind::ObjectFile);
-                break;
+        if !analyze {
+            state.args.linker_inputs.extend(objects);
+        }
+        for command in commands {
+            run_step(state, BuildStage::Compile, command)?;
+        }
+    } else {
+        // If we're not linking, just push all inputs to clang to get one output.
+        // IR inputs go in a command of their own, without the source flags.
+        let (ir_inputs, source_inputs): (Vec<_>, Vec<_>) = state
+            .args
+            .compiler_inputs
+            .iter()
+            .zip(&state.args.compiler_input_languages)
+            .partition(|(input, language)| is_ir_input(input, language.as_deref()));
+        if !ir_inputs.is_empty() && !source_inputs.is_empty() {
+            if let Some(output) = &state.args.output {
+                bail!("Cannot compile both IR and source inputs to the single output {output:?}");
             }
         }
-    }
 
-    if user_settings.module_kind.is_none() {
-        for arg in &result.linker_args {
-            if arg == "-shared" {
-                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
-                break;
-            } else if arg == "-pie" {
-                user_settings.module_kind = Some(ModuleKind::DynamicMain);
-                break;
+        let mut commands = vec![];
+        for (inputs, is_ir) in [(source_inputs, false), (ir_inputs, true)] {
+            if inputs.is_empty() {
+                continue;
+            }
+
+            let mut command = Command::new(&compiler_path);
+            set_reproducible_env(state, &mut command);
+
+            if is_ir {
+                command.args(ir_input_args(&command_args));
+                command.args(ir_input_args(compiler_args));
+            } else {
+                command.args(&command_args);
+                command.args(compiler_args);
+            }
+            // -x applies to all inputs after it, so only emit it when it changes
+            let mut current_language = None;
+            for (input, language) in inputs {
+                if language.as_deref() != current_language {
+                    command.arg("-x").arg(language.as_deref().unwrap_or("none"));
+                    current_language = language.as_deref();
+                }
+                command.arg(input);
+            }
+            if let Some(output_path) = state.args.output.as_ref() {
+                command.arg("-o").arg(output_path);
             }
+            commands.push(command);
+        }
+
+        for command in commands {
+            run_step(state, BuildStage::Compile, command)?;
         }
     }
 
-    Ok((result, build_settings))
+    Ok(())
 }
 
-fn prepare_linker_args(
-    args: Vec<String>,
-    user_settings: &mut UserSettings,
-) -> Result<PreparedArgs> {
-    let mut result = PreparedArgs {
-        compiler_args: Vec::new(),
-        linker_args: Vec::new(),
-        compiler_inputs: Vec::new(),
-        linker_inputs: Vec::new(),
-        output: None,
+/// With REPRODUCIBLE=1, pins __DATE__ and __TIME__ to the epoch unless the user
+/// already chose a SOURCE_DATE_EPOCH.
+fn set_reproducible_env(state: &State, command: &mut Command) {
+    if state.user_settings.reproducible && std::env::var_os("SOURCE_DATE_EPOCH").is_none() {
+        command.env("SOURCE_DATE_EPOCH", "0");
+    }
+}
+
+/// Since we compile each input to an object in our temp dir before linking, clang
+/// would write `-MD`/`-MMD` dependency files there too, with the temp object as
+/// the target. Point them at `<stem>.d` next to the final output instead, with
+/// the final output as the target, unless the user chose those themselves.
+/// Inputs sharing a stem (x/a.c and y/a.c) get a.d and a.1.d, as archive
+/// members do.
+fn dependency_file_args(
+    state: &State,
+    input: &Path,
+    used_names: &mut HashSet<OsString>,
+) -> Vec<OsString> {
+    let compiler_args = &state.args.compiler_args;
+    let has_arg = |name: &str| compiler_args.iter().any(|arg| arg == name);
+    // -Wp,-MD,<file> names the file itself
+    let wp_dependency_file = compiler_args
+        .iter()
+        .any(|arg| arg.starts_with("-Wp,-MD,") || arg.starts_with("-Wp,-MMD,"));
+
+    if !(has_arg("-MD") || has_arg("-MMD") || wp_dependency_file) {
+        return vec![];
+    }
+
+    let output = output_path(state);
+    let mut result = vec![];
+
+    if !has_arg("-MF") && !wp_dependency_file {
+        let dep_file = output.with_file_name(archive_member_name(input, used_names, ".d"));
+        result.push(OsString::from("-MF"));
+        result.push(dep_file.into_os_string());
+    }
+
+    if !has_arg("-MT") && !has_arg("-MQ") {
+        result.push(OsString::from("-MT"));
+        result.push(output.as_os_str().to_owned());
+    }
+
+    result
+}
+
+/// Names the object for a compiled input, numbering inputs that share a
+/// file name so they don't clobber each other.
+fn object_file_name(
+    input: &Path,
+    filename_counter: &mut HashMap<OsString, usize>,
+    suffix: &str,
+) -> OsString {
+    let input_name = input.file_name().unwrap_or_else(|| OsStr::new("output"));
+    let counter = filename_counter.entry(input_name.to_owned()).or_insert(0);
+    let mut output_name = input_name.to_owned();
+    output_name.push(format!(".{counter}{suffix}"));
+    *counter += 1;
+    output_name
+}
+
+/// E.g. a.o for a.c, or a.1.o if another member is already called a.o.
+fn archive_member_name(input: &Path, used: &mut HashSet<OsString>, suffix: &str) -> OsString {
+    let stem = input.file_stem().unwrap_or_else(|| OsStr::new("output"));
+    let mut name = stem.to_owned();
+    name.push(suffix);
+    let mut counter = 1;
+    while used.contains(&name) {
+        name = stem.to_owned();
+        name.push(format!(".{counter}{suffix}"));
+        counter += 1;
+    }
+    used.insert(name.clone());
+    name
+}
+
+fn thread_stack_size_stub_source(size: u64) -> String {
+    format!(
+        r#"#define _GNU_SOURCE
+#include <pthread.h>
+
+__attribute__((constructor)) static void __wasixcc_set_thread_stack_size(void) {{
+    pthread_attr_t attr;
+    if (pthread_attr_init(&attr) == 0) {{
+        pthread_attr_setstacksize(&attr, {size}u);
+        pthread_setattr_default_np(&attr);
+        pthread_attr_destroy(&attr);
+    }}
+}}
+"#
+    )
+}
+
+/// wasm-ld has no way to set libc's default thread stack size, so we link in
+/// a constructor that sets it through `pthread_setattr_default_np` before
+/// `main` runs. Threads created with an explicit stack size are unaffected, and
+/// the main thread's stack is still sized by the linker's `-z stack-size`.
+fn compile_thread_stack_size_stub(state: &mut State) -> Result<()> {
+    let Some(size) = state.user_settings.thread_stack_size else {
+        return Ok(());
     };
 
-    let mut iter = args.into_iter();
+    if !state.user_settings.module_kind().is_executable() {
+        print_warning("THREAD_STACK_SIZE only applies to executables; ignoring it");
+        return Ok(());
+    }
 
-    while let Some(arg) = iter.next() {
-        if arg == "-o" {
-            let Some(next_arg) = iter.next() else {
-                bail!("Expected argument after -o");
-            };
-            let output = PathBuf::from(next_arg);
-            if user_settings.module_kind.is_none() {
-                if let Some(module_kind) = output.extension().and_then(deduce_module_kind) {
-                    user_settings.module_kind = Some(module_kind);
-                }
+    if !state.user_settings.threads {
+        print_warning("THREAD_STACK_SIZE has no effect when THREADS is disabled; ignoring it");
+        return Ok(());
+    }
+
+    let source_path = state.temp_dir.join("__wasixcc_thread_stack_size.c");
+    let source = thread_stack_size_stub_source(size);
+    std::fs::write(&source_path, &source)
+        .with_context(|| format!("Failed to write {source_path:?}"))?;
+    state.script_lines.push((
+        BuildStage::Compile,
+        format!("cat > \"$tmp\"/__wasixcc_thread_stack_size.c <<'EOF'\n{source}EOF"),
+    ));
+    let output_path = source_path.with_extension("o");
+
+    let mut command = Command::new(state.user_settings.get_tool_path("clang"));
+    command.arg("--sysroot");
+    command.arg(state.user_settings.sysroot_location());
+    command.arg(format!("--target={}", state.user_settings.target));
+    command.args(["-c", "-O2", "-pthread"]);
+    command.args(
+        state
+            .user_settings
+            .compile_features()
+            .iter()
+            .map(|feature| format!("-m{feature}")),
+    );
+    if uses_pic(state) {
+        command.args(["-fPIC", "-ftls-model=global-dynamic"]);
+    } else {
+        command.arg("-ftls-model=local-exec");
+    }
+    command.arg(&source_path).arg("-o").arg(&output_path);
+
+    run_step(state, BuildStage::Compile, command)?;
+
+    state.args.linker_inputs.push(output_path);
+
+    Ok(())
+}
+
+/// Fails early if the sysroot lacks the files needed to link this module kind,
+/// which otherwise shows up as a cryptic wasm-ld error; e.g. when SYSROOT points
+/// at a wasi-sdk sysroot. Files only other module kinds need are just warned about.
+fn check_sysroot(user_settings: &UserSettings, args: &PreparedArgs) -> Result<()> {
+    let Some(sysroot) = &user_settings.sysroot_location else {
+        return Ok(());
+    };
+    let lib_path = sysroot.join("lib").join(&user_settings.target);
+    // CRT1 and NO_CRT1 leave the sysroot's startfiles out
+    let links_sysroot_startfile =
+        args.links_startfiles() && user_settings.crt1.is_none() && !user_settings.no_crt1;
+
+    let (startfile, other_startfile) = if user_settings.module_kind().is_executable() {
+        ("crt1.o", "scrt1.o")
+    } else {
+        ("scrt1.o", "crt1.o")
+    };
+
+    let missing = [
+        ("libc.a", args.links_default_libs()),
+        (startfile, links_sysroot_startfile),
+    ]
+    .into_iter()
+    .filter(|(file, needed)| *needed && !lib_path.join(file).is_file())
+    .map(|(file, _)| file)
+    .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        bail!(
+            "The sysroot at {sysroot:?} is missing {}, required to link a {:?}; looked under \
+            {lib_path:?}. Make sure SYSROOT points at a WASIX sysroot.",
+            missing.join(" and "),
+            user_settings.module_kind()
+        );
+    }
+
+    if let Some(sanitizer) = user_settings.sanitizer {
+        let runtime = format!("lib{}.a", sanitizer.runtime_library());
+        if user_settings.module_kind().is_executable() && !lib_path.join(&runtime).is_file() {
+            bail!(
+                "SANITIZER={sanitizer:?} needs {runtime}, but the sysroot at {sysroot:?} \
+                doesn't have it under {lib_path:?}; use a sysroot built with the \
+                sanitizer runtimes"
+            );
+        }
+    }
+
+    if user_settings.uses_profile_runtime()
+        && user_settings.module_kind().is_executable()
+        && !lib_path.join("libclang_rt.profile.a").is_file()
+    {
+        let setting = if user_settings.coverage {
+            "COVERAGE"
+        } else {
+            "PGO_GEN"
+        };
+        bail!(
+            "{setting}=1 needs libclang_rt.profile.a, but the sysroot at {sysroot:?} \
+            doesn't have it under {lib_path:?}"
+        );
+    }
+
+    if links_sysroot_startfile && !lib_path.join(other_startfile).is_file() {
+        print_warning(&format!(
+            "The sysroot at {sysroot:?} is missing {other_startfile} under {lib_path:?}; \
+            it is not needed for this build, but other module kinds will fail to link"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writing the output would truncate an input of the same name before it's
+/// read, e.g. for `wasixcc -r foo.o -o foo.o`, so that's refused up front.
+fn check_output_not_input(args: &PreparedArgs) -> Result<()> {
+    // An input has to exist, so a missing output can't be one of them
+    let Some(output) = args
+        .output
+        .as_ref()
+        .and_then(|output| output.canonicalize().ok())
+    else {
+        return Ok(());
+    };
+
+    for input in args.compiler_inputs.iter().chain(&args.linker_inputs) {
+        if input.canonicalize().is_ok_and(|input| input == output) {
+            bail!(
+                "The input {input:?} is also the output; writing the output would \
+                overwrite it before it's read. Use a different path for -o."
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails early if the output can't be written, rather than letting wasm-ld
+/// fail with a less obvious error after doing all the work.
+fn check_output_writable(output: &Path) -> Result<()> {
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let permission_error = |err: std::io::Error| {
+        if err.kind() == std::io::ErrorKind::PermissionDenied {
+            anyhow::anyhow!("Cannot write output {output:?}: permission denied")
+        } else {
+            anyhow::anyhow!("Cannot write output {output:?}: {err}")
+        }
+    };
+
+    if output.exists() {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(output)
+            .map_err(permission_error)?;
+    } else if parent.is_dir() {
+        tempfile::NamedTempFile::new_in(parent).map_err(permission_error)?;
+    }
+
+    Ok(())
+}
+
+fn link_inputs(state: &mut State) -> Result<()> {
+    let _span = tracing::info_span!("link").entered();
+
+    let module_kind = state.user_settings.module_kind();
+    if state.user_settings.standalone && module_kind != ModuleKind::StaticMain {
+        bail!(
+            "STANDALONE=1 only applies to static executables, but this is a \
+            {module_kind:?}; dynamically linked modules must import their memory"
+        );
+    }
+    if state.user_settings.entry.is_some() && !module_kind.is_executable() {
+        bail!("ENTRY only applies to executables, but this is a {module_kind:?}");
+    }
+    if state.user_settings.component && !module_kind.is_executable() {
+        bail!("COMPONENT=1 only applies to executables, but this is a {module_kind:?}");
+    }
+
+    if !state.build_settings.dry_run() {
+        check_output_writable(output_path(state))?;
+        prepare_lto_cache(state)?;
+        add_exports_from_objects(state)?;
+    }
+
+    if let Some(path) = &state.user_settings.version_script {
+        state.version_script = Some(read_version_script(path)?);
+    }
+
+    let command = linker_command(state);
+    run_step(state, BuildStage::Link, command)
+}
+
+/// Exports every defined global symbol of the objects named in
+/// EXPORTS_FROM_OBJECT, in addition to (and deduplicated with) the ones from
+/// EXPORTED_FUNCTIONS.
+fn add_exports_from_objects(state: &mut State) -> Result<()> {
+    for object in &state.user_settings.exports_from_objects {
+        let mut command = Command::new(state.user_settings.get_tool_path("llvm-nm"));
+        command.args(["--extern-only", "--defined-only", "--format=just-symbols"]);
+        command.arg(object);
+
+        let output = command
+            .output()
+            .map_err(|error| spawn_error(&command, error, &state.user_settings))?;
+        if !output.status.success() {
+            bail!(
+                "Failed to read symbols from {object:?}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        for symbol in String::from_utf8_lossy(&output.stdout).lines() {
+            let symbol = symbol.trim();
+            if !symbol.is_empty()
+                && !state
+                    .user_settings
+                    .exported_functions
+                    .iter()
+                    .any(|name| name == symbol)
+            {
+                state
+                    .user_settings
+                    .exported_functions
+                    .push(symbol.to_owned());
             }
-            result.output = Some(output);
-        } else if arg.starts_with('-') {
-            let has_next_arg = WASM_LD_FLAGS_WITH_ARGS.contains(&arg[..]);
-            result.linker_args.push(arg);
-            if has_next_arg {
-                if let Some(next_arg) = iter.next() {
-                    result.linker_args.push(next_arg);
-                }
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates the ThinLTO cache dir, or drops the setting with a warning if this
+/// wasm-ld doesn't know about caching.
+fn prepare_lto_cache(state: &mut State) -> Result<()> {
+    let Some(cache_dir) = &state.user_settings.lto_cache else {
+        return Ok(());
+    };
+
+    let help = Command::new(state.user_settings.get_tool_path("wasm-ld"))
+        .arg("--help")
+        .output();
+    let supported = match help {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).contains("--thinlto-cache-dir"),
+        Err(_) => false,
+    };
+
+    if !supported {
+        print_warning("wasm-ld doesn't support --thinlto-cache-dir; linking without an LTO cache");
+        state.user_settings.lto_cache = None;
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(cache_dir)
+        .with_context(|| format!("Failed to create LTO cache directory at {cache_dir:?}"))?;
+
+    Ok(())
+}
+
+fn linker_command(state: &State) -> Command {
+    if state.user_settings.no_default_flags {
+        return minimal_linker_command(state);
+    }
+
+    let linker_path = state.user_settings.get_tool_path("wasm-ld");
+
+    let sysroot_lib_path = state.user_settings.sysroot_location().join("lib");
+    let sysroot_lib_wasm32_path = sysroot_lib_path.join(&state.user_settings.target);
+
+    let mut command = Command::new(linker_path);
+
+    // Symbol options such as --wrap apply to all inputs wherever they appear, so
+    // references from the sysroot libraries added below get wrapped too
+    command.args(&state.args.linker_args);
+
+    let shared_memory = state.user_settings.threads;
+    if let Some(atomics) = state.compiled_with_atomics {
+        assert_eq!(
+            atomics, shared_memory,
+            "Internal error: inputs were compiled with atomics={atomics} but are \
+            being linked with shared-memory={shared_memory}"
+        );
+    }
+
+    if state.user_settings.is_memory64() {
+        command.arg("-mwasm64");
+    }
+
+    match &state.user_settings.link_features {
+        Some(features) => {
+            command.arg(format!("--features={}", features.join(",")));
+        }
+        None if state.user_settings.target_features.is_some() => {
+            for feature in state.user_settings.compile_features() {
+                command.arg(format!("--extra-features={feature}"));
             }
-        } else {
-            // Assume it's an input file
-            result.linker_inputs.push(PathBuf::from(arg));
         }
+        None => {
+            if shared_memory {
+                command.arg("--extra-features=atomics");
+            }
+            for feature in state.user_settings.base_features() {
+                command.arg(format!("--extra-features={feature}"));
+            }
+            if state.user_settings.simd {
+                command.arg("--extra-features=simd128");
+            }
+        }
+    }
+
+    if shared_memory {
+        command.arg("--shared-memory");
+    }
+
+    // A standalone module defines and exports its own memory, so it can run
+    // without a host providing one
+    if state.user_settings.standalone {
+        command.arg("--export-memory");
+    } else {
+        command.arg("--import-memory");
+    }
+    // local: *; in a version script hides everything it doesn't list as
+    // global, which get exported below
+    if state
+        .version_script
+        .as_ref()
+        .is_some_and(|script| script.local_rest)
+    {
+        command.arg("--no-export-dynamic");
+    } else {
+        command.arg("--export-dynamic");
+    }
+
+    // For hosts that register callbacks in the function table, or share their
+    // own table with the module
+    if state.user_settings.export_table {
+        command.arg("--export-table");
+    } else if state.user_settings.import_table {
+        command.arg("--import-table");
+    }
+
+    let module_kind = state.user_settings.module_kind();
+    let minimal_exports = state.user_settings.minimal_exports;
+
+    // The dynamic linker runs side modules' constructors itself; executables'
+    // _start already calls them. This and the runtime exports below are all
+    // --export-if-defined, so a module (or a stripped-down sysroot) without
+    // them still links, and simply doesn't export them.
+    if !minimal_exports || matches!(module_kind, ModuleKind::SharedLibrary) {
+        command.arg("--export-if-defined=__wasm_call_ctors");
+    }
+
+    // TODO: make configurable
+    if !state.user_settings.is_memory64() {
+        command.arg(format!("--max-memory={MAX_MEMORY}"));
+    }
+
+    if let Some(initial_memory) = state.user_settings.initial_memory {
+        command.arg(format!("--initial-memory={initial_memory}"));
+    }
+
+    if state.user_settings.emit_relocs {
+        command.arg("--emit-relocs");
+    }
+
+    // wasm-ld collects garbage by default, but this keeps it on even if a
+    // build script disables it; an explicit --no-gc-sections still wins
+    if state.user_settings.gc_sections
+        && !linker_flags(&state.args.linker_args)
+            .chain(linker_flags(&state.user_settings.extra_linker_flags))
+            .any(|arg| arg == "--no-gc-sections")
+    {
+        command.arg("--gc-sections");
+    }
+
+    // Only ThinLTO's backends run in parallel; full LTO does its codegen in one
+    // piece, so it doesn't get the flag
+    if uses_thin_lto(state) {
+        let jobs = state.user_settings.lto_jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZero::get)
+        });
+        command.arg(format!("--thinlto-jobs={jobs}"));
+    }
+
+    if let Some(cache_dir) = &state.user_settings.lto_cache {
+        let mut cache_arg = OsString::from("--thinlto-cache-dir=");
+        cache_arg.push(cache_dir);
+        command.arg(cache_arg);
+
+        if let Some(policy) = &state.user_settings.lto_cache_policy {
+            command.arg(format!("--thinlto-cache-policy={policy}"));
+        }
+    }
+
+    match &state.user_settings.linker_map {
+        Some(LinkerMap::Stdout) => {
+            command.arg("--print-map");
+        }
+        Some(LinkerMap::File(path)) => {
+            let mut map_arg = OsString::from("-Map=");
+            map_arg.push(path);
+            command.arg(map_arg);
+        }
+        None => (),
+    }
+
+    command.args(&state.user_settings.extra_linker_flags);
+
+    // For code generated at link time by LTO
+    if state.user_settings.exceptions_mode.uses_wasm_eh() {
+        command.args(["-mllvm", "--wasm-enable-sjlj"]);
+    }
+
+    // The runtime sets up TLS through these when spawning a thread, or when
+    // loading a side module. __wasm_signal is how it delivers signals to handlers
+    // installed by the module, and the stack/heap layout exports are used when
+    // forking and unwinding stacks. MINIMAL_EXPORTS keeps only what's needed to
+    // start the module.
+    if !minimal_exports {
+        command.arg("--export-if-defined=__wasm_signal");
+    }
+
+    if !minimal_exports
+        || state.user_settings.threads
+        || matches!(module_kind, ModuleKind::SharedLibrary)
+    {
+        command.args([
+            "--export-if-defined=__wasm_init_tls",
+            "--export-if-defined=__tls_size",
+            "--export-if-defined=__tls_align",
+            "--export-if-defined=__tls_base",
+        ]);
+    }
+
+    if module_kind.is_executable() && !minimal_exports {
+        command.args([
+            "--export-if-defined=__stack_pointer",
+            "--export-if-defined=__heap_base",
+            "--export-if-defined=__data_end",
+        ]);
+    }
+
+    for name in &state.user_settings.exported_functions {
+        command.arg(format!("--export={name}"));
+    }
+
+    if let Some(script) = &state.version_script {
+        for name in &script.global {
+            command.arg(format!("--export={name}"));
+        }
+    }
+
+    if state.user_settings.export_all {
+        command.arg("--export-all");
+    }
+
+    if matches!(module_kind, ModuleKind::DynamicMain) {
+        command.args(["--whole-archive", "--export-all"]);
+    }
+
+    // Searched before the sysroot, e.g. for a libc++ installed apart from libc
+    command.args(extra_lib_dir_args(state));
+
+    if module_kind.is_executable() {
+        let mut lib_arg = OsString::new();
+        lib_arg.push("-L");
+        lib_arg.push(&sysroot_lib_path);
+        command.arg(lib_arg);
+
+        let mut lib_arg = OsString::new();
+        lib_arg.push("-L");
+        lib_arg.push(&sysroot_lib_wasm32_path);
+        command.arg(lib_arg);
+    }
+
+    if module_kind.is_executable() && state.args.links_default_libs() {
+        // Hack: we're linking libclang_rt into libc, so no need to link that here
+        command.args([
+            "-lwasi-emulated-mman",
+            "-lc",
+            "-lresolv",
+            "-lrt",
+            "-lm",
+            "-lpthread",
+            "-lutil",
+        ]);
+
+        if links_cxx_stdlib(state) {
+            command.args(["-lc++", "-lc++abi"]);
+        }
+
+        // Side modules get the runtime from the main module
+        if let Some(sanitizer) = state.user_settings.sanitizer {
+            command.arg(format!("-l{}", sanitizer.runtime_library()));
+        }
+
+        // The profile runtime writes default.profraw (or $LLVM_PROFILE_FILE)
+        // when the module exits. Hosts that stop the module some other way can
+        // call __llvm_profile_write_file themselves. Either way, turning the
+        // raw profile into a report or a PGO_USE profile happens on the host:
+        //   llvm-profdata merge -sparse default.profraw -o default.profdata
+        //   llvm-cov report module.wasm -instr-profile=default.profdata
+        if state.user_settings.uses_profile_runtime() {
+            command.args([
+                "-lclang_rt.profile",
+                "--undefined=__llvm_profile_runtime",
+                "--export-if-defined=__llvm_profile_write_file",
+                "--export-if-defined=__llvm_profile_set_filename",
+            ]);
+        }
+    }
+
+    if matches!(module_kind, ModuleKind::DynamicMain) {
+        command.args(["--no-whole-archive"]);
+    }
+
+    if uses_pic(state) {
+        command.args([
+            "--experimental-pic",
+            "--export-if-defined=__wasm_apply_data_relocs",
+        ]);
+    }
+
+    // Overrides the default for the module kind below
+    if let Some(policy) = state.user_settings.import_undefined {
+        command.arg(policy.linker_flag());
+    }
+
+    if module_kind != ModuleKind::SharedLibrary && state.user_settings.soname.is_some() {
+        print_warning("SONAME only applies to shared libraries and is ignored");
+    }
+
+    match module_kind {
+        ModuleKind::StaticMain => {
+            // TODO: make configurable
+            command.args(["-z", "stack-size=8388608"]);
+        }
+
+        ModuleKind::DynamicMain => {
+            command.args(["-pie", "-lcommon-tag-stubs"]);
+        }
+
+        ModuleKind::SharedLibrary => {
+            command.args(["-shared", "--no-entry"]);
+            if state.user_settings.import_undefined.is_none() {
+                command.arg(UnresolvedSymbols::ImportDynamic.linker_flag());
+            }
+            // Recorded in the module's dylink.0 section; modules linked against
+            // this library then need it under this name rather than its file name
+            if let Some(soname) = &state.user_settings.soname {
+                command.arg(format!("--soname={soname}"));
+            }
+        }
+
+        ModuleKind::ObjectFile
+        | ModuleKind::Archive
+        | ModuleKind::Relocatable
+        | ModuleKind::Auto => {
+            panic!("Internal error: {module_kind:?} can't be linked")
+        }
+    }
+
+    command.args(entry_arg(state));
+
+    command.args(&state.args.linker_inputs);
+    command.args(startfile(state));
+
+    command.arg("-o");
+    command.arg(output_path(state));
+
+    command
+}
+
+/// Whether the link does ThinLTO: the last LTO flag is -flto=thin, or there's
+/// none and LTO_CACHE made us compile the inputs with it. Objects built
+/// elsewhere count only if -flto=thin is given for the link too, since full
+/// LTO and ThinLTO bitcode look the same from the outside.
+fn uses_thin_lto(state: &State) -> bool {
+    let lto_arg = state
+        .args
+        .compiler_args
+        .iter()
+        .rfind(|arg| arg.starts_with("-flto") || arg.starts_with("-fno-lto"));
+    match lto_arg {
+        Some(arg) => arg == "-flto=thin",
+        None => {
+            state.user_settings.lto_cache.is_some()
+                && !state.user_settings.no_default_flags
+                && !state.args.compiler_inputs.is_empty()
+        }
+    }
+}
+
+/// The link for NO_DEFAULT_FLAGS, which is about what clang's own driver does:
+/// the user's flags, -shared or -pie for the module kind, the inputs, and for
+/// executables the sysroot's library paths, -lc (plus -lc++ -lc++abi for C++)
+/// and the startfile. Nothing else is added, not even the memory, feature or
+/// export flags that WASIX modules normally need.
+fn minimal_linker_command(state: &State) -> Command {
+    let sysroot_lib_path = state.user_settings.sysroot_location().join("lib");
+    let sysroot_lib_wasm32_path = sysroot_lib_path.join(&state.user_settings.target);
+    let module_kind = state.user_settings.module_kind();
+
+    let mut command = Command::new(state.user_settings.get_tool_path("wasm-ld"));
+    command.args(&state.args.linker_args);
+    command.args(&state.user_settings.extra_linker_flags);
+
+    match module_kind {
+        ModuleKind::SharedLibrary => {
+            command.arg("-shared");
+        }
+        ModuleKind::DynamicMain => {
+            command.arg("-pie");
+        }
+        _ => (),
+    }
+
+    command.args(entry_arg(state));
+    if let Some(policy) = state.user_settings.import_undefined {
+        command.arg(policy.linker_flag());
+    }
+
+    command.args(&state.args.linker_inputs);
+
+    command.args(extra_lib_dir_args(state));
+
+    if module_kind.is_executable() {
+        for path in [&sysroot_lib_path, &sysroot_lib_wasm32_path] {
+            let mut lib_arg = OsString::from("-L");
+            lib_arg.push(path);
+            command.arg(lib_arg);
+        }
+        if state.args.links_default_libs() {
+            command.arg("-lc");
+            if links_cxx_stdlib(state) {
+                command.args(["-lc++", "-lc++abi"]);
+            }
+        }
+    }
+
+    command.args(startfile(state));
+
+    command.arg("-o");
+    command.arg(output_path(state));
+
+    command
+}
+
+fn extra_lib_dir_args(state: &State) -> Vec<OsString> {
+    state
+        .user_settings
+        .extra_lib_dirs
+        .iter()
+        .map(|dir| {
+            let mut lib_arg = OsString::from("-L");
+            lib_arg.push(dir);
+            lib_arg
+        })
+        .collect()
+}
+
+/// ENTRY replaces the `_start` from crt1.o as the entry point; with NO_CRT1
+/// the startup code is then entirely the user's.
+fn entry_arg(state: &State) -> Option<String> {
+    let entry = state.user_settings.entry.as_ref()?;
+    Some(format!("--entry={entry}"))
+}
+
+/// crt1.o for executables and scrt1.o for shared libraries, unless replaced
+/// through CRT1 or left out.
+fn startfile(state: &State) -> Option<PathBuf> {
+    if !state.args.links_startfiles() || state.user_settings.no_crt1 {
+        return None;
+    }
+
+    if let Some(crt1) = &state.user_settings.crt1 {
+        return Some(crt1.clone());
+    }
+
+    let sysroot_lib_wasm32_path = state
+        .user_settings
+        .sysroot_location()
+        .join("lib")
+        .join(&state.user_settings.target);
+    Some(if state.user_settings.module_kind().is_executable() {
+        sysroot_lib_wasm32_path.join("crt1.o")
+    } else {
+        sysroot_lib_wasm32_path.join("scrt1.o")
+    })
+}
+
+/// C++ code that doesn't use the standard library (e.g. freestanding code
+/// built with -nostdlib++) may not be able to link against it at all.
+fn links_cxx_stdlib(state: &State) -> bool {
+    state.cxx
+        && state.user_settings.cxx_stdlib
+        && state.args.links_default_libs()
+        && !state.args.has_arg("-nostdlib++")
+        && !state.args.has_arg("-nostdinc++")
+}
+
+fn emits_relocs(state: &State) -> bool {
+    state.user_settings.emit_relocs
+        || linker_flags(&state.args.linker_args).any(|arg| arg == "--emit-relocs")
+}
+
+/// Whether KEEP_NAMES applies. wasm-ld writes the name section unless told to
+/// strip, and stripping on request still wins. The section costs a few bytes
+/// per function, usually well under 10% of the module for C, but more for C++
+/// with its long mangled names.
+fn keeps_names(state: &State) -> bool {
+    if !state.user_settings.keep_names {
+        return false;
+    }
+
+    let strip_flag = linker_flags(&state.args.linker_args)
+        .chain(linker_flags(&state.user_settings.extra_linker_flags))
+        .find(|arg| ["--strip-all", "-s", "--strip-debug", "-S"].contains(&arg.as_str()));
+    if let Some(flag) = strip_flag {
+        print_warning(&format!(
+            "KEEP_NAMES is ignored since {flag} strips the name section"
+        ));
+        return false;
+    }
+    true
+}
+
+fn run_wasm_opt(state: &mut State) -> Result<()> {
+    let _span = tracing::info_span!("wasm-opt").entered();
+
+    let Some(command) = wasm_opt_command(state) else {
+        tracing::info!("Skipping wasm-opt as no passes were specified or needed");
+        return Ok(());
+    };
+
+    // Every wasm-opt pass, even ones that leave the code unchanged, re-encodes
+    // the code section and drops the reloc.* sections, so none of them are
+    // reloc-safe. The only way to keep relocations intact is to not run it.
+    if emits_relocs(state) {
+        print_warning(
+            "Skipping wasm-opt, so the module is not optimized beyond what wasm-ld \
+            does, since no wasm-opt pass preserves the relocations requested with \
+            --emit-relocs or EMIT_RELOCS",
+        );
+        return Ok(());
+    }
+
+    if state.build_settings.debug_level.emits_dwarf()
+        && matches!(
+            wasm_opt_level(state),
+            OptLevel::O2 | OptLevel::O3 | OptLevel::O4 | OptLevel::Os | OptLevel::Oz
+        )
+    {
+        print_warning(
+            "wasm-opt may drop some DWARF debug info when optimizing at -O2 or above; \
+            use -O1 or lower for a fully debuggable module",
+        );
+    }
+
+    run_step(state, BuildStage::Opt, command)
+}
+
+/// Disassemblers EMIT_WAT can use, in order of preference.
+const WAT_DISASSEMBLERS: &[&str] = &["wasm-dis", "wasm2wat"];
+
+/// Writes a .wat next to the final module. The module itself is already built
+/// at this point, so failures are only warned about.
+fn emit_wat(state: &mut State) {
+    let Some(disassembler) = WAT_DISASSEMBLERS
+        .iter()
+        .find_map(|program| find_in_path(OsStr::new(program)))
+    else {
+        print_warning(
+            "EMIT_WAT needs wasm-dis (from binaryen) or wasm2wat (from wabt) on PATH; \
+            no .wat file was written",
+        );
+        return;
+    };
+
+    let command = wat_command(state, &disassembler);
+    let wat_path = output_path(state).with_extension("wat");
+    if let Err(err) = run_step(state, BuildStage::Opt, command) {
+        print_warning(&format!("Failed to write {wat_path:?}: {err}"));
+    }
+}
+
+fn wat_command(state: &State, disassembler: &Path) -> Command {
+    let output = output_path(state);
+    let mut command = Command::new(disassembler);
+    command
+        .arg(output)
+        .arg("-o")
+        .arg(output.with_extension("wat"));
+    command
+}
+
+/// Wraps the final module into a component in place, with the adapter turning
+/// its WASI imports into component imports. This comes last, since wasm-opt
+/// and the EMIT_WAT disassemblers only understand core modules.
+fn encode_component(state: &mut State) -> Result<()> {
+    let adapter = state
+        .user_settings
+        .adapter
+        .clone()
+        .context("Internal error: COMPONENT=1 without an adapter")?;
+    if !state.build_settings.dry_run() {
+        if !state
+            .user_settings
+            .tool_overrides
+            .contains_key("wasm-tools")
+            && find_in_path(OsStr::new("wasm-tools")).is_none()
+        {
+            bail!(
+                "COMPONENT=1 needs wasm-tools on PATH to encode the component; install it \
+                with `cargo install wasm-tools`"
+            );
+        }
+        if !adapter.is_file() {
+            bail!("The adapter {adapter:?} given in ADAPTER doesn't exist");
+        }
+    }
+
+    let command = component_command(state, &adapter);
+    run_step(state, BuildStage::Component, command)
+}
+
+fn component_command(state: &State, adapter: &Path) -> Command {
+    let output = output_path(state);
+    let mut command = Command::new(state.user_settings.get_external_tool_path("wasm-tools"));
+    command
+        .args(["component", "new"])
+        .arg(output)
+        .arg("--adapt")
+        .arg(adapter)
+        .arg("-o")
+        .arg(output);
+    command
+}
+
+/// Runs a build step, recording it for the repro script first. Nothing is run
+/// when we're only printing commands.
+fn run_step(state: &mut State, stage: BuildStage, mut command: Command) -> Result<()> {
+    let line = command_to_shell_line(&command, Some(&state.temp_dir));
+
+    if state.build_settings.print_commands {
+        // clang -### prints to stderr too
+        eprintln!("{}", command_to_clang_line(&command));
+    }
+    if state.build_settings.dry_run() {
+        state.script_lines.push((stage, line));
+        return Ok(());
+    }
+
+    // Like gcc -v, show each command before running it
+    if state.user_settings.verbose {
+        eprintln!(" {}", command_to_shell_line(&command, None));
+    }
+    state.script_lines.push((stage, line));
+
+    let start = std::time::Instant::now();
+    let result = if state.user_settings.json_diagnostics {
+        diagnostics::run_command_with_json_diagnostics(
+            command,
+            &state.user_settings,
+            state.output_to_stdout,
+        )
+    } else {
+        if state.output_to_stdout {
+            command.stdout(std::io::stderr());
+        }
+        run_command(command, &state.user_settings)
+    };
+    state.stage_times.push((stage, start.elapsed()));
+    result
+}
+
+fn effective_command_lines(state: &State, stage: BuildStage) -> Vec<&str> {
+    state
+        .script_lines
+        .iter()
+        .filter(|(line_stage, _)| *line_stage == stage)
+        .map(|(_, line)| line.as_str())
+        .collect()
+}
+
+/// Renders a command as a shell line with the program resolved to an absolute
+/// path. For scripts, paths under our `temp_dir` are rewritten relative to the
+/// script's own `$tmp`, since ours is deleted once we exit; without one, as for
+/// VERBOSE, they're left as they are.
+fn command_to_shell_line(command: &Command, temp_dir: Option<&Path>) -> String {
+    let quote_arg = |arg: &OsStr| {
+        let arg = Path::new(arg);
+        match temp_dir.and_then(|temp_dir| arg.strip_prefix(temp_dir).ok()) {
+            Some(rest) => format!("\"$tmp\"/{}", shell_quote(rest.as_os_str())),
+            None => shell_quote(arg.as_os_str()),
+        }
+    };
+
+    let envs = command.get_envs().filter_map(|(name, value)| {
+        value.map(|value| format!("{}={}", name.to_string_lossy(), shell_quote(value)))
+    });
+
+    let program = resolve_program(command.get_program());
+    envs.chain(std::iter::once(shell_quote(program.as_os_str())))
+        .chain(command.get_args().map(quote_arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Formats a command like clang -### does: every argument double quoted, after
+/// a space, with `"`, `\` and `$` escaped.
+fn command_to_clang_line(command: &Command) -> String {
+    let quote = |arg: &OsStr| {
+        let mut quoted = String::from(" \"");
+        for ch in arg.to_string_lossy().chars() {
+            if matches!(ch, '"' | '\\' | '$') {
+                quoted.push('\\');
+            }
+            quoted.push(ch);
+        }
+        quoted.push('"');
+        quoted
+    };
+
+    let program = resolve_program(command.get_program());
+    std::iter::once(quote(program.as_os_str()))
+        .chain(command.get_args().map(quote))
+        .collect()
+}
+
+fn shell_quote(arg: &OsStr) -> String {
+    let arg = arg.to_string_lossy();
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "-_=+,./:@%".contains(ch))
+    {
+        arg.into_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+fn resolve_program(program: &OsStr) -> PathBuf {
+    let program_path = Path::new(program);
+    if program_path.components().count() > 1 {
+        return std::path::absolute(program_path).unwrap_or_else(|_| program_path.to_owned());
+    }
+
+    find_in_path(program).unwrap_or_else(|| program_path.to_owned())
+}
+
+fn find_in_path(program: &OsStr) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+fn repro_script_contents(script_lines: &[(BuildStage, String)]) -> Result<String> {
+    let cwd = std::env::current_dir().context("Failed to get current directory")?;
+
+    let mut script = String::from(
+        "#!/bin/sh\n\
+        # Generated by wasixcc; reproduces the build without going through wasixcc.\n\
+        set -e\n",
+    );
+    script.push_str(&format!("cd {}\n", shell_quote(cwd.as_os_str())));
+    script.push_str("tmp=$(mktemp -d)\n");
+    script.push_str("trap 'rm -rf \"$tmp\"' EXIT\n\n");
+    for (_, line) in script_lines {
+        script.push_str(line);
+        script.push('\n');
+    }
+    Ok(script)
+}
+
+fn write_repro_script(state: &State) -> Result<()> {
+    let Some(path) = &state.user_settings.repro_script else {
+        return Ok(());
+    };
+
+    let script = repro_script_contents(&state.script_lines)?;
+    std::fs::write(path, script)
+        .with_context(|| format!("Failed to write repro script to {path:?}"))?;
+
+    #[cfg(unix)]
+    {
+        let permissions = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+        std::fs::set_permissions(path, permissions)
+            .with_context(|| format!("Failed to set permissions for {path:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// Keeps a record of the exact commands next to the saved temps, as a script
+/// that also reruns them.
+fn write_saved_commands(state: &State) -> Result<()> {
+    if state.user_settings.save_temps.is_none() || state.build_settings.dry_run() {
+        return Ok(());
+    }
+
+    let path = state.temp_dir.join("commands.sh");
+    let script = repro_script_contents(&state.script_lines)?;
+    std::fs::write(&path, script).with_context(|| format!("Failed to write {path:?}"))
+}
+
+/// WASM_OPT_LEVEL lets wasm-opt optimize differently from clang, e.g. -O2 for
+/// clang and -Oz for wasm-opt.
+fn wasm_opt_level(state: &State) -> OptLevel {
+    state
+        .user_settings
+        .wasm_opt_level
+        .unwrap_or(state.build_settings.opt_level)
+}
+
+fn wasm_opt_command(state: &State) -> Option<Command> {
+    let mut command = Command::new("wasm-opt");
+
+    if state.user_settings.exceptions_mode.uses_wasm_eh() {
+        command.arg("--experimental-new-eh");
+    }
+
+    match wasm_opt_level(state) {
+        // -O0 does nothing, no need to specify it
+        OptLevel::O0 => (),
+        OptLevel::O1 => {
+            command.arg("-O1");
+        }
+        OptLevel::O2 => {
+            command.arg("-O2");
+        }
+        OptLevel::O3 => {
+            command.arg("-O3");
+        }
+        OptLevel::O4 => {
+            command.arg("-O4");
+        }
+        OptLevel::Os => {
+            command.arg("-Os");
+        }
+        OptLevel::Oz => {
+            command.arg("-Oz");
+        }
+    }
+
+    command.args(&state.user_settings.wasm_opt_flags);
+
+    // No passes were specified or needed
+    command.get_args().next()?;
+
+    // -g makes wasm-opt keep the DWARF sections and restricts it to passes
+    // that know how to update them, so source-level debugging keeps working.
+    // Without DWARF, it still keeps the name section, which is all KEEP_NAMES
+    // needs for readable stack traces.
+    if state.build_settings.debug_level.emits_dwarf() || keeps_names(state) {
+        command.arg("-g");
+    }
+
+    let output_path = output_path(state);
+    command.arg(output_path);
+    command.arg("-o");
+    command.arg(output_path);
+
+    Some(command)
+}
+
+fn prepare_compiler_args(
+    args: Vec<String>,
+    user_settings: &mut UserSettings,
+) -> Result<(PreparedArgs, BuildSettings)> {
+    let mut result = PreparedArgs {
+        compiler_args: Vec::new(),
+        linker_args: Vec::new(),
+        compiler_inputs: Vec::new(),
+        compiler_input_languages: Vec::new(),
+        compiler_input_arg_counts: Vec::new(),
+        linker_inputs: Vec::new(),
+        output: None,
+    };
+    let mut build_settings = BuildSettings {
+        opt_level: OptLevel::O0,
+        debug_level: DebugLevel::G0,
+        use_wasm_opt: true,
+        print_stage: None,
+        print_commands: false,
+    };
+
+    let mut extra_flags = vec![];
+    std::mem::swap(&mut extra_flags, &mut user_settings.extra_compiler_flags);
+
+    // MODULE_KIND=auto still gives way to -c, -shared, -o foo.a etc., so it's
+    // treated as unset until the flags are through
+    let auto_module_kind = user_settings.module_kind == Some(ModuleKind::Auto);
+    if auto_module_kind {
+        user_settings.module_kind = None;
+    }
+
+    // Before -o is seen, which would otherwise make a .o output an ObjectFile
+    if user_settings.module_kind.is_none() && args.iter().any(|arg| is_relocatable_arg(arg)) {
+        user_settings.module_kind = Some(ModuleKind::Relocatable);
+    }
+    let deduce_module_kind = user_settings.module_kind.is_none();
+
+    // Since we used to do CC="clang --flag1 --flag2", it seems putting the extra flags
+    // first has worked for us, so we keep that behavior. It also means flags on the
+    // command line come last and win, as they would with clang (e.g. a -Wall given
+    // on the command line overrides a -Wno-all from COMPILER_FLAGS, and -O3 wins
+    // over -O1).
+    let mut iter = extra_flags.into_iter().chain(args);
+
+    let mut current_language = None;
+    let mut save_temps = false;
+
+    while let Some(arg) = iter.next() {
+        if arg == "--save-temps" || arg == "-save-temps" {
+            // The directory is named after the output, which may come later
+            save_temps = true;
+        } else if let Some(arg) = arg.strip_prefix("-Wl,") {
+            // Every comma separates arguments, as in -Wl,--wrap=malloc,--wrap=free
+            result
+                .linker_args
+                .extend(arg.split(',').map(|arg| arg.to_owned()));
+        } else if arg.starts_with("-Wp,-MD,") || arg.starts_with("-Wp,-MMD,") {
+            // clang's driver turns these into -MD/-MMD -MF <file> itself, which
+            // splitting them up would bypass
+            result.compiler_args.push(arg);
+        } else if let Some((forward, options)) = arg
+            .strip_prefix("-Wp,")
+            .map(|options| ("-Xpreprocessor", options))
+            .or_else(|| {
+                arg.strip_prefix("-Wa,")
+                    .map(|options| ("-Xassembler", options))
+            })
+        {
+            // As with GCC, -Wp,-D,FOO hands the preprocessor -D and FOO as
+            // separate arguments
+            for option in options.split(',') {
+                result.compiler_args.push(forward.to_owned());
+                result.compiler_args.push(option.to_owned());
+            }
+        } else if arg == "-Xlinker" {
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after -Xlinker");
+            };
+            // A forwarded option that takes a value gets it from the next
+            // -Xlinker, as in -Xlinker -mllvm -Xlinker -foo
+            if WASM_LD_FLAGS_WITH_ARGS.contains(next_arg.as_str()) {
+                let value = match iter.next() {
+                    Some(xlinker) if xlinker == "-Xlinker" => iter.next(),
+                    _ => None,
+                };
+                let Some(value) = value else {
+                    bail!("Expected -Xlinker <value> after -Xlinker {next_arg}");
+                };
+                result.linker_args.push(next_arg);
+                result.linker_args.push(value);
+            } else {
+                result.linker_args.push(next_arg);
+            }
+        } else if arg == "-z" {
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after -z");
+            };
+            result.linker_args.push("-z".to_owned());
+            result.linker_args.push(next_arg);
+        } else if arg == "-o" {
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after -o");
+            };
+            let output = PathBuf::from(next_arg);
+            if user_settings.module_kind.is_none() {
+                if let Some(OutputKind::Module(module_kind)) = deduce_output_kind(&output) {
+                    user_settings.module_kind = Some(module_kind);
+                }
+            }
+            result.output = Some(output);
+        } else if let Some(sysroot) = arg.strip_prefix("--sysroot") {
+            // Taken over as our sysroot, so the compile and link steps agree on it
+            let sysroot = match sysroot.strip_prefix('=') {
+                Some(sysroot) => sysroot.to_owned(),
+                None if sysroot.is_empty() => {
+                    let Some(next_arg) = iter.next() else {
+                        bail!("Expected argument after --sysroot");
+                    };
+                    next_arg
+                }
+                None => bail!("Unknown argument: {arg}"),
+            };
+            let sysroot = PathBuf::from(sysroot);
+            if let Some(previous) = &user_settings.sysroot_location {
+                if *previous != sysroot {
+                    print_warning(&format!(
+                        "--sysroot {sysroot:?} overrides the sysroot {previous:?} from SYSROOT"
+                    ));
+                }
+            }
+            user_settings.sysroot_location = Some(sysroot);
+        } else if let Some(isysroot) = arg.strip_prefix("-isysroot") {
+            let isysroot = if isysroot.is_empty() {
+                let Some(next_arg) = iter.next() else {
+                    bail!("Expected argument after -isysroot");
+                };
+                next_arg
+            } else {
+                isysroot.to_owned()
+            };
+            print_warning(&format!(
+                "-isysroot {isysroot} makes clang look for headers there instead of in the \
+                sysroot, but libraries are still linked from the sysroot; use --sysroot or \
+                SYSROOT to change both"
+            ));
+            result.compiler_args.push("-isysroot".to_owned());
+            result.compiler_args.push(isysroot);
+        } else if arg == "-install_name" {
+            // The Darwin spelling of a shared library's name
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after -install_name");
+            };
+            // Only the file name is kept, since dylink.0 has no notion of an
+            // install path like @rpath/libfoo.dylib
+            let soname = Path::new(&next_arg)
+                .file_name()
+                .and_then(|name| name.to_str())
+                .with_context(|| format!("Invalid value {next_arg} for -install_name"))?
+                .to_owned();
+            if let Some(previous) = &user_settings.soname {
+                if *previous != soname {
+                    print_warning(&format!(
+                        "-install_name {next_arg} overrides the SONAME {previous}"
+                    ));
+                }
+            }
+            user_settings.soname = Some(soname);
+        } else if arg == "-compatibility_version" || arg == "-current_version" {
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after {arg}");
+            };
+            print_warning(&format!(
+                "{arg} {next_arg} is ignored, since WebAssembly modules have no library versions"
+            ));
+        } else if let Some(stage) = arg.strip_prefix("--print-effective-command") {
+            let stage = match stage.strip_prefix('=') {
+                Some(stage) => stage.to_owned(),
+                None if stage.is_empty() => {
+                    let Some(next_arg) = iter.next() else {
+                        bail!("Expected argument after --print-effective-command");
+                    };
+                    next_arg
+                }
+                None => bail!("Unknown argument: {arg}"),
+            };
+            build_settings.print_stage = Some(match stage.as_str() {
+                "compile" => BuildStage::Compile,
+                "link" => BuildStage::Link,
+                "opt" => BuildStage::Opt,
+                "component" => BuildStage::Component,
+                _ => {
+                    bail!("Unknown stage {stage}; expected one of compile, link, opt or component")
+                }
+            });
+        } else if let Some(language) = arg.strip_prefix("-x") {
+            let language = if language.is_empty() {
+                let Some(next_arg) = iter.next() else {
+                    bail!("Expected argument after -x");
+                };
+                next_arg
+            } else {
+                language.to_owned()
+            };
+            // Like clang, -x applies to the inputs following it, and -x none
+            // goes back to detecting the language from the extension
+            current_language = (language != "none").then_some(language);
+        } else if arg.starts_with('-') {
+            if update_build_settings_from_arg(&arg, &mut build_settings, user_settings)? {
+                let has_next_arg = CLANG_FLAGS_WITH_ARGS.contains(&arg[..]);
+                result.compiler_args.push(arg);
+                if has_next_arg {
+                    if let Some(next_arg) = iter.next() {
+                        result.compiler_args.push(next_arg);
+                    }
+                }
+            }
+        } else {
+            // Assume it's an input file
+            let input = PathBuf::from(&arg);
+            match input.extension().and_then(|ext| ext.to_str()) {
+                Some("a") | Some("o") | Some("obj") if current_language.is_none() => {
+                    result.linker_inputs.push(PathBuf::from(arg));
+                }
+                _ if is_objc_input(&input, current_language.as_deref()) => {
+                    bail!(
+                        "{input:?}: Objective-C is not supported for WASIX targets, since \
+                        there's no Objective-C runtime to link against"
+                    );
+                }
+                _ => {
+                    result.compiler_inputs.push(PathBuf::from(arg));
+                    result
+                        .compiler_input_languages
+                        .push(current_language.clone());
+                    result
+                        .compiler_input_arg_counts
+                        .push(result.compiler_args.len());
+                }
+            }
+        }
+    }
+
+    if deduce_module_kind {
+        check_module_kind_signals(&result)?;
+    }
+
+    if user_settings.module_kind.is_none() {
+        for arg in &result.compiler_args {
+            if arg == "-shared" {
+                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
+                break;
+            } else if arg == "-c" || arg == "-S" || arg == "-E" {
+                user_settings.module_kind = Some(ModuleKind::ObjectFile);
+                break;
+            }
+        }
+    }
+
+    take_version_script_arg(&mut result.linker_args, user_settings);
+
+    if user_settings.module_kind.is_none() {
+        for arg in linker_flags(&result.linker_args) {
+            if arg == "-shared" {
+                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
+                break;
+            } else if arg == "-pie" {
+                user_settings.module_kind = Some(ModuleKind::DynamicMain);
+                break;
+            }
+        }
+    }
+
+    // Headers get precompiled to a .pch/.gch by clang; there's nothing to link
+    if user_settings.module_kind.is_none()
+        && result.linker_inputs.is_empty()
+        && !result.compiler_inputs.is_empty()
+        && result
+            .compiler_inputs
+            .iter()
+            .zip(&result.compiler_input_languages)
+            .all(|(input, language)| is_header_input(input, language.as_deref()))
+    {
+        user_settings.module_kind = Some(ModuleKind::ObjectFile);
+    }
+
+    // Only now, so -c -o foo.a still writes an object as it would with clang
+    if user_settings.module_kind.is_none()
+        && result.output.as_deref().and_then(deduce_output_kind) == Some(OutputKind::Archive)
+    {
+        user_settings.module_kind = Some(ModuleKind::Archive);
+    }
+
+    if auto_module_kind && user_settings.module_kind.is_none() {
+        user_settings.module_kind = Some(ModuleKind::Auto);
+    }
+
+    if save_temps && user_settings.save_temps.is_none() {
+        user_settings.save_temps = Some(default_save_temps_dir(&result));
+    }
+
+    if user_settings.module_kind().is_binary() {
+        check_output_not_archive(result.output.as_deref())?;
+    }
+
+    Ok((result, build_settings))
+}
+
+/// Next to -o, or named after the first input without one (as with `-c a.c`),
+/// falling back to a.out.
+fn default_save_temps_dir(args: &PreparedArgs) -> PathBuf {
+    let output = args
+        .output
+        .as_deref()
+        .or_else(|| {
+            args.compiler_inputs
+                .first()
+                .map(|input| Path::new(input.file_name().unwrap_or(input.as_os_str())))
+        })
+        .unwrap_or(Path::new("a.out"));
+    let mut dir = output.as_os_str().to_owned();
+    dir.push(SAVE_TEMPS_DIR_SUFFIX);
+    PathBuf::from(dir)
+}
+
+fn prepare_linker_args(
+    args: Vec<String>,
+    user_settings: &mut UserSettings,
+) -> Result<PreparedArgs> {
+    let mut result = PreparedArgs {
+        compiler_args: Vec::new(),
+        linker_args: Vec::new(),
+        compiler_inputs: Vec::new(),
+        compiler_input_languages: Vec::new(),
+        compiler_input_arg_counts: Vec::new(),
+        linker_inputs: Vec::new(),
+        output: None,
+    };
+
+    let auto_module_kind = user_settings.module_kind == Some(ModuleKind::Auto);
+    if auto_module_kind {
+        user_settings.module_kind = None;
+    }
+
+    if user_settings.module_kind.is_none() && args.iter().any(|arg| is_relocatable_arg(arg)) {
+        user_settings.module_kind = Some(ModuleKind::Relocatable);
+    }
+    let deduce_module_kind = user_settings.module_kind.is_none();
+
+    let mut iter = args.into_iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "-o" {
+            let Some(next_arg) = iter.next() else {
+                bail!("Expected argument after -o");
+            };
+            let output = PathBuf::from(next_arg);
+            if user_settings.module_kind.is_none() {
+                if let Some(OutputKind::Module(module_kind)) = deduce_output_kind(&output) {
+                    user_settings.module_kind = Some(module_kind);
+                }
+            }
+            result.output = Some(output);
+        } else if arg.starts_with('-') {
+            let has_next_arg = WASM_LD_FLAGS_WITH_ARGS.contains(&arg[..]);
+            result.linker_args.push(arg);
+            if has_next_arg {
+                if let Some(next_arg) = iter.next() {
+                    result.linker_args.push(next_arg);
+                }
+            }
+        } else {
+            // Assume it's an input file
+            result.linker_inputs.push(PathBuf::from(arg));
+        }
+    }
+
+    if deduce_module_kind {
+        check_module_kind_signals(&result)?;
+    }
+
+    take_version_script_arg(&mut result.linker_args, user_settings);
+
+    if user_settings.module_kind.is_none() {
+        for arg in linker_flags(&result.linker_args) {
+            if arg == "-shared" {
+                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
+                break;
+            } else if arg == "-pie" {
+                user_settings.module_kind = Some(ModuleKind::DynamicMain);
+                break;
+            }
+        }
+    }
+
+    if auto_module_kind && user_settings.module_kind.is_none() {
+        user_settings.module_kind = Some(ModuleKind::Auto);
+    }
+
+    check_output_not_archive(result.output.as_deref())?;
+
+    Ok(result)
+}
+
+// The returned bool indicated whether the argument should be kept in the
+// compiler args.
+fn update_build_settings_from_arg(
+    arg: &str,
+    build_settings: &mut BuildSettings,
+    user_settings: &mut UserSettings,
+) -> Result<bool> {
+    if let Some(opt_level) = arg.strip_prefix("-O") {
+        build_settings.opt_level = match OptLevel::from_suffix(opt_level) {
+            Some(opt_level) => opt_level,
+            None => bail!("Invalid argument: -O{opt_level}"),
+        };
+        Ok(true)
+    } else if let Some(debug_level) = arg.strip_prefix("-g") {
+        build_settings.debug_level = match debug_level {
+            "" => DebugLevel::G2,
+            "0" => DebugLevel::G0,
+            "1" => DebugLevel::G1,
+            "2" => DebugLevel::G2,
+            "3" => DebugLevel::G3,
+            x => bail!("Invalid argument: -g{x}"),
+        };
+        Ok(true)
+    } else if arg == "-fwasm-exceptions" {
+        user_settings.exceptions_mode = ExceptionsMode::Wasm;
+        Ok(false)
+    } else if arg == "-fno-wasm-exceptions" {
+        // setjmp/longjmp support isn't affected, and still needs the wasm
+        // exception handling instructions
+        if user_settings.exceptions_mode == ExceptionsMode::Wasm {
+            user_settings.exceptions_mode = if user_settings.setjmp {
+                ExceptionsMode::Sjlj
+            } else {
+                ExceptionsMode::None
+            };
+        }
+        Ok(true)
+    } else if arg == "--no-wasm-opt" {
+        build_settings.use_wasm_opt = false;
+        Ok(false)
+    } else if arg == "-###" {
+        build_settings.print_commands = true;
+        Ok(false)
+    } else if arg == "--print-config" {
+        user_settings.print_config = true;
+        Ok(false)
+    } else if arg == "-r" || arg == "--relocatable" {
+        // Handled when linking, see link_relocatable
+        Ok(false)
+    } else if arg == "-v" {
+        // Also passed on, so clang prints its own details
+        user_settings.verbose = true;
+        Ok(true)
+    } else if arg.starts_with("-march=")
+        || arg
+            .strip_prefix("-mcpu=")
+            .or_else(|| arg.strip_prefix("-mtune="))
+            .is_some_and(|cpu| !WASM_CPUS.contains(cpu))
+    {
+        print_warning(&format!(
+            "Ignoring {arg}: CPU targeting doesn't apply to WASIX; enable wasm \
+            features with flags such as -msimd128 instead"
+        ));
+        Ok(false)
+    } else {
+        Ok(true)
+    }
+}
+
+/// Flags and output names that each imply a kind of module. When they disagree
+/// (e.g. -shared with -pie, or -c with -o foo.wasm) which one wins would only
+/// depend on the order they're checked in, so that's an error instead.
+fn check_module_kind_signals(args: &PreparedArgs) -> Result<()> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Signal {
+        SharedLibrary,
+        DynamicMain,
+        ObjectFile,
+        // A .wasm output, which any kind of linked module can have
+        Linked,
+    }
+
+    let mut signals = vec![];
+    for flag in ["-shared", "-pie", "-c", "-S", "-E"] {
+        if args.compiler_args.iter().any(|arg| arg == flag)
+            || linker_flags(&args.linker_args).any(|arg| arg == flag)
+        {
+            let signal = match flag {
+                "-shared" => Signal::SharedLibrary,
+                "-pie" => Signal::DynamicMain,
+                _ => Signal::ObjectFile,
+            };
+            signals.push((flag.to_owned(), signal));
+        }
+    }
+    if let Some(output) = &args.output {
+        let signal = match output.extension().and_then(OsStr::to_str) {
+            Some("o") | Some("obj") => Some(Signal::ObjectFile),
+            Some("so") => Some(Signal::SharedLibrary),
+            Some("wasm") => Some(Signal::Linked),
+            _ => None,
+        };
+        if let Some(signal) = signal {
+            signals.push((format!("-o {}", output.display()), signal));
+        }
+    }
+
+    let compatible = |a: Signal, b: Signal| {
+        a == b
+            || matches!(
+                (a, b),
+                (Signal::Linked, Signal::SharedLibrary | Signal::DynamicMain)
+                    | (Signal::SharedLibrary | Signal::DynamicMain, Signal::Linked)
+            )
+    };
+    let describe = |signal: Signal| match signal {
+        Signal::SharedLibrary => "a shared library",
+        Signal::DynamicMain => "a dynamically linked executable",
+        Signal::ObjectFile => "compiling without linking",
+        Signal::Linked => "a linked module",
+    };
+    for (index, (first, first_signal)) in signals.iter().enumerate() {
+        for (second, second_signal) in &signals[index + 1..] {
+            if !compatible(*first_signal, *second_signal) {
+                bail!(
+                    "{first} and {second} contradict each other: {first} means {} but \
+                    {second} means {}; remove one of them, or set MODULE_KIND",
+                    describe(*first_signal),
+                    describe(*second_signal)
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn deduce_output_kind(output: &Path) -> Option<OutputKind> {
+    match output.extension().map(|extension| extension.to_str()) {
+        Some(Some("o")) | Some(Some("obj")) => Some(OutputKind::Module(ModuleKind::ObjectFile)),
+        Some(Some("so")) => Some(OutputKind::Module(ModuleKind::SharedLibrary)),
+        Some(Some("a")) => Some(OutputKind::Archive),
+        Some(Some("wasm")) | None => Some(OutputKind::Binary),
+        _ => None, // Default to static main if no extension matches
+    }
+}
+
+/// Linking into a .a would leave an executable with an archive's name, which
+/// then fails confusingly when used as a library.
+fn check_output_not_archive(output: Option<&Path>) -> Result<()> {
+    if let Some(output) = output {
+        if deduce_output_kind(output) == Some(OutputKind::Archive) {
+            bail!(
+                "Output {output:?} looks like a static archive, which can't be linked \
+                directly; leave out -shared/MODULE_KIND to build an archive of the \
+                inputs instead, or create it with wasixar"
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ExceptionsMode, LlvmLocation, Sanitizer, UserSettings};
+    use std::path::PathBuf;
+
+    pub(crate) fn test_user_settings() -> UserSettings {
+        UserSettings {
+            sysroot_location: Some(PathBuf::from("/sysroot")),
+            llvm_location: LlvmLocation::FromSystem(0),
+            extra_compiler_flags: vec![],
+            extra_linker_flags: vec![],
+            run_wasm_opt: None,
+            wasm_opt_flags: vec![],
+            module_kind: None,
+            exceptions_mode: ExceptionsMode::None,
+            setjmp: false,
+            pic: false,
+            initial_memory: None,
+            abi_profile: AbiProfile::WasixThreads,
+            threads: true,
+            exported_functions: vec![],
+            export_all: false,
+            object_suffix: ".o".to_owned(),
+            thread_stack_size: None,
+            repro_script: None,
+            link_features: None,
+            ar_deterministic: false,
+            linker_map: None,
+            lto_cache: None,
+            lto_cache_policy: None,
+            emit_relocs: false,
+            assertions: None,
+            exports_from_objects: vec![],
+            print_config: false,
+            strict_aliasing: true,
+            minimal_exports: false,
+            analyze: None,
+            gc_sections: false,
+            reproducible: false,
+            verbose: false,
+            emit_wat: false,
+            wasm_opt_level: None,
+            target: "wasm32-wasi".to_owned(),
+            target_features: None,
+            standalone: false,
+            json_diagnostics: false,
+            save_temps: None,
+            sanitizer: None,
+            coverage: false,
+            cxx_stdlib: true,
+            soname: None,
+            pgo_generate: false,
+            pgo_use: None,
+            temp_dir: None,
+            simd: false,
+            timings: false,
+            crt1: None,
+            no_crt1: false,
+            export_table: false,
+            import_table: false,
+            no_default_flags: false,
+            jobs: 1,
+            version_script: None,
+            keep_names: false,
+            extra_lib_dirs: vec![],
+            entry: None,
+            import_undefined: None,
+            component: false,
+            adapter: None,
+            feature_bulk_memory: None,
+            feature_mutable_globals: None,
+            lto_jobs: None,
+            tool_overrides: HashMap::new(),
+        }
+    }
+
+    fn test_build_settings() -> BuildSettings {
+        BuildSettings {
+            opt_level: OptLevel::O0,
+            debug_level: DebugLevel::G0,
+            use_wasm_opt: true,
+            print_stage: None,
+            print_commands: false,
+        }
+    }
+
+    fn test_state(user_settings: UserSettings, build_settings: BuildSettings) -> State {
+        State {
+            user_settings,
+            build_settings,
+            args: PreparedArgs {
+                compiler_args: vec![],
+                linker_args: vec![],
+                compiler_inputs: vec![],
+                compiler_input_languages: vec![],
+                compiler_input_arg_counts: vec![],
+                linker_inputs: vec![PathBuf::from("in.o")],
+                output: Some(PathBuf::from("out.wasm")),
+            },
+            cxx: false,
+            temp_dir: PathBuf::from("."),
+            compiled_with_atomics: None,
+            script_lines: Vec::new(),
+            stage_times: Vec::new(),
+            version_script: None,
+            output_to_stdout: false,
+        }
+    }
+
+    /// Runs the whole build for `args` in --print-effective-command mode, so the
+    /// commands for every stage get recorded without running anything.
+    fn dry_run(mut user_settings: UserSettings, args: &[&str]) -> State {
+        let args = ["--print-effective-command=compile"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        let (args, build_settings) = prepare_compiler_args(args, &mut user_settings).unwrap();
+        let mut state = test_state(user_settings, build_settings);
+        state.cxx = has_cxx_inputs(&args);
+        state.args = args;
+        compile_and_link(&mut state).unwrap();
+        state
+    }
+
+    fn command_args(command: &Command) -> Vec<String> {
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    /// Stands in for clang, writing "object" to its -o argument.
+    #[cfg(unix)]
+    pub(crate) const FAKE_CLANG: &str = "\
+        while [ $# -gt 0 ]; do\n\
+          if [ \"$1\" = -o ]; then echo object > \"$2\"; fi\n\
+          shift\n\
+        done\n";
+
+    /// A directory of shell scripts standing in for the toolchain, for use with
+    /// LlvmLocation::FromPath. The scripts are given without their #! line.
+    #[cfg(unix)]
+    pub(crate) fn fake_toolchain(tools: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::TempDir::new().unwrap();
+        write_fake_tools(dir.path(), tools);
+        dir
+    }
+
+    /// Adds to (or replaces tools in) a directory like fake_toolchain's.
+    #[cfg(unix)]
+    pub(crate) fn write_fake_tools(dir: &Path, tools: &[(&str, &str)]) {
+        use std::os::unix::fs::PermissionsExt;
+        for (name, script) in tools {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{script}")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+    }
+
+    /// Creates `<dir>/sysroot` with the libc.a and crt1.o that check_sysroot
+    /// looks for, and returns its path.
+    #[cfg(unix)]
+    pub(crate) fn fake_sysroot(dir: &Path) -> PathBuf {
+        let sysroot = dir.join("sysroot");
+        let lib_path = sysroot.join("lib").join("wasm32-wasi");
+        std::fs::create_dir_all(&lib_path).unwrap();
+        std::fs::write(lib_path.join("libc.a"), "").unwrap();
+        std::fs::write(lib_path.join("crt1.o"), "").unwrap();
+        sysroot
+    }
+
+    #[test]
+    fn test_deduce_output_kind() {
+        assert_eq!(
+            deduce_output_kind(Path::new("a.o")),
+            Some(OutputKind::Module(ModuleKind::ObjectFile))
+        );
+        assert_eq!(
+            deduce_output_kind(Path::new("a.obj")),
+            Some(OutputKind::Module(ModuleKind::ObjectFile))
+        );
+        assert_eq!(
+            deduce_output_kind(Path::new("liba.so")),
+            Some(OutputKind::Module(ModuleKind::SharedLibrary))
+        );
+        assert_eq!(
+            deduce_output_kind(Path::new("liba.a")),
+            Some(OutputKind::Archive)
+        );
+        assert_eq!(
+            deduce_output_kind(Path::new("a.wasm")),
+            Some(OutputKind::Binary)
+        );
+        assert_eq!(
+            deduce_output_kind(Path::new("out/a")),
+            Some(OutputKind::Binary)
+        );
+        assert_eq!(deduce_output_kind(Path::new("a.unknown")), None);
+    }
+
+    #[test]
+    fn test_output_extension_module_kind() {
+        let module_kind = |args: &[&str]| {
+            let mut us = test_user_settings();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            prepare_compiler_args(args, &mut us).map(|_| us.module_kind)
+        };
+
+        assert_eq!(
+            module_kind(&["a.c", "-o", "a.o"]).unwrap(),
+            Some(ModuleKind::ObjectFile)
+        );
+        assert_eq!(module_kind(&["a.c", "-o", "a.wasm"]).unwrap(), None);
+        assert_eq!(module_kind(&["a.c", "-o", "a"]).unwrap(), None);
+        assert_eq!(
+            module_kind(&["a.c", "-shared", "-o", "liba.wasm"]).unwrap(),
+            Some(ModuleKind::SharedLibrary)
+        );
+        assert_eq!(
+            module_kind(&["-c", "a.c", "-o", "a.a"]).unwrap(),
+            Some(ModuleKind::ObjectFile)
+        );
+        assert_eq!(
+            module_kind(&["a.c", "-o", "liba.a"]).unwrap(),
+            Some(ModuleKind::Archive)
+        );
+        assert!(module_kind(&["a.c", "-shared", "-o", "liba.a"]).is_err());
+    }
+
+    #[test]
+    fn test_module_kind_conflicts() {
+        let error = |args: &[&str]| {
+            let mut us = test_user_settings();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            prepare_compiler_args(args, &mut us)
+                .err()
+                .map(|error| error.to_string())
+        };
+
+        let message = error(&["a.c", "-shared", "-pie"]).unwrap();
+        assert_eq!(
+            message,
+            "-shared and -pie contradict each other: -shared means a shared library but -pie \
+            means a dynamically linked executable; remove one of them, or set MODULE_KIND"
+        );
+        assert!(error(&["a.c", "-shared", "-Wl,-pie"]).is_some());
+        assert!(error(&["-c", "a.c", "-shared"]).is_some());
+        assert!(error(&["-c", "a.c", "-o", "a.wasm"]).is_some());
+        assert!(error(&["-c", "a.c", "-o", "liba.so"]).is_some());
+        assert!(error(&["a.c", "-shared", "-o", "a.o"]).is_some());
+        assert!(error(&["a.c", "-pie", "-o", "liba.so"]).is_some());
+
+        assert!(error(&["-c", "a.c", "-o", "a.o"]).is_none());
+        assert!(error(&["a.c", "-shared", "-o", "liba.so"]).is_none());
+        assert!(error(&["a.c", "-pie", "-o", "a.wasm"]).is_none());
+        // MODULE_KIND decides, so the flags aren't consulted
+        let mut us = test_user_settings();
+        us.module_kind = Some(ModuleKind::SharedLibrary);
+        let args = ["-c", "a.c", "-shared"].map(String::from).to_vec();
+        assert!(prepare_compiler_args(args, &mut us).is_ok());
+
+        let mut us = test_user_settings();
+        let args = ["-shared", "-pie", "a.o"].map(String::from).to_vec();
+        assert!(prepare_linker_args(args, &mut us).is_err());
+    }
+
+    #[test]
+    fn test_update_build_settings_from_arg() {
+        let mut bs = BuildSettings {
+            opt_level: OptLevel::O0,
+            debug_level: DebugLevel::None,
+            use_wasm_opt: true,
+            print_stage: None,
+            print_commands: false,
+        };
+        let mut us = test_user_settings();
+        assert!(update_build_settings_from_arg("-O3", &mut bs, &mut us).unwrap());
+        assert_eq!(bs.opt_level, OptLevel::O3);
+        assert!(update_build_settings_from_arg("-g1", &mut bs, &mut us).unwrap());
+        assert_eq!(bs.debug_level, DebugLevel::G1);
+        assert!(!update_build_settings_from_arg("--no-wasm-opt", &mut bs, &mut us).unwrap());
+        assert!(!update_build_settings_from_arg("-fwasm-exceptions", &mut bs, &mut us).unwrap());
+        assert_eq!(us.exceptions_mode, ExceptionsMode::Wasm);
+        assert!(update_build_settings_from_arg("-fno-wasm-exceptions", &mut bs, &mut us).unwrap());
+        assert_eq!(us.exceptions_mode, ExceptionsMode::None);
+        assert!(update_build_settings_from_arg("-v", &mut bs, &mut us).unwrap());
+        assert!(us.verbose);
+    }
+
+    #[test]
+    fn test_extra_compiler_flags_come_before_user_args() {
+        let mut us = test_user_settings();
+        us.extra_compiler_flags = vec!["-Wno-all".to_string(), "-O1".to_string()];
+        let args = vec!["-Wall".to_string(), "-O3".to_string(), "in.c".to_string()];
+        let (pa, bs) = prepare_compiler_args(args, &mut us).unwrap();
+        assert_eq!(bs.opt_level, OptLevel::O3);
+        assert_eq!(
+            pa.compiler_args,
+            vec![
+                "-Wno-all".to_string(),
+                "-O1".to_string(),
+                "-Wall".to_string(),
+                "-O3".to_string(),
+            ]
+        );
+
+        let mut us = test_user_settings();
+        us.extra_compiler_flags = vec!["-Wno-all".to_string(), "-O1".to_string()];
+        let state = dry_run(us, &["-Wall", "-O3", "in.c"]);
+        let line = &state.script_lines[0].1;
+        let position = |flag: &str| line.find(flag).unwrap();
+        assert!(position(" -Wno-all ") < position(" -Wall "));
+        assert!(position(" -O1 ") < position(" -O3 "));
+    }
+
+    #[test]
+    fn test_prepare_compiler_args_and_build_settings() {
+        let mut us = test_user_settings();
+        let args = vec![
+            "-O2".to_string(),
+            "-g0".to_string(),
+            "-fwasm-exceptions".to_string(),
+            "--no-wasm-opt".to_string(),
+            "-Wl,-foo,bar".to_string(),
+            "-Xlinker".to_string(),
+            "baz".to_string(),
+            "-z".to_string(),
+            "zo".to_string(),
+            "-o".to_string(),
+            "out".to_string(),
+            "in.c".to_string(),
+            "lib.o".to_string(),
+        ];
+        let (pa, bs) = prepare_compiler_args(args, &mut us).unwrap();
+        assert_eq!(bs.opt_level, OptLevel::O2);
+        assert_eq!(bs.debug_level, DebugLevel::G0);
+        assert!(!bs.use_wasm_opt);
+        assert_eq!(us.exceptions_mode, ExceptionsMode::Wasm);
+        assert_eq!(pa.compiler_args, vec!["-O2".to_string(), "-g0".to_string()]);
+        assert_eq!(
+            pa.linker_args,
+            vec![
+                "-foo".to_string(),
+                "bar".to_string(),
+                "baz".to_string(),
+                "-z".to_string(),
+                "zo".to_string()
+            ]
+        );
+        assert_eq!(pa.output, Some(PathBuf::from("out")));
+        assert_eq!(pa.compiler_inputs, vec![PathBuf::from("in.c")]);
+        assert_eq!(pa.linker_inputs, vec![PathBuf::from("lib.o")]);
+    }
+
+    #[test]
+    fn test_prepare_linker_args() {
+        let mut us = test_user_settings();
+        let args = vec![
+            "-o".to_string(),
+            "out.wasm".to_string(),
+            "-shared".to_string(),
+            "-m".to_string(),
+            "module".to_string(),
+            "mod.wasm".to_string(),
+        ];
+        let pa = prepare_linker_args(args, &mut us).unwrap();
+        assert_eq!(pa.output, Some(PathBuf::from("out.wasm")));
+        assert_eq!(
+            pa.linker_args,
+            vec![
+                "-shared".to_string(),
+                "-m".to_string(),
+                "module".to_string()
+            ]
+        );
+        assert_eq!(pa.linker_inputs, vec![PathBuf::from("mod.wasm")]);
+        assert_eq!(us.module_kind, Some(ModuleKind::SharedLibrary));
+    }
+
+    #[test]
+    fn test_wasm_opt_level() {
+        let build_settings = || BuildSettings {
+            opt_level: OptLevel::O2,
+            debug_level: DebugLevel::G0,
+            use_wasm_opt: true,
+            print_stage: None,
+            print_commands: false,
+        };
+        let state = test_state(test_user_settings(), build_settings());
+        let args = command_args(&wasm_opt_command(&state).unwrap());
+        assert_eq!(args, vec!["-O2", "out.wasm", "-o", "out.wasm"]);
+
+        let mut us = test_user_settings();
+        us.wasm_opt_level = Some(OptLevel::Oz);
+        let state = test_state(us, build_settings());
+        let args = command_args(&wasm_opt_command(&state).unwrap());
+        assert_eq!(args, vec!["-Oz", "out.wasm", "-o", "out.wasm"]);
+
+        let mut us = test_user_settings();
+        us.wasm_opt_level = Some(OptLevel::Oz);
+        let state = dry_run(us, &["-O2", "a.c"]);
+        assert!(effective_command_lines(&state, BuildStage::Compile)[0].contains(" -O2 "));
+        assert!(effective_command_lines(&state, BuildStage::Opt)[0].contains(" -Oz "));
+    }
+
+    #[test]
+    fn test_wasm_opt_command_preserves_dwarf() {
+        let state = test_state(
+            test_user_settings(),
+            BuildSettings {
+                opt_level: OptLevel::O1,
+                debug_level: DebugLevel::G2,
+                use_wasm_opt: true,
+                print_stage: None,
+                print_commands: false,
+            },
+        );
+        let args = command_args(&wasm_opt_command(&state).unwrap());
+        assert_eq!(args, vec!["-O1", "-g", "out.wasm", "-o", "out.wasm"]);
+
+        let state = test_state(
+            test_user_settings(),
+            BuildSettings {
+                opt_level: OptLevel::O1,
+                debug_level: DebugLevel::G0,
+                use_wasm_opt: true,
+                print_stage: None,
+                print_commands: false,
+            },
+        );
+        let args = command_args(&wasm_opt_command(&state).unwrap());
+        assert!(!args.contains(&"-g".to_string()));
+
+        let state = test_state(
+            test_user_settings(),
+            BuildSettings {
+                opt_level: OptLevel::O0,
+                debug_level: DebugLevel::G2,
+                use_wasm_opt: true,
+                print_stage: None,
+                print_commands: false,
+            },
+        );
+        assert!(wasm_opt_command(&state).is_none());
+    }
+
+    #[test]
+    fn test_keep_names() {
+        let opt_line = |us: UserSettings, args: &[&str]| {
+            let state = dry_run(us, args);
+            effective_command_lines(&state, BuildStage::Opt)[0].to_owned()
+        };
+
+        assert!(!opt_line(test_user_settings(), &["-O3", "a.c"]).contains(" -g "));
+
+        let mut us = test_user_settings();
+        us.keep_names = true;
+        assert!(opt_line(us, &["-O3", "a.c"]).contains(" -O3 -g "));
+
+        // Asking wasm-ld to strip still wins
+        let mut us = test_user_settings();
+        us.keep_names = true;
+        assert!(!opt_line(us, &["-O3", "a.c", "-Wl,--strip-all"]).contains(" -g "));
+    }
+
+    #[test]
+    fn test_linker_command_initial_memory() {
+        let state = test_state(test_user_settings(), test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(!args.iter().any(|arg| arg.starts_with("--initial-memory")));
+
+        let mut us = test_user_settings();
+        us.initial_memory = Some(16 * 1024 * 1024);
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&"--initial-memory=16777216".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomics_consistent_between_compile_and_link() {
+        let tmp = fake_toolchain(&[("clang", "exit 0")]);
+
+        for threads in [true, false] {
+            let mut us = test_user_settings();
+            us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+            us.threads = threads;
+            let mut state = test_state(us, test_build_settings());
+            state.args.compiler_inputs.push(PathBuf::from("in.c"));
+            state.args.compiler_input_languages.push(None);
+            state.args.compiler_input_arg_counts.push(0);
+            state.temp_dir = tmp.path().to_owned();
+
+            compile_inputs(&mut state).unwrap();
+            assert_eq!(state.compiled_with_atomics, Some(threads));
+
+            let args = command_args(&linker_command(&state));
+            assert_eq!(args.contains(&"--shared-memory".to_string()), threads);
+            assert_eq!(
+                args.contains(&"--extra-features=atomics".to_string()),
+                threads
+            );
+        }
+    }
+
+    #[test]
+    fn test_user_atomics_flag() {
+        let compile = |threads: bool, args: &[&str]| {
+            let mut us = test_user_settings();
+            us.threads = threads;
+            let args = ["-###"]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string())
+                .collect();
+            let (args, build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+            let mut state = test_state(us, build_settings);
+            state.args = args;
+            compile_and_link(&mut state).map(|()| state)
+        };
+
+        let err = compile(false, &["-matomics", "foo.c"]).unwrap_err();
+        assert!(
+            err.to_string().starts_with("-matomics needs shared memory"),
+            "{err}"
+        );
+        compile(false, &["-matomics", "-mno-atomics", "foo.c"]).unwrap();
+
+        let state = compile(true, &["-matomics", "foo.c"]).unwrap();
+        assert_eq!(state.compiled_with_atomics, Some(true));
+    }
+
+    #[test]
+    fn test_abi_profile_flags() {
+        let flags = |profile: AbiProfile| {
+            let mut us = test_user_settings();
+            us.abi_profile = profile;
+            us.threads = profile.threads();
+            let state = dry_run(us, &["a.c"]);
+            let compile_flags = effective_command_lines(&state, BuildStage::Compile)[0]
+                .split(' ')
+                .filter(|arg| arg.starts_with("-m") && *arg != "-mthread-model")
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            let link_flags = effective_command_lines(&state, BuildStage::Link)[0]
+                .split(' ')
+                .filter(|arg| arg.starts_with("--extra-features=") || *arg == "--shared-memory")
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            (compile_flags, link_flags)
+        };
+
+        assert_eq!(flags(AbiProfile::Mvp), (vec![], vec![]));
+        assert_eq!(
+            flags(AbiProfile::Wasix),
+            (
+                vec!["-mbulk-memory".to_string(), "-mmutable-globals".to_string()],
+                vec![
+                    "--extra-features=bulk-memory".to_string(),
+                    "--extra-features=mutable-globals".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            flags(AbiProfile::WasixThreads),
+            (
+                vec![
+                    "-mbulk-memory".to_string(),
+                    "-mmutable-globals".to_string(),
+                    "-matomics".to_string()
+                ],
+                vec![
+                    "--extra-features=atomics".to_string(),
+                    "--extra-features=bulk-memory".to_string(),
+                    "--extra-features=mutable-globals".to_string(),
+                    "--shared-memory".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_feature_toggles() {
+        let flags = |us: UserSettings| {
+            let state = dry_run(us, &["a.c"]);
+            let compile_flags = effective_command_lines(&state, BuildStage::Compile)[0]
+                .split(' ')
+                .filter(|arg| arg.starts_with("-m") && *arg != "-mthread-model")
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            let link_flags = effective_command_lines(&state, BuildStage::Link)[0]
+                .split(' ')
+                .filter(|arg| arg.starts_with("--extra-features=") || *arg == "--shared-memory")
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            (compile_flags, link_flags)
+        };
+
+        let mut us = test_user_settings();
+        us.abi_profile = AbiProfile::Wasix;
+        us.threads = false;
+        us.feature_bulk_memory = Some(false);
+        assert_eq!(
+            flags(us),
+            (
+                vec!["-mmutable-globals".to_string()],
+                vec!["--extra-features=mutable-globals".to_string()]
+            )
+        );
+
+        let mut us = test_user_settings();
+        us.abi_profile = AbiProfile::Mvp;
+        us.threads = false;
+        us.feature_mutable_globals = Some(true);
+        assert_eq!(
+            flags(us),
+            (
+                vec!["-mmutable-globals".to_string()],
+                vec!["--extra-features=mutable-globals".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_wat_command() {
+        let mut state = test_state(test_user_settings(), test_build_settings());
+        state.args.output = Some(PathBuf::from("build/app.wasm"));
+        let command = wat_command(&state, Path::new("/bin/wasm-dis"));
+        assert_eq!(command.get_program(), "/bin/wasm-dis");
+        assert_eq!(
+            command_args(&command),
+            ["build/app.wasm", "-o", "build/app.wat"]
+        );
+
+        state.args.output = Some(PathBuf::from("app"));
+        let command = wat_command(&state, Path::new("wasm2wat"));
+        assert_eq!(command_args(&command), ["app", "-o", "app.wat"]);
+    }
+
+    #[test]
+    fn test_pic_static_main() {
+        let state = dry_run(test_user_settings(), &["a.c"]);
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(!link_line.contains("--experimental-pic"));
+
+        let mut us = test_user_settings();
+        us.pic = true;
+        let state = dry_run(us, &["a.c"]);
+        assert!(effective_command_lines(&state, BuildStage::Compile)[0].contains(" -fPIC "));
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains(" --experimental-pic "));
+        assert!(link_line.contains(" --export-if-defined=__wasm_apply_data_relocs "));
+        assert!(!link_line.contains(" -pie "));
+    }
+
+    #[test]
+    fn test_minimal_exports() {
+        let exports = |module_kind: ModuleKind, threads: bool, minimal_exports: bool| {
+            let mut us = test_user_settings();
+            us.module_kind = Some(module_kind);
+            us.threads = threads;
+            us.minimal_exports = minimal_exports;
+            let state = test_state(us, test_build_settings());
+            command_args(&linker_command(&state))
+                .into_iter()
+                .filter(|arg| {
+                    arg.starts_with("--export=") || arg.starts_with("--export-if-defined=")
+                })
+                .collect::<Vec<_>>()
+        };
+        let tls_exports = [
+            "--export-if-defined=__wasm_init_tls",
+            "--export-if-defined=__tls_size",
+            "--export-if-defined=__tls_align",
+            "--export-if-defined=__tls_base",
+        ];
+
+        let full = exports(ModuleKind::StaticMain, true, false);
+        assert!(full.contains(&"--export-if-defined=__wasm_call_ctors".to_string()));
+        assert!(full.contains(&"--export-if-defined=__wasm_signal".to_string()));
+        // Only what was asked for through EXPORTED_FUNCTIONS is a hard export
+        assert!(!full.iter().any(|arg| arg.starts_with("--export=")));
+        assert!(full.contains(&"--export-if-defined=__heap_base".to_string()));
+
+        assert_eq!(
+            exports(ModuleKind::StaticMain, false, true),
+            Vec::<String>::new()
+        );
+        assert_eq!(exports(ModuleKind::StaticMain, true, true), tls_exports);
+        let mut dynamic_main = tls_exports.to_vec();
+        dynamic_main.push("--export-if-defined=__wasm_apply_data_relocs");
+        assert_eq!(exports(ModuleKind::DynamicMain, true, true), dynamic_main);
+        let mut shared_library = vec!["--export-if-defined=__wasm_call_ctors"];
+        shared_library.extend(dynamic_main);
+        assert_eq!(
+            exports(ModuleKind::SharedLibrary, false, true),
+            shared_library
+        );
+    }
+
+    #[test]
+    fn test_standalone() {
+        let state = dry_run(test_user_settings(), &["a.c"]);
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains(" --import-memory "));
+        assert!(link_line.contains(" --shared-memory "));
+
+        let mut us = test_user_settings();
+        us.standalone = true;
+        us.abi_profile = AbiProfile::Wasix;
+        us.threads = false;
+        let state = dry_run(us, &["a.c"]);
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains(" --export-memory "));
+        assert!(!link_line.contains("--import-memory"));
+        assert!(!link_line.contains("--shared-memory"));
+
+        let mut us = test_user_settings();
+        us.standalone = true;
+        us.threads = false;
+        us.module_kind = Some(ModuleKind::SharedLibrary);
+        let mut state = test_state(us, test_build_settings());
+        let err = link_inputs(&mut state).unwrap_err();
+        assert!(err.to_string().contains("SharedLibrary"), "{err}");
+    }
+
+    #[test]
+    fn test_passthrough_command() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+        let command = passthrough_command(
+            args(&["-print-file-name=libc.a"]),
+            &test_user_settings(),
+            false,
+        );
+        assert_eq!(
+            command_args(&command),
+            [
+                "--sysroot",
+                "/sysroot",
+                "--target=wasm32-wasi",
+                "-print-file-name=libc.a"
+            ]
+        );
+
+        let command = passthrough_command(
+            args(&[
+                "--sysroot=/other",
+                "-target",
+                "wasm64-wasi",
+                "-print-prog-name=ld",
+            ]),
+            &test_user_settings(),
+            true,
+        );
+        assert!(command.get_program().to_string_lossy().contains("clang++"));
+        assert_eq!(
+            command_args(&command),
+            [
+                "--sysroot=/other",
+                "-target",
+                "wasm64-wasi",
+                "-print-prog-name=ld"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_print_file_name_resolves_in_sysroot() {
+        // Stands in for clang's lookup in <sysroot>/lib/<target>
+        let tmp = fake_toolchain(&[(
+            "clang",
+            "while [ $# -gt 0 ]; do\n\
+              case \"$1\" in\n\
+                --sysroot) sysroot=$2; shift ;;\n\
+                --target=*) target=${1#--target=} ;;\n\
+                -print-file-name=*) echo \"$sysroot/lib/$target/${1#-print-file-name=}\" ;;\n\
+              esac\n\
+              shift\n\
+            done\n",
+        )]);
+        let sysroot = fake_sysroot(tmp.path());
+        let lib_path = sysroot.join("lib/wasm32-wasi");
+
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        us.sysroot_location = Some(sysroot);
+        let output = passthrough_command(vec!["-print-file-name=libc.a".to_owned()], &us, false)
+            .output()
+            .unwrap();
+        let printed = String::from_utf8(output.stdout).unwrap();
+        assert!(Path::new(printed.trim()).is_file(), "{printed}");
+        assert_eq!(Path::new(printed.trim()), lib_path.join("libc.a"));
+    }
+
+    #[test]
+    fn test_target_and_features() {
+        let mut us = test_user_settings();
+        us.target = "wasm64-wasi".to_owned();
+        us.target_features = Some(vec![
+            "atomics".to_owned(),
+            "bulk-memory".to_owned(),
+            "memory64".to_owned(),
+        ]);
+        let state = dry_run(us, &["a.c"]);
+        let compile_line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile_line.contains(" --target=wasm64-wasi "));
+        assert!(compile_line.contains(" -matomics -mbulk-memory -mmemory64 "));
+        assert!(!compile_line.contains("-mmutable-globals"));
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains(" -mwasm64 "));
+        assert!(link_line.contains(
+            " --extra-features=atomics --extra-features=bulk-memory --extra-features=memory64 "
+        ));
+        assert!(!link_line.contains("--max-memory"));
+        assert!(link_line.contains(" -L/sysroot/lib/wasm64-wasi "));
+    }
+
+    #[test]
+    fn test_simd() {
+        let mut us = test_user_settings();
+        us.simd = true;
+        let state = dry_run(us, &["a.c"]);
+        let compile_line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile_line.contains(" -mbulk-memory -mmutable-globals -matomics -msimd128 "));
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains(
+            " --extra-features=atomics --extra-features=bulk-memory \
+            --extra-features=mutable-globals --extra-features=simd128 "
+        ));
+
+        // Not repeated if TARGET_FEATURES already has it
+        let mut us = test_user_settings();
+        us.simd = true;
+        us.target_features = Some(vec!["atomics".to_owned(), "simd128".to_owned()]);
+        assert_eq!(us.compile_features(), vec!["atomics", "simd128"]);
+        us.target_features = Some(vec!["atomics".to_owned()]);
+        assert_eq!(us.compile_features(), vec!["atomics", "simd128"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Internal error")]
+    fn test_linker_command_rejects_inconsistent_atomics() {
+        let mut us = test_user_settings();
+        us.threads = false;
+        let mut state = test_state(us, test_build_settings());
+        state.compiled_with_atomics = Some(true);
+        linker_command(&state);
+    }
+
+    #[test]
+    fn test_linker_command_exported_functions() {
+        let mut us = test_user_settings();
+        us.exported_functions = vec!["foo".to_string(), "bar".to_string()];
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&"--export=foo".to_string()));
+        assert!(args.contains(&"--export=bar".to_string()));
+        assert!(!args.contains(&"--export-all".to_string()));
+
+        let mut us = test_user_settings();
+        us.export_all = true;
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&"--export-all".to_string()));
+    }
+
+    #[test]
+    fn test_object_file_name() {
+        let mut counter = HashMap::new();
+        assert_eq!(
+            object_file_name(Path::new("src/a.c"), &mut counter, ".o"),
+            OsString::from("a.c.0.o")
+        );
+        assert_eq!(
+            object_file_name(Path::new("other/a.c"), &mut counter, ".wasm.o"),
+            OsString::from("a.c.1.wasm.o")
+        );
+        assert_eq!(
+            object_file_name(Path::new("b.c"), &mut counter, ".wasm.o"),
+            OsString::from("b.c.0.wasm.o")
+        );
+    }
+
+    #[test]
+    fn test_thread_stack_size_stub_source() {
+        let source = thread_stack_size_stub_source(1048576);
+        assert!(source.contains("pthread_attr_setstacksize(&attr, 1048576u);"));
+        assert!(source.contains("pthread_setattr_default_np(&attr);"));
+    }
+
+    #[test]
+    fn test_has_cxx_inputs() {
+        let mut us = test_user_settings();
+        let (pa, _) =
+            prepare_compiler_args(vec!["a.c".to_string(), "b.cpp".to_string()], &mut us).unwrap();
+        assert!(has_cxx_inputs(&pa));
+
+        let (pa, _) = prepare_compiler_args(vec!["a.c".to_string()], &mut us).unwrap();
+        assert!(!has_cxx_inputs(&pa));
+
+        for ext in ["cc", "cxx", "C", "c++"] {
+            let (pa, _) = prepare_compiler_args(vec![format!("a.{ext}")], &mut us).unwrap();
+            assert!(has_cxx_inputs(&pa), "{ext} should be a C++ extension");
+        }
+
+        let (pa, _) = prepare_compiler_args(
+            vec!["-x".to_string(), "c".to_string(), "b.cpp".to_string()],
+            &mut us,
+        )
+        .unwrap();
+        assert!(!has_cxx_inputs(&pa));
+
+        let (pa, _) =
+            prepare_compiler_args(vec!["-xc".to_string(), "b.cpp".to_string()], &mut us).unwrap();
+        assert!(!has_cxx_inputs(&pa));
+    }
+
+    #[test]
+    fn test_native_cpu_flags_are_dropped() {
+        let mut us = test_user_settings();
+        let (pa, _) = prepare_compiler_args(
+            vec![
+                "-march=native".to_string(),
+                "-mcpu=skylake".to_string(),
+                "-mtune=native".to_string(),
+                "-mcpu=generic".to_string(),
+                "a.c".to_string(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        assert_eq!(pa.compiler_args, vec!["-mcpu=generic".to_string()]);
+        assert_eq!(pa.compiler_inputs, vec![PathBuf::from("a.c")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_repro_script() {
+        let temp_dir = Path::new("/tmp/wasixcc-build");
+        let mut command = Command::new("/opt/llvm/bin/clang");
+        command
+            .arg("-DGREETING=hello world")
+            .arg("in.c")
+            .arg("-o")
+            .arg(temp_dir.join("in.c.0.o"));
+        assert_eq!(
+            command_to_shell_line(&command, None),
+            "/opt/llvm/bin/clang '-DGREETING=hello world' in.c -o /tmp/wasixcc-build/in.c.0.o"
+        );
+        let line = command_to_shell_line(&command, Some(temp_dir));
+        assert_eq!(
+            line,
+            r#"/opt/llvm/bin/clang '-DGREETING=hello world' in.c -o "$tmp"/in.c.0.o"#
+        );
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let script_path = tmp.path().join("repro.sh");
+        let mut us = test_user_settings();
+        us.repro_script = Some(script_path.clone());
+        let mut state = test_state(us, test_build_settings());
+        state.script_lines.push((BuildStage::Compile, line));
+        write_repro_script(&state).unwrap();
+
+        let script = std::fs::read_to_string(&script_path).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("set -e\n"));
+        let status = Command::new("sh")
+            .arg("-n")
+            .arg(&script_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_language_override() {
+        let mut us = test_user_settings();
+        let (pa, _) = prepare_compiler_args(
+            vec![
+                "-x".to_string(),
+                "c++".to_string(),
+                "foo.cc".to_string(),
+                "lib.o".to_string(),
+                "-x".to_string(),
+                "none".to_string(),
+                "bar.c".to_string(),
+                "baz.o".to_string(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        assert!(pa.compiler_args.is_empty());
+        assert_eq!(
+            pa.compiler_inputs,
+            vec![
+                PathBuf::from("foo.cc"),
+                PathBuf::from("lib.o"),
+                PathBuf::from("bar.c")
+            ]
+        );
+        assert_eq!(
+            pa.compiler_input_languages,
+            vec![Some("c++".to_string()), Some("c++".to_string()), None]
+        );
+        assert_eq!(pa.linker_inputs, vec![PathBuf::from("baz.o")]);
+        assert!(has_cxx_inputs(&pa));
+
+        let (pa, _) = prepare_compiler_args(
+            vec![
+                "-xc++".to_string(),
+                "-xnone".to_string(),
+                "bar.c".to_string(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        assert_eq!(pa.compiler_input_languages, vec![None]);
+        assert!(!has_cxx_inputs(&pa));
+    }
+
+    #[test]
+    fn test_dependency_file_args() {
+        let mut state = test_state(test_user_settings(), test_build_settings());
+        state.args.output = Some(PathBuf::from("build/prog.wasm"));
+        let mut names = HashSet::new();
+        assert!(dependency_file_args(&state, Path::new("src/a.c"), &mut names).is_empty());
+
+        state.args.compiler_args = vec!["-MMD".to_string()];
+        assert_eq!(
+            dependency_file_args(&state, Path::new("src/a.c"), &mut names),
+            vec![
+                OsString::from("-MF"),
+                OsString::from("build/a.d"),
+                OsString::from("-MT"),
+                OsString::from("build/prog.wasm"),
+            ]
+        );
+        // Another a.c doesn't overwrite the first one's
+        assert_eq!(
+            dependency_file_args(&state, Path::new("lib/a.c"), &mut names)[1],
+            OsString::from("build/a.1.d")
+        );
+
+        state.args.compiler_args = vec![
+            "-MD".to_string(),
+            "-MF".to_string(),
+            "deps.d".to_string(),
+            "-MT".to_string(),
+            "prog".to_string(),
+        ];
+        assert!(dependency_file_args(&state, Path::new("src/a.c"), &mut names).is_empty());
+    }
+
+    #[test]
+    fn test_linker_command_link_features() {
+        let mut us = test_user_settings();
+        us.link_features = Some(vec!["atomics".to_string(), "simd128".to_string()]);
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&"--features=atomics,simd128".to_string()));
+        assert!(!args.iter().any(|arg| arg.starts_with("--extra-features")));
+        assert!(args.contains(&"--shared-memory".to_string()));
+    }
+
+    #[test]
+    fn test_remove_duplicate_compiler_inputs() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let source = tmp.path().join("foo.c");
+        std::fs::write(&source, "").unwrap();
+
+        let mut us = test_user_settings();
+        let (mut pa, _) = prepare_compiler_args(
+            vec![
+                source.to_string_lossy().into_owned(),
+                "bar.c".to_string(),
+                tmp.path()
+                    .join(".")
+                    .join("foo.c")
+                    .to_string_lossy()
+                    .into_owned(),
+                "bar.c".to_string(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        remove_duplicate_compiler_inputs(&mut pa);
+        assert_eq!(pa.compiler_inputs, vec![source, PathBuf::from("bar.c")]);
+        assert_eq!(pa.compiler_input_languages, vec![None, None]);
+    }
+
+    #[test]
+    fn test_print_effective_command() {
+        for (flag, stage, program) in [
+            ("compile", BuildStage::Compile, "clang-0"),
+            ("link", BuildStage::Link, "wasm-ld-0"),
+            ("opt", BuildStage::Opt, "wasm-opt"),
+        ] {
+            let mut us = test_user_settings();
+            let (args, build_settings) = prepare_compiler_args(
+                vec![
+                    "--print-effective-command".to_string(),
+                    flag.to_string(),
+                    "-O2".to_string(),
+                    "a.c".to_string(),
+                    "-o".to_string(),
+                    "out.wasm".to_string(),
+                ],
+                &mut us,
+            )
+            .unwrap();
+            assert_eq!(build_settings.print_stage, Some(stage));
+
+            let mut state = State {
+                user_settings: us,
+                build_settings,
+                args,
+                cxx: false,
+                temp_dir: PathBuf::from("/tmp/wasixcc-build"),
+                compiled_with_atomics: None,
+                script_lines: Vec::new(),
+                stage_times: Vec::new(),
+                version_script: None,
+                output_to_stdout: false,
+            };
+            // Nothing is executed, so this succeeds without any tools installed
+            compile_and_link(&mut state).unwrap();
+
+            let lines = effective_command_lines(&state, stage);
+            assert_eq!(lines.len(), 1);
+            let tool = lines[0].split(' ').next().unwrap();
+            assert!(tool.ends_with(program), "{}", lines[0]);
+        }
+
+        let mut us = test_user_settings();
+        assert!(prepare_compiler_args(
+            vec!["--print-effective-command=bogus".to_string()],
+            &mut us
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_temps() {
+        let tmp = fake_toolchain(&[("clang", FAKE_CLANG), ("llvm-ar", "touch \"$2\"\n")]);
+
+        let save_dir = tmp.path().join("temps");
+        let output = tmp.path().join("libx.a");
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        us.save_temps = Some(save_dir.clone());
+        let args = vec![
+            "a.c".to_owned(),
+            "-o".to_owned(),
+            output.to_str().unwrap().to_owned(),
+        ];
+        run(args, us, false).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(save_dir.join("a.o")).unwrap(),
+            "object\n"
+        );
+        let commands = std::fs::read_to_string(save_dir.join("commands.sh")).unwrap();
+        assert!(commands.contains(" -save-temps=obj "));
+        assert!(commands.contains("llvm-ar rcs"));
+
+        // Each output gets its own directory
+        let save_temps_dir = |args: &[&str]| {
+            let mut us = test_user_settings();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            let (args, _) = prepare_compiler_args(args, &mut us).unwrap();
+            assert!(!args
+                .compiler_args
+                .iter()
+                .any(|arg| arg.contains("save-temps")));
+            us.save_temps
+        };
+        assert_eq!(
+            save_temps_dir(&["--save-temps", "a.c", "-o", "out/prog"]),
+            Some(PathBuf::from("out/prog.wasixcc-temps"))
+        );
+        assert_eq!(
+            save_temps_dir(&["-save-temps", "-c", "src/b.c"]),
+            Some(PathBuf::from("b.c.wasixcc-temps"))
+        );
+        assert_eq!(
+            save_temps_dir(&["--save-temps", "a.o"]),
+            Some(PathBuf::from("a.out.wasixcc-temps"))
+        );
+    }
+
+    #[test]
+    fn test_temp_dir() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let base = tmp.path().join("scratch").join("wasixcc");
+        let mut us = test_user_settings();
+        us.temp_dir = Some(base.clone());
+
+        let (dir, guard) = create_temp_dir(&us, true).unwrap();
+        assert_eq!(dir.parent(), Some(base.as_path()));
+        assert!(dir.is_dir());
+        drop(guard);
+        assert!(!dir.exists());
+        assert!(base.is_dir());
+
+        // SAVE_TEMPS takes over, and its directory is kept
+        us.save_temps = Some(tmp.path().join("temps"));
+        let (dir, guard) = create_temp_dir(&us, true).unwrap();
+        assert!(guard.is_none());
+        assert_eq!(dir, tmp.path().join("temps"));
+    }
+
+    #[test]
+    fn test_check_sysroot() {
+        let args = test_state(test_user_settings(), test_build_settings()).args;
+        let tmp = tempfile::TempDir::new().unwrap();
+        let lib_path = tmp.path().join("lib").join("wasm32-wasi");
+        std::fs::create_dir_all(&lib_path).unwrap();
+
+        let mut us = test_user_settings();
+        us.sysroot_location = Some(tmp.path().to_owned());
+        let err = check_sysroot(&us, &args).unwrap_err().to_string();
+        assert!(err.contains("libc.a and crt1.o"), "{err}");
+        assert!(err.contains(&format!("{lib_path:?}")), "{err}");
+
+        std::fs::write(lib_path.join("libc.a"), "").unwrap();
+        std::fs::write(lib_path.join("crt1.o"), "").unwrap();
+        // scrt1.o is only needed by shared libraries, so this just warns
+        check_sysroot(&us, &args).unwrap();
+
+        us.module_kind = Some(ModuleKind::SharedLibrary);
+        let err = check_sysroot(&us, &args).unwrap_err().to_string();
+        assert!(err.contains("missing scrt1.o"), "{err}");
+
+        std::fs::write(lib_path.join("scrt1.o"), "").unwrap();
+        check_sysroot(&us, &args).unwrap();
+
+        // Only executables link the sanitizer runtime
+        us.sanitizer = Some(Sanitizer::Undefined);
+        check_sysroot(&us, &args).unwrap();
+        us.module_kind = None;
+        let err = check_sysroot(&us, &args).unwrap_err().to_string();
+        assert!(err.contains("libclang_rt.ubsan_standalone.a"), "{err}");
+        std::fs::write(lib_path.join("libclang_rt.ubsan_standalone.a"), "").unwrap();
+        check_sysroot(&us, &args).unwrap();
+
+        us.coverage = true;
+        let err = check_sysroot(&us, &args).unwrap_err().to_string();
+        assert!(err.contains("libclang_rt.profile.a"), "{err}");
+        std::fs::write(lib_path.join("libclang_rt.profile.a"), "").unwrap();
+        check_sysroot(&us, &args).unwrap();
+    }
+
+    #[test]
+    fn test_cxx_stdlib() {
+        let link_line = |us: UserSettings, args: &[&str]| {
+            let state = dry_run(us, args);
+            effective_command_lines(&state, BuildStage::Link)[0].to_owned()
+        };
+
+        assert!(link_line(test_user_settings(), &["a.cpp"]).contains(" -lc++ -lc++abi "));
+        assert!(!link_line(test_user_settings(), &["a.c"]).contains("-lc++"));
+
+        let mut us = test_user_settings();
+        us.cxx_stdlib = false;
+        assert!(!link_line(us, &["a.cpp"]).contains("-lc++"));
+
+        for flag in ["-nostdlib++", "-nostdinc++"] {
+            assert!(!link_line(test_user_settings(), &[flag, "a.cpp"]).contains("-lc++"));
+        }
+    }
+
+    #[test]
+    fn test_nostdlib_flags() {
+        let link_line = |args: &[&str]| {
+            let state = dry_run(test_user_settings(), args);
+            effective_command_lines(&state, BuildStage::Link)[0].to_owned()
+        };
+
+        let line = link_line(&["a.cpp"]);
+        assert!(line.contains(" -lc ") && line.contains(" -lc++ "));
+        assert!(line.contains("crt1.o"));
+
+        let line = link_line(&["-nostdlib", "a.cpp"]);
+        assert!(!line.contains(" -lc ") && !line.contains("-lc++"));
+        assert!(!line.contains("crt1.o"));
+
+        let line = link_line(&["-nodefaultlibs", "a.cpp"]);
+        assert!(!line.contains(" -lc ") && !line.contains("-lc++"));
+        assert!(line.contains("crt1.o"));
+
+        let line = link_line(&["-nostartfiles", "a.cpp"]);
+        assert!(line.contains(" -lc ") && line.contains(" -lc++ "));
+        assert!(!line.contains("crt1.o"));
+
+        // Nothing from the sysroot is needed when neither is linked
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut us = test_user_settings();
+        us.sysroot_location = Some(tmp.path().to_owned());
+        let (args, _) =
+            prepare_compiler_args(vec!["-nostdlib".into(), "a.c".into()], &mut us).unwrap();
+        check_sysroot(&us, &args).unwrap();
+        let (args, _) =
+            prepare_compiler_args(vec!["-nostartfiles".into(), "a.c".into()], &mut us).unwrap();
+        let err = check_sysroot(&us, &args).unwrap_err().to_string();
+        assert!(err.contains("missing libc.a"), "{err}");
+        assert!(!err.contains("crt1.o"), "{err}");
+    }
+
+    #[test]
+    fn test_crt1_override() {
+        let link_line = |crt1: Option<&str>, no_crt1: bool, args: &[&str]| {
+            let mut us = test_user_settings();
+            us.crt1 = crt1.map(PathBuf::from);
+            us.no_crt1 = no_crt1;
+            let state = dry_run(us, args);
+            effective_command_lines(&state, BuildStage::Link)[0].to_owned()
+        };
+
+        let line = link_line(Some("/runtime/mycrt1.o"), false, &["a.c"]);
+        assert!(line.contains(" /runtime/mycrt1.o "));
+        assert!(!line.contains("/crt1.o") && !line.contains("scrt1.o"));
+        let line = link_line(Some("/runtime/mycrt1.o"), false, &["-shared", "a.c"]);
+        assert!(line.contains(" /runtime/mycrt1.o ") && !line.contains("scrt1.o"));
+
+        let line = link_line(None, true, &["a.c"]);
+        assert!(!line.contains("crt1.o"));
+        assert!(line.contains(" -lc "));
+
+        // The sysroot doesn't need startfiles then
+        let tmp = tempfile::TempDir::new().unwrap();
+        let lib_path = tmp.path().join("lib").join("wasm32-wasi");
+        std::fs::create_dir_all(&lib_path).unwrap();
+        std::fs::write(lib_path.join("libc.a"), "").unwrap();
+        let mut us = test_user_settings();
+        us.no_crt1 = true;
+        us.sysroot_location = Some(tmp.path().to_owned());
+        let args = test_state(test_user_settings(), test_build_settings()).args;
+        check_sysroot(&us, &args).unwrap();
+        us.no_crt1 = false;
+        assert!(check_sysroot(&us, &args).is_err());
+    }
+
+    #[test]
+    fn test_coverage_flags() {
+        let mut us = test_user_settings();
+        us.coverage = true;
+        let state = dry_run(us, &["a.c"]);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -fprofile-instr-generate -fcoverage-mapping "));
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(
+            " -lclang_rt.profile --undefined=__llvm_profile_runtime \
+            --export-if-defined=__llvm_profile_write_file "
+        ));
+    }
+
+    #[test]
+    fn test_pgo_flags() {
+        let mut us = test_user_settings();
+        us.pgo_generate = true;
+        let state = dry_run(us, &["a.c"]);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -fprofile-instr-generate "));
+        assert!(!compile.contains("-fcoverage-mapping"));
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" -lclang_rt.profile --undefined=__llvm_profile_runtime "));
+
+        let tmp = tempfile::TempDir::new().unwrap();
+        let profile = tmp.path().join("app.profdata");
+        std::fs::write(&profile, "").unwrap();
+        let mut us = test_user_settings();
+        us.pgo_use = Some(profile.clone());
+        let state = dry_run(us, &["a.c", "b.c"]);
+        for line in effective_command_lines(&state, BuildStage::Compile) {
+            assert!(line.contains(&format!(" -fprofile-instr-use={} ", profile.display())));
+        }
+        assert!(!effective_command_lines(&state, BuildStage::Link)[0].contains("profile"));
+
+        let mut us = test_user_settings();
+        us.pgo_use = Some(tmp.path().join("missing.profdata"));
+        let err = run(vec!["-c".to_owned(), "a.c".to_owned()], us, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("missing.profdata"), "{err}");
+    }
+
+    #[test]
+    fn test_sanitizer_flags() {
+        let mut us = test_user_settings();
+        us.sanitizer = Some(Sanitizer::Undefined);
+        let state = dry_run(us, &["a.c"]);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -fsanitize=undefined "));
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" -lclang_rt.ubsan_standalone "));
+
+        let mut us = test_user_settings();
+        us.sanitizer = Some(Sanitizer::Undefined);
+        let state = dry_run(us, &["-shared", "a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(!link.contains("ubsan"));
+    }
+
+    #[test]
+    fn test_check_output_not_input() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let input = tmp.path().join("foo.o");
+        std::fs::write(&input, "object").unwrap();
+
+        let mut us = test_user_settings();
+        let mut args = prepare_linker_args(
+            vec![
+                input.to_str().unwrap().to_owned(),
+                "-o".to_owned(),
+                tmp.path()
+                    .join(".")
+                    .join("foo.o")
+                    .to_str()
+                    .unwrap()
+                    .to_owned(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        let err = check_output_not_input(&args).unwrap_err().to_string();
+        assert!(err.contains("is also the output"), "{err}");
+
+        args.output = Some(tmp.path().join("bar.o"));
+        check_output_not_input(&args).unwrap();
+        std::fs::write(tmp.path().join("bar.o"), "").unwrap();
+        check_output_not_input(&args).unwrap();
+
+        let err = link_only(
+            vec![
+                "-r".to_owned(),
+                input.to_str().unwrap().to_owned(),
+                "-o".to_owned(),
+                input.to_str().unwrap().to_owned(),
+            ],
+            test_user_settings(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("is also the output"), "{err}");
+        assert_eq!(std::fs::read_to_string(&input).unwrap(), "object");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_output_writable() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempfile::TempDir::new().unwrap();
+        let output = tmp.path().join("out.wasm");
+        check_output_writable(&output).unwrap();
+
+        std::fs::set_permissions(tmp.path(), std::fs::Permissions::from_mode(0o555)).unwrap();
+        // Permissions don't apply when running as root
+        let permissions_enforced = std::fs::write(&output, "").is_err();
+        if permissions_enforced {
+            let err = check_output_writable(&output).unwrap_err();
+            assert!(err.to_string().contains("permission denied"), "{err}");
+        }
+        std::fs::set_permissions(tmp.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_linker_map() {
+        for module_kind in [
+            ModuleKind::StaticMain,
+            ModuleKind::DynamicMain,
+            ModuleKind::SharedLibrary,
+        ] {
+            let mut us = test_user_settings();
+            us.module_kind = Some(module_kind);
+            us.linker_map = Some(LinkerMap::File(PathBuf::from("out.map")));
+            let state = test_state(us, test_build_settings());
+            let args = command_args(&linker_command(&state));
+            assert!(args.contains(&"-Map=out.map".to_string()));
+        }
+
+        let mut us = test_user_settings();
+        us.linker_map = Some(LinkerMap::Stdout);
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&"--print-map".to_string()));
+
+        // Object files aren't linked, so there's nowhere for the map to come from
+        let mut us = test_user_settings();
+        us.linker_map = Some(LinkerMap::Stdout);
+        let state = dry_run(us, &["-c", "a.c"]);
+        assert!(effective_command_lines(&state, BuildStage::Link).is_empty());
+        assert!(!state
+            .script_lines
+            .iter()
+            .any(|(_, line)| line.contains("-Map") || line.contains("--print-map")));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut us = test_user_settings();
+        us.analyze = Some(AnalyzeMode::Report);
+        let state = dry_run(us, &["a.c", "b.c", "-o", "out.wasm"]);
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert!(line.contains(" --analyze --analyzer-output text "));
+            assert!(!line.contains(" -c "));
+            assert!(!line.contains("--analyzer-Werror"));
+            assert!(line.ends_with(".plist"));
+        }
+        assert!(state.args.linker_inputs.is_empty());
+        assert!(effective_command_lines(&state, BuildStage::Link).is_empty());
+        assert!(effective_command_lines(&state, BuildStage::Opt).is_empty());
+
+        let mut us = test_user_settings();
+        us.analyze = Some(AnalyzeMode::Strict);
+        let state = dry_run(us, &["-c", "a.c"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(line.contains(" --analyzer-Werror "));
+        assert!(!line.ends_with("a.o"));
+    }
+
+    #[test]
+    fn test_user_sysroot_args() {
+        for args in [
+            &["--sysroot=/other", "a.c"][..],
+            &["--sysroot", "/other", "a.c"][..],
+        ] {
+            let state = dry_run(test_user_settings(), args);
+            assert_eq!(
+                state.user_settings.sysroot_location,
+                Some(PathBuf::from("/other"))
+            );
+            assert!(!state
+                .args
+                .compiler_args
+                .iter()
+                .any(|arg| arg.contains("sysroot")));
+            let line = effective_command_lines(&state, BuildStage::Compile)[0];
+            assert_eq!(line.matches("sysroot").count(), 1);
+            assert!(line.contains(" --sysroot /other "));
+            let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+            assert!(link_line.contains(" -L/other/lib "));
+            assert!(!link_line.contains("/sysroot"));
+        }
+
+        let state = dry_run(
+            test_user_settings(),
+            &["-isysroot", "/headers", "-c", "a.c"],
+        );
+        assert_eq!(
+            state.user_settings.sysroot_location,
+            Some(PathBuf::from("/sysroot"))
+        );
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(line.contains(" --sysroot /sysroot "));
+        assert!(line.contains(" -isysroot /headers "));
+
+        let state = dry_run(test_user_settings(), &["-isysroot/headers", "-c", "a.c"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(line.contains(" -isysroot /headers "));
+    }
+
+    #[test]
+    fn test_reproducible() {
+        let state = dry_run(test_user_settings(), &["a.c"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(!line.contains("SOURCE_DATE_EPOCH"));
+        assert!(!line.contains("-ffile-prefix-map"));
+
+        let mut us = test_user_settings();
+        us.reproducible = true;
+        let state = dry_run(us, &["a.c", "b.c"]);
+        let cwd = std::env::current_dir().unwrap();
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            if std::env::var_os("SOURCE_DATE_EPOCH").is_none() {
+                assert!(line.starts_with("SOURCE_DATE_EPOCH=0 "), "{line}");
+            }
+            assert!(line.contains(&format!("-ffile-prefix-map={}=.", cwd.display())));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_diagnostics_failure() {
+        let tmp = fake_toolchain(&[(
+            "clang",
+            "echo \"a.c:1:1: error: expected ';'\" >&2\nexit 1\n",
+        )]);
+
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        us.json_diagnostics = true;
+        let mut state = test_state(us, test_build_settings());
+        state.args.compiler_inputs.push(PathBuf::from("a.c"));
+        state.args.compiler_input_languages.push(None);
+        state.args.compiler_input_arg_counts.push(0);
+        state.temp_dir = tmp.path().to_owned();
+
+        assert!(compile_inputs(&mut state).is_err());
+        let line = &state.script_lines[0].1;
+        assert!(line.contains(" -fno-color-diagnostics -fno-caret-diagnostics "));
+
+        let diagnostics = crate::diagnostics::take_diagnostics();
+        assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+        let diagnostic = serde_json::from_str::<serde_json::Value>(&diagnostics[0]).unwrap();
+        assert_eq!(
+            diagnostic,
+            serde_json::json!({
+                "tool": "clang",
+                "severity": "error",
+                "message": "expected ';'",
+                "file": "a.c",
+                "line": 1,
+                "column": 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_per_input_compiler_args() {
+        let state = dry_run(
+            test_user_settings(),
+            &["-std=c11", "a.c", "-include", "pch.h", "b.c", "-O2"],
+        );
+        let a = &state.script_lines[0].1;
+        let b = &state.script_lines[1].1;
+        assert!(a.contains(" -std=c11 ") && a.contains(" -O2 ") && a.contains(" a.c "));
+        assert!(!a.contains("-include"));
+        assert!(b.contains(" -std=c11 -include pch.h -O2 ") && b.contains(" b.c "));
+
+        // Objects get all the flags in one command, as clang applies them globally
+        let state = dry_run(
+            test_user_settings(),
+            &["-c", "a.c", "-include", "pch.h", "b.c"],
+        );
+        assert_eq!(state.script_lines.len(), 1);
+        assert!(state.script_lines[0].1.contains(" -include pch.h "));
+    }
+
+    #[test]
+    fn test_precompiled_header() {
+        let state = dry_run(
+            test_user_settings(),
+            &["-x", "c++-header", "pch.h", "-o", "pch.h.pch"],
+        );
+        assert_eq!(
+            state.user_settings.module_kind,
+            Some(ModuleKind::ObjectFile)
+        );
+        assert!(state.cxx);
+        assert_eq!(state.script_lines.len(), 1);
+        let line = &state.script_lines[0].1;
+        assert!(line.starts_with("clang++-0 --sysroot"));
+        assert!(line.contains(" --target=wasm32-wasi "));
+        assert!(line.ends_with(" -x c++-header pch.h -o pch.h.pch"));
+
+        let state = dry_run(test_user_settings(), &["config.hpp"]);
+        assert_eq!(
+            state.user_settings.module_kind,
+            Some(ModuleKind::ObjectFile)
+        );
+        assert!(state.cxx);
+
+        // Headers given along with sources are still just compiled
+        let state = dry_run(test_user_settings(), &["a.c", "b.h"]);
+        assert_eq!(state.user_settings.module_kind, None);
+
+        // The header is then used with the same target and flags
+        let state = dry_run(
+            test_user_settings(),
+            &["-include-pch", "pch.h.pch", "a.cpp", "-o", "a.wasm"],
+        );
+        let line = &state.script_lines[0].1;
+        assert!(line.contains(" --target=wasm32-wasi "));
+        assert!(line.contains(" -include-pch pch.h.pch ") && line.contains(" a.cpp "));
+        assert_eq!(state.args.compiler_inputs, vec![PathBuf::from("a.cpp")]);
+    }
+
+    #[test]
+    fn test_archive_output() {
+        let state = dry_run(test_user_settings(), &["a.c", "b.c", "c.o", "-o", "libx.a"]);
+        assert_eq!(state.user_settings.module_kind, Some(ModuleKind::Archive));
+        let lines = state
+            .script_lines
+            .iter()
+            .map(|(_, line)| line.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(" -c ") && lines[0].ends_with(" -o \"$tmp\"/a.o"));
+        assert_eq!(
+            lines[2],
+            "llvm-ar-0 rcs libx.a c.o \"$tmp\"/a.o \"$tmp\"/b.o"
+        );
+
+        let mut us = test_user_settings();
+        us.ar_deterministic = true;
+        let state = dry_run(us, &["a.c", "-o", "libx.a"]);
+        assert_eq!(
+            state.script_lines[1].1,
+            "llvm-ar-0 rcsD libx.a \"$tmp\"/a.o"
+        );
+
+        // Same-named sources, and a given object with the name a compiled
+        // source would get
+        let state = dry_run(
+            test_user_settings(),
+            &["x/a.c", "y/a.c", "c.c", "c.o", "-o", "libx.a"],
+        );
+        let archive_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert_eq!(
+            archive_line,
+            "llvm-ar-0 rcs libx.a c.o \"$tmp\"/a.o \"$tmp\"/a.1.o \"$tmp\"/c.1.o"
+        );
+
+        let mut us = test_user_settings();
+        us.module_kind = Some(ModuleKind::Archive);
+        let (args, build_settings) =
+            prepare_compiler_args(vec!["a.c".into(), "lib.a".into()], &mut us).unwrap();
+        let mut state = test_state(us, build_settings);
+        state.args = args;
+        assert!(archive_objects(&mut state).is_err());
+    }
+
+    #[test]
+    fn test_relocatable_output() {
+        let state = dry_run(
+            test_user_settings(),
+            &["-r", "a.c", "b.o", "-o", "merged.o"],
+        );
+        assert_eq!(
+            state.user_settings.module_kind,
+            Some(ModuleKind::Relocatable)
+        );
+        let compile = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(compile.len(), 1);
+        assert!(compile[0].contains(" -c ") && !compile[0].contains(" -r "));
+        assert_eq!(
+            effective_command_lines(&state, BuildStage::Link),
+            vec!["wasm-ld-0 -r b.o \"$tmp\"/a.c.0.o -o merged.o"]
+        );
+        assert!(effective_command_lines(&state, BuildStage::Opt).is_empty());
+
+        let mut us = test_user_settings();
+        let args = prepare_linker_args(
+            vec![
+                "--relocatable".into(),
+                "a.o".into(),
+                "-o".into(),
+                "m.o".into(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        assert_eq!(us.module_kind, Some(ModuleKind::Relocatable));
+        let mut state = test_state(us, test_build_settings());
+        state.args = args;
+        state.build_settings.print_stage = Some(BuildStage::Link);
+        link_relocatable(&mut state).unwrap();
+        assert_eq!(state.script_lines[0].1, "wasm-ld-0 -r a.o -o m.o");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_auto_module_kind() {
+        // Objects are copies of the sources, which hold llvm-nm's listing
+        let tmp = fake_toolchain(&[
+            (
+                "clang",
+                "while [ $# -gt 0 ]; do\n\
+                  case \"$1\" in -o) out=\"$2\"; shift;; *.c) src=\"$1\";; esac\n\
+                  shift\n\
+                done\n\
+                cp \"$src\" \"$out\"\n",
+            ),
+            ("llvm-nm", "for last; do :; done\ncat \"$last\"\n"),
+            ("wasm-ld", "echo \"$@\" > \"$(dirname \"$0\")/ld-args\"\n"),
+        ]);
+        let sysroot = fake_sysroot(tmp.path());
+        let write_file = |name: &str, contents: &str| {
+            let path = tmp.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_owned()
+        };
+
+        let main = write_file("main.c", "__main_argc_argv T 0 0\nhelper U\n");
+        let helper = write_file("helper.c", "helper T 0 0\n");
+        let build = |inputs: &[&str]| {
+            let mut us = test_user_settings();
+            us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+            us.sysroot_location = Some(sysroot.clone());
+            us.module_kind = Some(ModuleKind::Auto);
+            us.run_wasm_opt = Some(false);
+            let mut args = inputs.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            args.extend(["-o".to_owned(), write_file("out", "")]);
+            run(args, us, false).unwrap();
+            std::fs::read_to_string(tmp.path().join("ld-args")).unwrap()
+        };
+
+        let ld_args = build(&[&main, &helper]);
+        assert!(!ld_args.starts_with("-r ") && ld_args.contains("crt1.o"));
+        let ld_args = build(&[&helper]);
+        assert!(ld_args.starts_with("-r ") && !ld_args.contains("crt1.o"));
+
+        // Flags still decide over auto
+        let mut us = test_user_settings();
+        us.module_kind = Some(ModuleKind::Auto);
+        prepare_compiler_args(vec!["-c".into(), "a.c".into()], &mut us).unwrap();
+        assert_eq!(us.module_kind, Some(ModuleKind::ObjectFile));
+        let mut us = test_user_settings();
+        us.module_kind = Some(ModuleKind::Auto);
+        prepare_compiler_args(vec!["a.c".into()], &mut us).unwrap();
+        assert_eq!(us.module_kind, Some(ModuleKind::Auto));
+    }
+
+    #[test]
+    fn test_wrap_symbols() {
+        let state = dry_run(
+            test_user_settings(),
+            &["a.c", "-Wl,--wrap=malloc,--wrap=free", "-Wl,--wrap,calloc"],
+        );
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --wrap=malloc --wrap=free --wrap calloc "));
+        // Before libc, whose own malloc calls get wrapped as well
+        assert!(link.find("--wrap=malloc").unwrap() < link.find(" -lc ").unwrap());
+
+        let mut us = test_user_settings();
+        let args = prepare_linker_args(
+            vec!["--wrap".into(), "malloc".into(), "a.o".into()],
+            &mut us,
+        )
+        .unwrap();
+        assert_eq!(args.linker_args, vec!["--wrap", "malloc"]);
+        assert_eq!(args.linker_inputs, vec![PathBuf::from("a.o")]);
+    }
+
+    #[test]
+    fn test_preprocessor_and_assembler_args() {
+        let mut us = test_user_settings();
+        let args = ["-Wp,-D,FOO", "-Wa,--noexecstack", "a.c"]
+            .map(String::from)
+            .to_vec();
+        let (args, _) = prepare_compiler_args(args, &mut us).unwrap();
+        assert_eq!(
+            args.compiler_args,
+            vec![
+                "-Xpreprocessor",
+                "-D",
+                "-Xpreprocessor",
+                "FOO",
+                "-Xassembler",
+                "--noexecstack"
+            ]
+        );
+
+        let state = dry_run(test_user_settings(), &["-c", "a.c", "-Wp,-DBAR,-UBAZ"]);
+        assert!(effective_command_lines(&state, BuildStage::Compile)[0]
+            .contains(" -Xpreprocessor -DBAR -Xpreprocessor -UBAZ "));
+
+        // Left for clang to turn into -MD -MF foo.d, so we don't pick a file
+        let state = dry_run(
+            test_user_settings(),
+            &["a.c", "-Wp,-MD,foo.d", "-o", "prog"],
+        );
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -Wp,-MD,foo.d "));
+        assert!(!compile.contains("-Xpreprocessor"));
+        assert!(!compile.contains(" -MF "));
+        assert!(compile.contains(" -MT prog "));
+    }
+
+    #[test]
+    fn test_xlinker_value_pairs() {
+        let mut us = test_user_settings();
+        let args = ["-Xlinker", "-mllvm", "-Xlinker", "-foo", "a.c"]
+            .map(String::from)
+            .to_vec();
+        let (args, _) = prepare_compiler_args(args, &mut us).unwrap();
+        assert_eq!(args.linker_args, vec!["-mllvm", "-foo"]);
+
+        let state = dry_run(
+            test_user_settings(),
+            &["a.c", "-Xlinker", "-mllvm", "-Xlinker", "-foo"],
+        );
+        assert!(effective_command_lines(&state, BuildStage::Link)[0].contains(" -mllvm -foo "));
+
+        // The value of -mllvm isn't a linker flag of its own
+        let mut us = test_user_settings();
+        let args = ["-Xlinker", "-mllvm", "-Xlinker", "-shared", "a.c"]
+            .map(String::from)
+            .to_vec();
+        prepare_compiler_args(args, &mut us).unwrap();
+        assert_eq!(us.module_kind, None);
+
+        let mut us = test_user_settings();
+        let args = ["a.c", "-Xlinker", "-mllvm"].map(String::from).to_vec();
+        assert!(prepare_compiler_args(args, &mut us).is_err());
+    }
+
+    #[test]
+    fn test_timings_summary() {
+        use std::time::Duration;
+        assert_eq!(
+            timings_summary(&[
+                (BuildStage::Compile, Duration::from_millis(1200)),
+                (BuildStage::Link, Duration::from_millis(800)),
+                (BuildStage::Compile, Duration::from_millis(2000)),
+            ]),
+            "compile: 3.2s, link: 0.8s"
+        );
+        assert_eq!(timings_summary(&[]), "");
+
+        // Nothing runs, so nothing is timed
+        let mut us = test_user_settings();
+        us.timings = true;
+        assert!(dry_run(us, &["a.c"]).stage_times.is_empty());
+    }
+
+    #[test]
+    fn test_objc_inputs() {
+        let prepare = |args: &[&str]| {
+            let mut us = test_user_settings();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            prepare_compiler_args(args, &mut us).map(|_| ())
+        };
+
+        let error = prepare(&["-c", "view.m"]).unwrap_err().to_string();
+        assert!(error.contains("Objective-C is not supported for WASIX targets"));
+        assert!(prepare(&["-c", "view.mm"]).is_err());
+        assert!(prepare(&["-c", "-x", "objective-c", "view.c"]).is_err());
+        assert!(prepare(&["-c", "-x", "objective-c", "-x", "none", "a.c"]).is_ok());
+        // An object or archive is fine, whatever language it came from
+        assert!(prepare(&["a.c", "libobjc.a"]).is_ok());
+    }
+
+    #[test]
+    fn test_ir_inputs() {
+        let args = [
+            "-MD", "-DFOO", "-I", "include", "-std=c11", "a.ll", "b.bc", "c.c",
+        ];
+        let state = dry_run(test_user_settings(), &args);
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 3);
+        for line in &lines[..2] {
+            assert!(line.contains(" --target=wasm32-wasi -c "));
+            assert!(line.contains(" -matomics "));
+            assert!(!line.contains(" -D"));
+            assert!(!line.contains("include"));
+            assert!(!line.contains("-std="));
+            assert!(!line.contains(" -MD ") && !line.contains(" -MF "));
+        }
+        assert!(lines[0].ends_with(" a.ll -o \"$tmp\"/a.ll.0.o"));
+        assert!(lines[2].contains(" -D_WASI_EMULATED_MMAN "));
+        assert!(lines[2].contains(" -MD -DFOO -I include -std=c11 "));
+        assert!(lines[2].contains(" -MF "));
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" \"$tmp\"/a.ll.0.o \"$tmp\"/b.bc.0.o \"$tmp\"/c.c.0.o "));
+
+        let state = dry_run(test_user_settings(), &["-c", "-x", "ir", "a.txt"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(!line.contains(" -D"));
+        assert!(line.ends_with(" -x ir a.txt"));
+
+        // With -c, the sources keep their flags in a command of their own
+        let state = dry_run(test_user_settings(), &["-c", "-DFOO", "a.c", "b.ll"]);
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(" -D_WASI_EMULATED_MMAN ") && lines[0].contains(" -DFOO "));
+        assert!(lines[0].ends_with(" a.c"));
+        assert!(!lines[1].contains(" -D"));
+        assert!(lines[1].ends_with(" b.ll"));
+
+        let mut us = test_user_settings();
+        let args = ["-c", "a.c", "b.ll", "-o", "out.o"]
+            .map(String::from)
+            .to_vec();
+        let mut state = test_state(us.clone(), test_build_settings());
+        (state.args, state.build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+        state.user_settings = us;
+        let error = compile_inputs(&mut state).unwrap_err().to_string();
+        assert!(error.contains("both IR and source inputs"), "{error}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ir_input_object() {
+        // Fails on the flags clang would warn about as unused for IR
+        let script = format!(
+            "for arg; do\n\
+              case \"$arg\" in -D*|-I*|-std=*|-fno-exceptions)\n\
+                echo \"argument unused during compilation: '$arg'\" >&2; exit 1;;\n\
+              esac\n\
+            done\n\
+            {FAKE_CLANG}"
+        );
+        let tmp = fake_toolchain(&[("clang", &script)]);
+
+        let input = tmp.path().join("a.ll");
+        std::fs::write(&input, "").unwrap();
+        let output = tmp.path().join("a.o");
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        let args = ["-c", "-DFOO", "-Iinclude", "-std=c11", "-fno-exceptions"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .chain([input.to_str().unwrap().to_owned()])
+            .chain(["-o".to_owned(), output.to_str().unwrap().to_owned()])
+            .collect();
+        run(args, us, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "object\n");
+    }
+
+    #[test]
+    fn test_table_flags() {
+        let link_line = |us: UserSettings| {
+            let state = dry_run(us, &["a.c"]);
+            effective_command_lines(&state, BuildStage::Link)[0].to_owned()
+        };
+
+        let line = link_line(test_user_settings());
+        assert!(!line.contains("-table"));
+
+        let mut us = test_user_settings();
+        us.export_table = true;
+        let line = link_line(us);
+        assert!(line.contains(" --export-table ") && !line.contains("--import-table"));
+
+        let mut us = test_user_settings();
+        us.import_table = true;
+        let line = link_line(us);
+        assert!(line.contains(" --import-table ") && !line.contains("--export-table"));
     }
 
-    if user_settings.module_kind.is_none() {
-        for arg in &result.linker_args {
-            if arg == "-shared" {
-                user_settings.module_kind = Some(ModuleKind::SharedLibrary);
-                break;
-            } else if arg == "-pie" {
-                user_settings.module_kind = Some(ModuleKind::DynamicMain);
-                break;
-            }
+    #[test]
+    fn test_no_default_flags() {
+        let mut us = test_user_settings();
+        us.no_default_flags = true;
+        let state = dry_run(us, &["a.c", "-O2", "-Wl,--no-entry"]);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert_eq!(
+            compile,
+            "clang-0 --sysroot /sysroot --target=wasm32-wasi -c -O2 a.c -o \"$tmp\"/a.c.0.o"
+        );
+        assert_eq!(
+            link,
+            "wasm-ld-0 --no-entry \"$tmp\"/a.c.0.o -L/sysroot/lib -L/sysroot/lib/wasm32-wasi -lc \
+            /sysroot/lib/wasm32-wasi/crt1.o -o a.out"
+        );
+
+        let mut us = test_user_settings();
+        us.no_default_flags = true;
+        let state = dry_run(us, &["-shared", "a.c", "-o", "liba.so"]);
+        assert_eq!(
+            effective_command_lines(&state, BuildStage::Link)[0],
+            "wasm-ld-0 -shared \"$tmp\"/a.c.0.o /sysroot/lib/wasm32-wasi/scrt1.o -o liba.so"
+        );
+    }
+
+    #[test]
+    fn test_visibility() {
+        let compile_line = |pic: bool, args: &[&str]| {
+            let mut us = test_user_settings();
+            us.pic = pic;
+            let state = dry_run(us, args);
+            effective_command_lines(&state, BuildStage::Compile)[0].to_owned()
+        };
+
+        let line = compile_line(true, &["-c", "a.c"]);
+        assert!(line.contains(" -fvisibility=default "));
+        let line = compile_line(false, &["-c", "a.c"]);
+        assert!(!line.contains("-fvisibility"));
+
+        for pic in [true, false] {
+            let line = compile_line(pic, &["-c", "a.c", "-fvisibility=hidden"]);
+            assert!(line.contains(" -fvisibility=hidden "));
+            assert!(!line.contains("-fvisibility=default"));
         }
     }
 
-    Ok(result)
-}
+    #[test]
+    fn test_version_script() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let script = tmp.path().join("libfoo.map");
+        std::fs::write(&script, "LIBFOO { global: foo_init; foo_run; local: *; };").unwrap();
 
-// The returned bool indicated whether the argument should be kept in the
-// compiler args.
-// TODO: update build settings from UserSettings::extra_compiler_flags as well
-fn update_build_settings_from_arg(
-    arg: &str,
-    build_settings: &mut BuildSettings,
-    user_settings: &mut UserSettings,
-) -> Result<bool> {
-    if let Some(opt_level) = arg.strip_prefix("-O") {
-        build_settings.opt_level = match opt_level {
-            "0" => OptLevel::O0,
-            "1" => OptLevel::O1,
-            "2" => OptLevel::O2,
-            "3" => OptLevel::O3,
-            "4" => OptLevel::O4,
-            "s" => OptLevel::Os,
-            "z" => OptLevel::Oz,
-            x => bail!("Invalid argument: -O{x}"),
-        };
-        Ok(true)
-    } else if let Some(debug_level) = arg.strip_prefix("-g") {
-        build_settings.debug_level = match debug_level {
-            "" => DebugLevel::G2,
-            "0" => DebugLevel::G0,
-            "1" => DebugLevel::G1,
-            "2" => DebugLevel::G2,
-            "3" => DebugLevel::G3,
-            x => bail!("Invalid argument: -g{x}"),
-        };
-        Ok(true)
-    } else if arg == "-fwasm-exceptions" {
-        user_settings.wasm_exceptions = true;
-        Ok(false)
-    } else if arg == "-fno-wasm-exceptions" {
-        user_settings.wasm_exceptions = false;
-        Ok(true)
-    } else if arg == "--no-wasm-opt" {
-        build_settings.use_wasm_opt = false;
-        Ok(false)
-    } else {
-        Ok(true)
+        let mut us = test_user_settings();
+        us.version_script = Some(script.clone());
+        let state = dry_run(us, &["-shared", "a.c", "-o", "libfoo.so"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --no-export-dynamic "));
+        assert!(!link.contains(" --export-dynamic "));
+        assert!(link.contains(" --export=foo_init --export=foo_run "));
+
+        // Taken over from the linker flags, since wasm-ld can't read it
+        let version_script_arg = format!("-Wl,--version-script={}", script.display());
+        let state = dry_run(
+            test_user_settings(),
+            &["-shared", "a.c", &version_script_arg, "-o", "libfoo.so"],
+        );
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(!link.contains("--version-script"));
+        assert!(link.contains(" --export=foo_init --export=foo_run "));
+
+        let mut us = test_user_settings();
+        let args = prepare_linker_args(
+            vec![
+                "--version-script".into(),
+                "libbar.map".into(),
+                "-L".into(),
+                "--version-script=x".into(),
+                "a.o".into(),
+            ],
+            &mut us,
+        )
+        .unwrap();
+        assert_eq!(us.version_script, Some(PathBuf::from("libbar.map")));
+        assert_eq!(args.linker_args, vec!["-L", "--version-script=x"]);
+
+        // Without local: *, everything else is still exported as usual
+        std::fs::write(&script, "{ global: foo_init; };").unwrap();
+        let mut us = test_user_settings();
+        us.version_script = Some(script);
+        let state = dry_run(us, &["-shared", "a.c", "-o", "libfoo.so"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --export-dynamic "));
+        assert!(link.contains(" --export=foo_init "));
     }
-}
 
-fn deduce_module_kind(extension: &OsStr) -> Option<ModuleKind> {
-    match extension.to_str() {
-        Some("o") | Some("obj") => Some(ModuleKind::ObjectFile),
-        Some("so") => Some(ModuleKind::SharedLibrary),
-        _ => None, // Default to static main if no extension matches
+    #[test]
+    fn test_stdout_output() {
+        let mut us = test_user_settings();
+        let args = ["-c", "a.c", "-o", "-"].map(String::from).to_vec();
+        let (mut args, _) = prepare_compiler_args(args, &mut us).unwrap();
+        assert!(redirect_stdout_output(&mut args, &us, Path::new("/tmp/build")).unwrap());
+        assert_eq!(args.output, Some(PathBuf::from("/tmp/build/stdout-output")));
+
+        let args = ["-c", "a.c", "-o", "a.o"].map(String::from).to_vec();
+        let (mut args, _) = prepare_compiler_args(args, &mut us).unwrap();
+        assert!(!redirect_stdout_output(&mut args, &us, Path::new("/tmp/build")).unwrap());
+        assert_eq!(args.output, Some(PathBuf::from("a.o")));
+
+        // Files named after the output would go to the temp dir with it
+        let redirect = |args: &[&str], us: &UserSettings| {
+            let mut us = us.clone();
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            let (mut args, _) = prepare_compiler_args(args, &mut us).unwrap();
+            redirect_stdout_output(&mut args, &us, Path::new("/tmp/build"))
+        };
+        let error = redirect(&["-c", "a.c", "-MD", "-o", "-"], &us).unwrap_err();
+        assert!(error.to_string().contains("need -MF and -MT"), "{error}");
+        assert!(redirect(&["-c", "a.c", "-MD", "-MF", "a.d", "-o", "-"], &us).is_err());
+        assert!(redirect(&["-c", "a.c", "-Wp,-MD,a.d", "-o", "-"], &us).is_err());
+        assert!(redirect(
+            &["-c", "a.c", "-MD", "-MF", "a.d", "-MT", "a.o", "-o", "-"],
+            &us
+        )
+        .unwrap());
+        assert!(redirect(&["-c", "a.c", "-Wp,-MMD,a.d", "-MT", "a.o", "-o", "-"], &us).unwrap());
+        us.emit_wat = true;
+        let error = redirect(&["a.c", "-o", "-"], &us).unwrap_err();
+        assert!(error.to_string().contains("EMIT_WAT"), "{error}");
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{LlvmLocation, UserSettings};
-    use std::{ffi::OsStr, path::PathBuf};
+    #[test]
+    fn test_extra_lib_dirs() {
+        let mut us = test_user_settings();
+        us.extra_lib_dirs = vec![PathBuf::from("/opt/libcxx/lib"), PathBuf::from("/opt/z")];
+        let state = dry_run(us, &["a.c", "-lc++"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" -L/opt/libcxx/lib -L/opt/z -L/sysroot/lib "));
+        assert!(link.find("-L/opt/z").unwrap() < link.find(" -lc ").unwrap());
+    }
 
     #[test]
-    fn test_deduce_module_kind() {
+    fn test_print_commands() {
+        let mut us = test_user_settings();
+        let args = ["-###", "a.c"].map(String::from).to_vec();
+        let (args, build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+        assert!(build_settings.print_commands && build_settings.dry_run());
+        assert!(!args.compiler_args.contains(&"-###".to_owned()));
+
+        let mut state = test_state(us, build_settings);
+        state.args = args;
+        compile_and_link(&mut state).unwrap();
         assert_eq!(
-            deduce_module_kind(OsStr::new("o")),
-            Some(ModuleKind::ObjectFile)
+            effective_command_lines(&state, BuildStage::Compile).len(),
+            1
         );
+        assert_eq!(effective_command_lines(&state, BuildStage::Link).len(), 1);
+
+        let mut command = Command::new("/usr/bin/clang");
+        command.args(["-c", "a b.c", r#"-DX="$y\""#]);
         assert_eq!(
-            deduce_module_kind(OsStr::new("so")),
-            Some(ModuleKind::SharedLibrary)
+            command_to_clang_line(&command),
+            r#" "/usr/bin/clang" "-c" "a b.c" "-DX=\"\$y\\\"""#
         );
-        assert_eq!(deduce_module_kind(OsStr::new("unknown")), None);
     }
 
     #[test]
-    fn test_update_build_settings_from_arg() {
-        let mut bs = BuildSettings {
-            opt_level: OptLevel::O0,
-            debug_level: DebugLevel::None,
-            use_wasm_opt: true,
-        };
-        let mut us = UserSettings {
-            sysroot_location: None,
-            llvm_location: LlvmLocation::FromSystem(0),
-            extra_compiler_flags: vec![],
-            extra_linker_flags: vec![],
-            run_wasm_opt: None,
-            wasm_opt_flags: vec![],
-            module_kind: None,
-            wasm_exceptions: false,
-            pic: false,
-        };
-        assert!(update_build_settings_from_arg("-O3", &mut bs, &mut us).unwrap());
-        assert_eq!(bs.opt_level, OptLevel::O3);
-        assert!(update_build_settings_from_arg("-g1", &mut bs, &mut us).unwrap());
-        assert_eq!(bs.debug_level, DebugLevel::G1);
-        assert!(!update_build_settings_from_arg("--no-wasm-opt", &mut bs, &mut us).unwrap());
-        assert!(!update_build_settings_from_arg("-fwasm-exceptions", &mut bs, &mut us).unwrap());
-        assert!(us.wasm_exceptions);
-        assert!(update_build_settings_from_arg("-fno-wasm-exceptions", &mut bs, &mut us).unwrap());
-        assert!(!us.wasm_exceptions);
+    fn test_soname() {
+        let mut us = test_user_settings();
+        us.soname = Some("libx.so.1".to_owned());
+        let state = dry_run(us, &["a.c", "-shared", "-o", "libx.so"]);
+        let link = effective_command_lines(&state, BuildStage::Link).join("\n");
+        assert!(link.contains(" --soname=libx.so.1 "));
+
+        let state = dry_run(
+            test_user_settings(),
+            &[
+                "a.c",
+                "-shared",
+                "-install_name",
+                "@rpath/libx.dylib",
+                "-compatibility_version",
+                "1.0",
+                "-current_version",
+                "1.2",
+            ],
+        );
+        assert_eq!(state.user_settings.soname.as_deref(), Some("libx.dylib"));
+        let link = effective_command_lines(&state, BuildStage::Link).join("\n");
+        assert!(link.contains(" --soname=libx.dylib "));
+        assert!(!link.contains("version"));
+        for line in effective_command_lines(&state, BuildStage::Compile) {
+            assert!(!line.contains("install_name") && !line.contains("version"));
+        }
     }
 
     #[test]
-    fn test_prepare_compiler_args_and_build_settings() {
-        let mut us = UserSettings {
-            sysroot_location: None,
-            llvm_location: LlvmLocation::FromSystem(0),
-            extra_compiler_flags: vec![],
-            extra_linker_flags: vec![],
-            run_wasm_opt: None,
-            wasm_opt_flags: vec![],
-            module_kind: None,
-            wasm_exceptions: false,
-            pic: false,
+    fn test_entry() {
+        let mut us = test_user_settings();
+        us.entry = Some("my_start".to_owned());
+        us.no_crt1 = true;
+        let state = dry_run(us.clone(), &["a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --entry=my_start "));
+        assert!(!link.contains("crt1.o"));
+
+        let mut minimal = us.clone();
+        minimal.no_default_flags = true;
+        let state = dry_run(minimal, &["a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --entry=my_start "));
+
+        let args = ["--print-effective-command=link", "a.c", "-shared"]
+            .map(String::from)
+            .to_vec();
+        let (args, build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+        let mut state = test_state(us, build_settings);
+        state.args = args;
+        let err = compile_and_link(&mut state).unwrap_err().to_string();
+        assert!(err.contains("ENTRY only applies to executables"), "{err}");
+    }
+
+    #[test]
+    fn test_import_undefined() {
+        let unresolved_flags = |policy: Option<&str>, args: &[&str]| {
+            let mut us = test_user_settings();
+            us.import_undefined = policy.map(|name| UnresolvedSymbols::from_name(name).unwrap());
+            let state = dry_run(us, args);
+            effective_command_lines(&state, BuildStage::Link)[0]
+                .split(' ')
+                .filter(|arg| arg.starts_with("--unresolved-symbols"))
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
         };
-        let args = vec![
-            "-O2".to_string(),
-            "-g0".to_string(),
-            "-fwasm-exceptions".to_string(),
-            "--no-wasm-opt".to_string(),
-            "-Wl,-foo,bar".to_string(),
-            "-Xlinker".to_string(),
-            "baz".to_string(),
-            "-z".to_string(),
-            "zo".to_string(),
-            "-o".to_string(),
-            "out".to_string(),
-            "in.c".to_string(),
-            "lib.o".to_string(),
-        ];
-        let (pa, bs) = prepare_compiler_args(args, &mut us).unwrap();
-        assert_eq!(bs.opt_level, OptLevel::O2);
-        assert_eq!(bs.debug_level, DebugLevel::G0);
-        assert!(!bs.use_wasm_opt);
-        assert!(us.wasm_exceptions);
-        assert_eq!(pa.compiler_args, vec!["-O2".to_string(), "-g0".to_string()]);
+
+        assert!(unresolved_flags(None, &["a.c"]).is_empty());
         assert_eq!(
-            pa.linker_args,
+            unresolved_flags(None, &["a.c", "-shared"]),
+            ["--unresolved-symbols=import-dynamic"]
+        );
+        for name in ["report-all", "ignore-all", "import-dynamic"] {
+            let expected = [format!("--unresolved-symbols={name}")];
+            assert_eq!(unresolved_flags(Some(name), &["a.c"]), expected);
+            assert_eq!(unresolved_flags(Some(name), &["a.c", "-shared"]), expected);
+        }
+    }
+
+    #[test]
+    fn test_component() {
+        let mut us = test_user_settings();
+        us.component = true;
+        us.adapter = Some(PathBuf::from("/adapters/wasi_snapshot_preview1.wasm"));
+        us.tool_overrides.insert(
+            "wasm-tools",
+            PathBuf::from("/opt/wasm-tools/bin/wasm-tools"),
+        );
+        let state = dry_run(us.clone(), &["a.c", "-o", "a.wasm"]);
+        assert_eq!(
+            effective_command_lines(&state, BuildStage::Component),
             vec![
-                "-foo".to_string(),
-                "bar".to_string(),
-                "baz".to_string(),
-                "-z".to_string(),
-                "zo".to_string()
+                "/opt/wasm-tools/bin/wasm-tools component new a.wasm --adapt \
+                /adapters/wasi_snapshot_preview1.wasm -o a.wasm"
             ]
         );
-        assert_eq!(pa.output, Some(PathBuf::from("out")));
-        assert_eq!(pa.compiler_inputs, vec![PathBuf::from("in.c")]);
-        assert_eq!(pa.linker_inputs, vec![PathBuf::from("lib.o")]);
+        assert!(!effective_command_lines(&state, BuildStage::Opt)
+            .iter()
+            .any(|line| line.contains("wasm-tools")));
+        // Counted as a stage of its own
+        let summary = timings_summary(&[
+            (BuildStage::Opt, std::time::Duration::from_secs(1)),
+            (BuildStage::Component, std::time::Duration::from_secs(2)),
+        ]);
+        assert_eq!(summary, "wasm-opt: 1.0s, component: 2.0s");
+
+        let args = ["--print-effective-command=link", "a.c", "-shared"]
+            .map(String::from)
+            .to_vec();
+        let (args, build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+        let mut state = test_state(us, build_settings);
+        state.args = args;
+        let err = compile_and_link(&mut state).unwrap_err().to_string();
+        assert!(
+            err.contains("COMPONENT=1 only applies to executables"),
+            "{err}"
+        );
     }
 
     #[test]
-    fn test_prepare_linker_args() {
-        let mut us = UserSettings {
-            sysroot_location: None,
-            llvm_location: LlvmLocation::FromSystem(0),
-            extra_compiler_flags: vec![],
-            extra_linker_flags: vec![],
-            run_wasm_opt: None,
-            wasm_opt_flags: vec![],
-            module_kind: None,
-            wasm_exceptions: false,
-            pic: false,
+    fn test_gc_sections() {
+        let state = dry_run(test_user_settings(), &["a.c", "b.c"]);
+        for line in effective_command_lines(&state, BuildStage::Compile) {
+            assert!(!line.contains("-sections"));
+        }
+        assert!(!effective_command_lines(&state, BuildStage::Link)[0].contains("--gc-sections"));
+
+        let mut us = test_user_settings();
+        us.gc_sections = true;
+        let state = dry_run(us, &["a.c", "b.c"]);
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            assert!(line.contains(" -ffunction-sections -fdata-sections "));
+        }
+        assert!(effective_command_lines(&state, BuildStage::Link)[0].contains(" --gc-sections "));
+
+        let mut us = test_user_settings();
+        us.gc_sections = true;
+        let state = dry_run(us, &["a.c", "-Wl,--no-gc-sections"]);
+        assert!(!effective_command_lines(&state, BuildStage::Link)[0].contains(" --gc-sections "));
+    }
+
+    #[test]
+    fn test_strict_aliasing() {
+        let state = dry_run(test_user_settings(), &["a.c", "b.c"]);
+        for line in effective_command_lines(&state, BuildStage::Compile) {
+            assert!(!line.contains("strict-aliasing"));
+        }
+
+        let mut us = test_user_settings();
+        us.strict_aliasing = false;
+        let state = dry_run(us, &["a.c", "b.c", "c.cpp"]);
+        let lines = effective_command_lines(&state, BuildStage::Compile);
+        assert_eq!(lines.len(), 3);
+        for line in lines {
+            assert!(line.contains(" -fno-strict-aliasing "));
+        }
+
+        let mut us = test_user_settings();
+        us.strict_aliasing = false;
+        let state = dry_run(us, &["-fstrict-aliasing", "-c", "a.c"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(
+            line.find("-fno-strict-aliasing").unwrap() < line.find(" -fstrict-aliasing").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cxx_exceptions() {
+        let state = dry_run(test_user_settings(), &["throw.cpp"]);
+        let compile_line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile_line.contains(" -fno-exceptions "));
+        assert!(!compile_line.contains("-fwasm-exceptions"));
+
+        let mut us = test_user_settings();
+        us.exceptions_mode = ExceptionsMode::Wasm;
+        let state = dry_run(us, &["throw.cpp"]);
+        let compile_line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile_line.contains(" -fwasm-exceptions "));
+        assert!(!compile_line.contains("-fno-exceptions"));
+        let link_line = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link_line.contains("--wasm-enable-sjlj"));
+        assert!(
+            effective_command_lines(&state, BuildStage::Opt)[0].contains("--experimental-new-eh")
+        );
+    }
+
+    #[test]
+    fn test_cxx_exceptions_conflict() {
+        let compile = |args: &[&str]| {
+            let mut us = test_user_settings();
+            us.exceptions_mode = ExceptionsMode::Wasm;
+            let args = ["--print-effective-command=compile"]
+                .iter()
+                .chain(args)
+                .map(|arg| arg.to_string())
+                .collect();
+            let (args, build_settings) = prepare_compiler_args(args, &mut us).unwrap();
+            let mut state = test_state(us, build_settings);
+            state.cxx = has_cxx_inputs(&args);
+            state.args = args;
+            compile_and_link(&mut state)
         };
-        let args = vec![
-            "-o".to_string(),
-            "out.wasm".to_string(),
-            "-shared".to_string(),
-            "-m".to_string(),
-            "module".to_string(),
-            "mod.wasm".to_string(),
-        ];
-        let pa = prepare_linker_args(args, &mut us).unwrap();
-        assert_eq!(pa.output, Some(PathBuf::from("out.wasm")));
+
+        let err = compile(&["-fno-exceptions", "throw.cpp"]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("-fno-exceptions turns C++ exceptions off"));
+        assert!(compile(&["-fno-cxx-exceptions", "throw.cpp"]).is_err());
+        // The last one wins, like in clang
+        compile(&["-fno-exceptions", "-fexceptions", "throw.cpp"]).unwrap();
+        // C has no exceptions for the flag to turn off
+        compile(&["-fno-exceptions", "a.c"]).unwrap();
+    }
+
+    #[test]
+    fn test_exceptions_mode_flags() {
+        let lines = |mode| {
+            let mut us = test_user_settings();
+            us.exceptions_mode = mode;
+            us.setjmp = mode == ExceptionsMode::Sjlj;
+            let state = dry_run(us, &["jump.cpp"]);
+            [BuildStage::Compile, BuildStage::Link, BuildStage::Opt]
+                // wasm-opt may have nothing to do and not run at all
+                .map(|stage| effective_command_lines(&state, stage).join("\n"))
+        };
+
+        let [compile, link, opt] = lines(ExceptionsMode::Sjlj);
+        assert!(compile.contains(" -mllvm -wasm-enable-sjlj "));
+        assert!(compile.contains(" -fno-exceptions "));
+        assert!(!compile.contains("-fwasm-exceptions"));
+        assert!(link.contains(" -mllvm --wasm-enable-sjlj"));
+        assert!(opt.contains("--experimental-new-eh"));
+
+        let [compile, link, opt] = lines(ExceptionsMode::None);
+        assert!(!compile.contains("sjlj") && !compile.contains("-fwasm-exceptions"));
+        assert!(compile.contains(" -fno-exceptions "));
+        assert!(!link.contains("sjlj"));
+        assert!(!opt.contains("--experimental-new-eh"));
+
+        // Both at once, for C++ that also uses setjmp
+        let mut us = test_user_settings();
+        us.exceptions_mode = ExceptionsMode::Wasm;
+        us.setjmp = true;
+        let state = dry_run(us, &["jump.cpp"]);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -fwasm-exceptions -mllvm -wasm-enable-sjlj "));
+
+        // Turning C++ exceptions off keeps setjmp/longjmp working in every step
+        let mut us = test_user_settings();
+        us.exceptions_mode = ExceptionsMode::Wasm;
+        us.setjmp = true;
+        let state = dry_run(us, &["-fno-wasm-exceptions", "jump.cpp"]);
+        assert_eq!(state.user_settings.exceptions_mode, ExceptionsMode::Sjlj);
+        let compile = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(compile.contains(" -mllvm -wasm-enable-sjlj "));
+        assert!(compile.contains(" -fno-exceptions "));
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" -mllvm --wasm-enable-sjlj"));
+        let opt = effective_command_lines(&state, BuildStage::Opt).join("\n");
+        assert!(opt.contains("--experimental-new-eh"));
+    }
+
+    #[test]
+    fn test_lto_jobs() {
+        let mut us = test_user_settings();
+        us.lto_jobs = Some(4);
+        let state = dry_run(us.clone(), &["-flto=thin", "a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(link.contains(" --thinlto-jobs=4 "));
+
+        let state = dry_run(us.clone(), &["a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(!link.contains("--thinlto-jobs"));
+
+        let state = dry_run(us.clone(), &["-flto", "-fno-lto", "a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        assert!(!link.contains("--thinlto-jobs"));
+
+        for full_lto in ["-flto", "-flto=full"] {
+            let state = dry_run(us.clone(), &[full_lto, "a.c"]);
+            let link = effective_command_lines(&state, BuildStage::Link)[0];
+            assert!(!link.contains("--thinlto-jobs"), "{full_lto}");
+        }
+
+        let state = dry_run(test_user_settings(), &["-flto=thin", "a.c"]);
+        let link = effective_command_lines(&state, BuildStage::Link)[0];
+        let jobs = std::thread::available_parallelism().unwrap();
+        assert!(link.contains(&format!(" --thinlto-jobs={jobs} ")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lto_cache() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let cache_dir = tmp.path().join("cache");
+
+        let mut us = test_user_settings();
+        us.lto_cache = Some(cache_dir.clone());
+        us.lto_cache_policy = Some("prune_after=1h".to_string());
+        let state = test_state(us, test_build_settings());
+        let args = command_args(&linker_command(&state));
+        assert!(args.contains(&format!("--thinlto-cache-dir={}", cache_dir.display())));
+        assert!(args.contains(&"--thinlto-cache-policy=prune_after=1h".to_string()));
+
+        // A wasm-ld that knows about the cache gets it created
+        let write_wasm_ld = |help: &str| {
+            write_fake_tools(tmp.path(), &[("wasm-ld", &format!("echo '{help}'"))]);
+        };
+        let lto_state = || {
+            let mut us = test_user_settings();
+            us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+            us.lto_cache = Some(cache_dir.clone());
+            test_state(us, test_build_settings())
+        };
+
+        write_wasm_ld("  --thinlto-cache-dir=<value>");
+        let mut state = lto_state();
+        prepare_lto_cache(&mut state).unwrap();
+        assert!(cache_dir.is_dir());
+        assert!(state.user_settings.lto_cache.is_some());
+
+        // ... and one that doesn't falls back to linking without it
+        write_wasm_ld("  --other-flag");
+        let mut state = lto_state();
+        prepare_lto_cache(&mut state).unwrap();
+        assert!(state.user_settings.lto_cache.is_none());
+        let args = command_args(&linker_command(&state));
+        assert!(!args
+            .iter()
+            .any(|arg| arg.starts_with("--thinlto-cache-dir")));
+    }
+
+    #[test]
+    fn test_emit_relocs_skips_wasm_opt() {
+        let build = |emit_relocs_setting: bool, args: &[&str]| {
+            let mut us = test_user_settings();
+            us.emit_relocs = emit_relocs_setting;
+            dry_run(us, args)
+        };
+
+        let state = build(false, &["-O2", "a.c"]);
+        assert!(!effective_command_lines(&state, BuildStage::Link)[0].contains("--emit-relocs"));
+        assert_eq!(effective_command_lines(&state, BuildStage::Opt).len(), 1);
+
+        assert!(take_warnings().is_empty());
+
+        let skipped_warning = |warnings: Vec<String>| {
+            warnings
+                .iter()
+                .any(|warning| warning.starts_with("Skipping wasm-opt, so the module is not"))
+        };
+        let state = build(true, &["-O2", "a.c"]);
+        assert!(effective_command_lines(&state, BuildStage::Link)[0].contains("--emit-relocs"));
+        assert!(effective_command_lines(&state, BuildStage::Opt).is_empty());
+        assert!(skipped_warning(take_warnings()));
+
+        let state = build(false, &["-O2", "a.c", "-Wl,--emit-relocs"]);
+        assert!(effective_command_lines(&state, BuildStage::Opt).is_empty());
+        assert!(skipped_warning(take_warnings()));
+
+        // There was nothing for wasm-opt to do anyway
+        build(true, &["-O0", "a.c"]);
+        assert!(!skipped_warning(take_warnings()));
+    }
+
+    #[test]
+    fn test_assertion_args() {
+        let opt_build_settings = || BuildSettings {
+            opt_level: OptLevel::O2,
+            ..test_build_settings()
+        };
+
+        let state = test_state(test_user_settings(), test_build_settings());
+        assert!(assertion_args(&state).is_empty());
+
+        let state = test_state(test_user_settings(), opt_build_settings());
+        assert_eq!(assertion_args(&state), ["-DNDEBUG"]);
+
+        let mut us = test_user_settings();
+        us.assertions = Some(true);
+        let state = test_state(us, opt_build_settings());
         assert_eq!(
-            pa.linker_args,
-            vec![
-                "-shared".to_string(),
-                "-m".to_string(),
-                "module".to_string()
-            ]
+            assertion_args(&state),
+            ["-DWASIXCC_ASSERTIONS=1", "-UNDEBUG"]
         );
-        assert_eq!(pa.linker_inputs, vec![PathBuf::from("mod.wasm")]);
-        assert_eq!(us.module_kind, Some(ModuleKind::SharedLibrary));
+
+        // The user's own flags come after ours so they can override them
+        let mut us = test_user_settings();
+        us.assertions = Some(false);
+        let state = dry_run(us, &["-UNDEBUG", "-c", "a.c"]);
+        let line = effective_command_lines(&state, BuildStage::Compile)[0];
+        assert!(line.find("-DNDEBUG").unwrap() < line.find("-UNDEBUG").unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_add_exports_from_objects() {
+        let tmp = fake_toolchain(&[("llvm-nm", "printf 'api_init\\napi_run\\n'")]);
+
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        us.exported_functions = vec!["api_run".to_string(), "other".to_string()];
+        us.exports_from_objects = vec![PathBuf::from("api.o")];
+        let mut state = test_state(us, test_build_settings());
+        add_exports_from_objects(&mut state).unwrap();
+        assert_eq!(
+            state.user_settings.exported_functions,
+            vec!["api_run", "other", "api_init"]
+        );
+
+        let args = command_args(&linker_command(&state));
+        for name in ["api_run", "other", "api_init"] {
+            assert!(args.contains(&format!("--export={name}")));
+        }
+    }
+
+    #[test]
+    fn test_config_report() {
+        let mut us = test_user_settings();
+        us.llvm_location = LlvmLocation::FromPath(PathBuf::from("/llvm/bin"));
+        let state = dry_run(us, &["-O2", "a.c"]);
+        let report = config_report(&state);
+        assert!(report.contains("clang: /llvm/bin/clang\n"));
+        assert!(report.contains("wasm-ld: /llvm/bin/wasm-ld\n"));
+        assert!(report.contains("sysroot: /sysroot\n"));
+        assert!(report.contains("module kind: StaticMain\n"));
+        assert!(report.contains("compile command: /llvm/bin/clang --sysroot /sysroot"));
+        assert!(report.contains("link command: /llvm/bin/wasm-ld"));
+        assert!(report.contains("wasm-opt command: "));
+
+        let mut us = test_user_settings();
+        us.sysroot_location = None;
+        let state = test_state(us, test_build_settings());
+        let report = config_report(&state);
+        assert!(report.contains("sysroot: (not set)\n"));
+        assert!(!report.contains("command:"));
     }
 }
@@ -0,0 +1,136 @@
+//! Support for `wasixcc.toml` config files, which provide defaults for user
+//! settings. Only top-level keys with string, boolean, integer or string
+//! array values are supported; e.g.:
+//!
+//! ```toml
+//! SYSROOT = "/opt/wasix-sysroot"
+//! PIC = true
+//! COMPILER_FLAGS = ["-O2", "-DFOO"]
+//! ```
+
+use super::*;
+
+const CONFIG_FILE_NAME: &str = "wasixcc.toml";
+
+/// Loads the first config file found by walking up from the current directory,
+/// falling back to `$XDG_CONFIG_HOME/wasixcc/config.toml`. Keys map to the
+/// user setting names, e.g. `SYSROOT`.
+pub(crate) fn load_config_file() -> Result<HashMap<String, String>> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    let Some(path) = find_config_file(&current_dir, xdg_config_home.as_deref()) else {
+        return Ok(HashMap::new());
+    };
+
+    tracing::info!("Reading config file {path:?}");
+
+    let contents = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read config file {path:?}"))?;
+    parse_config(&contents).with_context(|| format!("Invalid config file {path:?}"))
+}
+
+fn find_config_file(start: &Path, xdg_config_home: Option<&Path>) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .chain(xdg_config_home.map(|dir| dir.join("wasixcc").join("config.toml")))
+        .find(|path| path.is_file())
+}
+
+fn parse_config(contents: &str) -> Result<HashMap<String, String>> {
+    let table = toml::from_str::<toml::Table>(contents)?;
+
+    let mut result = HashMap::new();
+    for (key, value) in table {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+        {
+            bail!("Invalid key {key:?}");
+        }
+
+        let value = config_value(value).with_context(|| format!("Invalid value for {key}"))?;
+        result.insert(key.to_uppercase(), value);
+    }
+
+    Ok(result)
+}
+
+/// Converts a TOML value into the same string form accepted by `-s` flags;
+/// arrays become colon-separated lists.
+fn config_value(value: toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(string) => Ok(string),
+        toml::Value::Boolean(boolean) => Ok(boolean.to_string()),
+        toml::Value::Integer(integer) => Ok(integer.to_string()),
+        toml::Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| match item {
+                    toml::Value::String(item) => Ok(item.replace(':', "\\:")),
+                    item => bail!("Expected an array of strings; got {item}"),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(items.join(":"))
+        }
+        toml::Value::Table(_) => bail!("Tables are not supported"),
+        value => bail!("Expected a string, boolean, integer or array of strings; got {value}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse_config(
+            r#"
+            # Defaults for this project
+            SYSROOT = "/opt/wasix sysroot"
+            pic = true
+            INITIAL_MEMORY = 1048576 # 1MiB
+            COMPILER_FLAGS = ["-O2", '-DPATH="a:b"', "-DHASH=#"] # trailing
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config["SYSROOT"], "/opt/wasix sysroot");
+        assert_eq!(config["PIC"], "true");
+        assert_eq!(config["INITIAL_MEMORY"], "1048576");
+        assert_eq!(config["COMPILER_FLAGS"], r#"-O2:-DPATH="a\:b":-DHASH=#"#);
+
+        assert!(parse_config("[table]").is_err());
+        assert!(parse_config("COMPILER_FLAGS = [\"-O2\"").is_err());
+        assert!(parse_config("SYSROOT").is_err());
+        assert!(parse_config("SYSROOT = \"unterminated").is_err());
+        assert!(parse_config("SYSROOT = bare").is_err());
+        assert!(parse_config("FLAGS = [1]").is_err());
+        assert!(parse_config("SYSROOT = 1.5").is_err());
+    }
+
+    #[test]
+    fn test_find_config_file() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let project = tmp.path().join("project");
+        let nested = project.join("src").join("nested");
+        std::fs::create_dir_all(&nested).unwrap();
+        let xdg = tmp.path().join("xdg");
+        std::fs::create_dir_all(xdg.join("wasixcc")).unwrap();
+        std::fs::write(xdg.join("wasixcc").join("config.toml"), "").unwrap();
+
+        assert_eq!(
+            find_config_file(&nested, Some(&xdg)),
+            Some(xdg.join("wasixcc").join("config.toml"))
+        );
+
+        std::fs::write(project.join(CONFIG_FILE_NAME), "").unwrap();
+        assert_eq!(
+            find_config_file(&nested, Some(&xdg)),
+            Some(project.join(CONFIG_FILE_NAME))
+        );
+    }
+}
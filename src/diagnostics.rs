@@ -0,0 +1,203 @@
+use regex::Regex;
+
+use super::*;
+
+/// `file:line:column: severity: message [-Wflag]`, as printed by clang.
+static CLANG_DIAGNOSTIC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?P<file>.+?):(?P<line>\d+):(?P<column>\d+): (?P<severity>fatal error|error|warning|note|remark): (?P<message>.*?)(?: \[(?P<option>[^\]]+)\])?$",
+    )
+    .unwrap()
+});
+
+/// `wasm-ld: severity: message`, as printed by lld and most other LLVM tools.
+static TOOL_DIAGNOSTIC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<tool>[\w.+-]+): (?P<severity>error|warning): (?P<message>.*)$").unwrap()
+});
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Diagnostic<'a> {
+    tool: &'a str,
+    file: Option<&'a str>,
+    line: Option<u32>,
+    column: Option<u32>,
+    // error, warning etc; output lines that aren't diagnostics get "text"
+    severity: &'a str,
+    message: &'a str,
+    option: Option<&'a str>,
+}
+
+/// Runs a command with its stderr captured, and re-emits it on our stderr as
+/// one JSON object per line for DIAGNOSTICS=json. Stdout is passed through,
+/// unless `stdout_to_stderr` is set; see `-o -`.
+pub(crate) fn run_command_with_json_diagnostics(
+    mut command: Command,
+    user_settings: &UserSettings,
+    stdout_to_stderr: bool,
+) -> Result<()> {
+    tracing::info!("Executing build command: {command:?}");
+
+    if stdout_to_stderr {
+        command.stdout(std::io::stderr());
+    } else {
+        command.stdout(std::process::Stdio::inherit());
+    }
+    let output = command
+        .stdin(std::process::Stdio::inherit())
+        .output()
+        .map_err(|error| spawn_error(&command, error, user_settings))?;
+
+    let tool = Path::new(command.get_program())
+        .file_name()
+        .and_then(OsStr::to_str)
+        .unwrap_or("unknown");
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        if !line.is_empty() {
+            print_diagnostic(diagnostic_json(&parse_diagnostic(tool, line)));
+        }
+    }
+
+    if !output.status.success() {
+        bail!(
+            "Command failed with status: {}; the command was: {command:?}",
+            output.status
+        );
+    }
+
+    Ok(())
+}
+
+fn print_diagnostic(json: String) {
+    eprintln!("{json}");
+    #[cfg(test)]
+    DIAGNOSTICS.with_borrow_mut(|diagnostics| diagnostics.push(json));
+}
+
+#[cfg(test)]
+thread_local! {
+    static DIAGNOSTICS: std::cell::RefCell<Vec<String>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// The JSON diagnostics printed so far on this thread, for tests to check.
+#[cfg(test)]
+pub(crate) fn take_diagnostics() -> Vec<String> {
+    DIAGNOSTICS.take()
+}
+
+fn parse_diagnostic<'a>(tool: &'a str, line: &'a str) -> Diagnostic<'a> {
+    if let Some(captures) = CLANG_DIAGNOSTIC.captures(line) {
+        let capture = |name| captures.name(name).map(|m| m.as_str());
+        return Diagnostic {
+            tool,
+            file: capture("file"),
+            line: capture("line").and_then(|line| line.parse().ok()),
+            column: capture("column").and_then(|column| column.parse().ok()),
+            severity: capture("severity").unwrap(),
+            message: capture("message").unwrap(),
+            option: capture("option"),
+        };
+    }
+
+    if let Some(captures) = TOOL_DIAGNOSTIC.captures(line) {
+        return Diagnostic {
+            tool: captures.name("tool").unwrap().as_str(),
+            severity: captures.name("severity").unwrap().as_str(),
+            message: captures.name("message").unwrap().as_str(),
+            ..Default::default()
+        };
+    }
+
+    Diagnostic {
+        tool,
+        severity: "text",
+        message: line,
+        ..Default::default()
+    }
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic) -> String {
+    let mut fields = vec![
+        format!("\"tool\":{}", json_string(diagnostic.tool)),
+        format!("\"severity\":{}", json_string(diagnostic.severity)),
+        format!("\"message\":{}", json_string(diagnostic.message)),
+    ];
+    if let Some(file) = diagnostic.file {
+        fields.push(format!("\"file\":{}", json_string(file)));
+    }
+    if let Some(line) = diagnostic.line {
+        fields.push(format!("\"line\":{line}"));
+    }
+    if let Some(column) = diagnostic.column {
+        fields.push(format!("\"column\":{column}"));
+    }
+    if let Some(option) = diagnostic.option {
+        fields.push(format!("\"option\":{}", json_string(option)));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            ch if ch.is_control() => result.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => result.push(ch),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostic() {
+        let diagnostic = parse_diagnostic(
+            "clang",
+            "src/a.c:3:7: warning: unused variable 'x' [-Wunused-variable]",
+        );
+        assert_eq!(
+            diagnostic,
+            Diagnostic {
+                tool: "clang",
+                file: Some("src/a.c"),
+                line: Some(3),
+                column: Some(7),
+                severity: "warning",
+                message: "unused variable 'x'",
+                option: Some("-Wunused-variable"),
+            }
+        );
+        assert_eq!(
+            diagnostic_json(&diagnostic),
+            r#"{"tool":"clang","severity":"warning","message":"unused variable 'x'","file":"src/a.c","line":3,"column":7,"option":"-Wunused-variable"}"#
+        );
+
+        let diagnostic = parse_diagnostic("wasm-ld-20", "wasm-ld: error: undefined symbol: foo");
+        assert_eq!(diagnostic.tool, "wasm-ld");
+        assert_eq!(diagnostic.severity, "error");
+        assert_eq!(diagnostic.message, "undefined symbol: foo");
+
+        let diagnostic = parse_diagnostic("clang", "1 warning generated.");
+        assert_eq!(diagnostic.severity, "text");
+        assert_eq!(
+            diagnostic_json(&diagnostic),
+            r#"{"tool":"clang","severity":"text","message":"1 warning generated."}"#
+        );
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a \"b\" \\ c\td"), r#""a \"b\" \\ c\td""#);
+        assert_eq!(json_string("\u{1}"), r#""\u0001""#);
+    }
+}
@@ -0,0 +1,296 @@
+use super::*;
+
+/// The tools `wasixcc doctor` runs with `--version`, and whether a build can
+/// work at all without them.
+const TOOLS: &[(&str, bool)] = &[
+    ("clang", true),
+    ("clang++", true),
+    ("wasm-ld", true),
+    ("llvm-ar", false),
+    ("llvm-nm", false),
+    ("llvm-ranlib", false),
+];
+
+/// The LLVM version wasixcc is built and tested against.
+const EXPECTED_LLVM_VERSION: u32 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    // Something is off, but builds may still work
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Check {
+    name: String,
+    status: Status,
+    // The tool's version line on success, or what went wrong
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_owned(),
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        name: &str,
+        critical: bool,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            status: if critical { Status::Fail } else { Status::Warn },
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+pub(crate) fn run(args: Vec<String>, user_settings: UserSettings) -> Result<()> {
+    if !args.is_empty() {
+        bail!("Unexpected arguments for doctor: {}", args.join(" "));
+    }
+
+    let checks = run_checks(&user_settings);
+    print!("{}", report(&checks));
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{failed} critical check(s) failed");
+    }
+    Ok(())
+}
+
+fn run_checks(user_settings: &UserSettings) -> Vec<Check> {
+    let mut checks = vec![];
+
+    for (tool, critical) in TOOLS {
+        let command = Command::new(user_settings.get_tool_path(tool));
+        let check = check_tool(tool, *critical, command, user_settings);
+        if *tool == "clang" && check.status == Status::Pass {
+            checks.push(check_llvm_version(&check.detail));
+        }
+        checks.push(check);
+    }
+    checks.push(check_tool(
+        "wasm-opt",
+        false,
+        Command::new("wasm-opt"),
+        user_settings,
+    ));
+
+    checks.extend(check_sysroot_contents(user_settings));
+    checks
+}
+
+/// Actually runs the tool, since a tool that exists can still fail to start,
+/// e.g. when a shared library it links against (such as libxml2) is missing.
+fn check_tool(
+    name: &str,
+    critical: bool,
+    mut command: Command,
+    user_settings: &UserSettings,
+) -> Check {
+    command.arg("--version");
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(error) => {
+            let hint = spawn_error(&command, error, user_settings).to_string();
+            return Check::problem(name, critical, "could not be run", hint);
+        }
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        let version = stdout.lines().next().unwrap_or("").trim();
+        return Check::pass(name, version);
+    }
+
+    let error = stderr.lines().next().unwrap_or("").trim();
+    let hint = if stderr.contains("error while loading shared libraries") {
+        "The tool is installed but a shared library it needs is missing; install that \
+        library with your package manager"
+    } else {
+        "The tool is installed but failed to run; try running it directly to see why"
+    };
+    Check::problem(
+        name,
+        critical,
+        format!("exited with {}: {error}", output.status),
+        hint,
+    )
+}
+
+fn check_llvm_version(clang_version_line: &str) -> Check {
+    const NAME: &str = "LLVM version";
+    match features::parse_clang_version(clang_version_line) {
+        Some(version) if version >= EXPECTED_LLVM_VERSION => Check::pass(NAME, version.to_string()),
+        Some(version) => Check::problem(
+            NAME,
+            false,
+            version.to_string(),
+            format!(
+                "wasixcc expects LLVM {EXPECTED_LLVM_VERSION} or newer; older versions may \
+                miss WebAssembly features WASIX relies on"
+            ),
+        ),
+        None => Check::problem(
+            NAME,
+            false,
+            "unknown",
+            "Couldn't read the version from clang --version",
+        ),
+    }
+}
+
+/// Like check_sysroot in the compiler, but reports every missing file instead
+/// of stopping at the first, and doesn't depend on what's being built.
+fn check_sysroot_contents(user_settings: &UserSettings) -> Vec<Check> {
+    let Some(sysroot) = &user_settings.sysroot_location else {
+        return vec![Check::problem(
+            "sysroot",
+            true,
+            "not set",
+            "Set the sysroot with -sSYSROOT=path or the WASIXCC_SYSROOT environment variable",
+        )];
+    };
+    if !sysroot.is_dir() {
+        return vec![Check::problem(
+            "sysroot",
+            true,
+            format!("{sysroot:?} is not a directory"),
+            "Make sure SYSROOT points at a WASIX sysroot",
+        )];
+    }
+
+    let lib_path = sysroot.join("lib").join(&user_settings.target);
+    let files = [
+        ("include", sysroot.join("include"), true, "headers"),
+        ("libc.a", lib_path.join("libc.a"), true, "linking anything"),
+        (
+            "crt1.o",
+            lib_path.join("crt1.o"),
+            true,
+            "linking executables",
+        ),
+        (
+            "scrt1.o",
+            lib_path.join("scrt1.o"),
+            false,
+            "PIC and shared libraries",
+        ),
+        (
+            "libunwind.a",
+            lib_path.join("libunwind.a"),
+            false,
+            "C++ exceptions",
+        ),
+    ];
+
+    let mut checks = vec![Check::pass("sysroot", sysroot.display().to_string())];
+    for (name, path, critical, needed_for) in files {
+        let name = format!("sysroot {name}");
+        if path.exists() {
+            checks.push(Check::pass(&name, path.display().to_string()));
+        } else {
+            checks.push(Check::problem(
+                &name,
+                critical,
+                format!("{path:?} is missing"),
+                format!(
+                    "Needed for {needed_for}; use a sysroot built for {}",
+                    user_settings.target
+                ),
+            ));
+        }
+    }
+    checks
+}
+
+fn report(checks: &[Check]) -> String {
+    let mut report = String::new();
+    for check in checks {
+        let status = match check.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        report.push_str(&format!("{status} {}: {}\n", check.name, check.detail));
+        if let Some(hint) = &check.hint {
+            report.push_str(&format!("     {hint}\n"));
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_doctor_checks() {
+        use crate::compiler::tests::{fake_sysroot, fake_toolchain, test_user_settings};
+
+        let tmp = fake_toolchain(&[
+            ("clang", "echo 'clang version 19.1.7'\n"),
+            ("clang++", "echo 'clang version 19.1.7'\n"),
+            (
+                "wasm-ld",
+                "echo 'wasm-ld: error while loading shared libraries: libxml2.so.2' >&2\nexit 127\n",
+            ),
+        ]);
+
+        let sysroot = fake_sysroot(tmp.path());
+        let mut user_settings = test_user_settings();
+        user_settings.tool_overrides.clear();
+        user_settings.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        user_settings.sysroot_location = Some(sysroot.clone());
+        std::fs::create_dir(sysroot.join("include")).unwrap();
+
+        let checks = run_checks(&user_settings);
+        let status = |name: &str| {
+            checks
+                .iter()
+                .find(|check| check.name == name)
+                .unwrap_or_else(|| panic!("no check named {name}"))
+                .status
+        };
+        assert_eq!(status("clang"), Status::Pass);
+        assert_eq!(status("LLVM version"), Status::Warn);
+        assert_eq!(status("wasm-ld"), Status::Fail);
+        assert_eq!(status("llvm-nm"), Status::Warn);
+        assert_eq!(status("sysroot libc.a"), Status::Pass);
+        assert_eq!(status("sysroot scrt1.o"), Status::Warn);
+
+        let report = report(&checks);
+        assert!(report.contains("PASS clang: clang version 19.1.7\n"));
+        assert!(report.contains("shared library it needs is missing"));
+        assert!(report.contains("LLVM_LOCATION"));
+
+        assert!(run(vec![], user_settings).is_err());
+    }
+
+    #[test]
+    fn test_missing_sysroot() {
+        let mut user_settings = crate::compiler::tests::test_user_settings();
+        user_settings.sysroot_location = None;
+        let checks = check_sysroot_contents(&user_settings);
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].status, Status::Fail);
+    }
+}
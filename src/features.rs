@@ -0,0 +1,145 @@
+use super::*;
+
+/// What the toolchain can build, as reported by `wasixcc --print-features`.
+/// Printed as one line of `key=value` pairs, always in the same order, so build
+/// systems can probe for features without test compiles.
+#[derive(Debug, PartialEq, Eq)]
+struct Capabilities {
+    // Major version of the clang in use, if it could be run
+    llvm_version: Option<u32>,
+    threads: bool,
+    exceptions: ExceptionsMode,
+    pic: bool,
+    simd: bool,
+}
+
+impl Capabilities {
+    fn to_line(&self) -> String {
+        let yes_no = |value: bool| if value { "yes" } else { "no" };
+        let llvm = self
+            .llvm_version
+            .map_or("unknown".to_owned(), |version| version.to_string());
+        let exceptions = match self.exceptions {
+            ExceptionsMode::Wasm => "wasm",
+            ExceptionsMode::Sjlj => "sjlj",
+            ExceptionsMode::None => "none",
+        };
+        format!(
+            "llvm={llvm} threads={} exceptions={exceptions} pic={} simd={}",
+            yes_no(self.threads),
+            yes_no(self.pic),
+            yes_no(self.simd),
+        )
+    }
+}
+
+pub(crate) fn run(args: Vec<String>, user_settings: UserSettings) -> Result<()> {
+    if !args.is_empty() {
+        bail!(
+            "Unexpected arguments for --print-features: {}",
+            args.join(" ")
+        );
+    }
+
+    let llvm_version = detect_llvm_version(&user_settings);
+    println!(
+        "{}",
+        detect_capabilities(&user_settings, llvm_version).to_line()
+    );
+    Ok(())
+}
+
+fn detect_llvm_version(user_settings: &UserSettings) -> Option<u32> {
+    let output = Command::new(user_settings.get_tool_path("clang"))
+        .arg("--version")
+        .output()
+        .ok()?;
+    parse_clang_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Finds the major version in e.g. `Ubuntu clang version 20.1.2 (...)`.
+pub(crate) fn parse_clang_version(output: &str) -> Option<u32> {
+    let (_, rest) = output.split_once("clang version ")?;
+    let major = rest.split(['.', ' ', '\n']).next()?;
+    major.parse().ok()
+}
+
+/// The sysroot decides most of this: everything needs libc, PIC modules need
+/// scrt1.o (see check_sysroot) and C++ exceptions need libunwind. setjmp/longjmp
+/// only needs the compiler.
+fn detect_capabilities(user_settings: &UserSettings, llvm_version: Option<u32>) -> Capabilities {
+    let has_lib = |file: &str| {
+        user_settings
+            .sysroot_location
+            .as_ref()
+            .is_some_and(|sysroot| {
+                sysroot
+                    .join("lib")
+                    .join(&user_settings.target)
+                    .join(file)
+                    .is_file()
+            })
+    };
+    let has_libc = has_lib("libc.a");
+
+    let exceptions = if has_libc && has_lib("libunwind.a") {
+        ExceptionsMode::Wasm
+    } else if has_libc && llvm_version.is_some() {
+        ExceptionsMode::Sjlj
+    } else {
+        ExceptionsMode::None
+    };
+
+    Capabilities {
+        llvm_version,
+        // WASIX's libc always comes with pthreads
+        threads: has_libc,
+        exceptions,
+        pic: has_lib("scrt1.o"),
+        // simd128 is in every clang version that can target WASIX
+        simd: llvm_version.is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_clang_version() {
+        assert_eq!(
+            parse_clang_version("Ubuntu clang version 20.1.2 (0ubuntu1)\nTarget: x86_64\n"),
+            Some(20)
+        );
+        assert_eq!(parse_clang_version("clang version 19\n"), Some(19));
+        assert_eq!(parse_clang_version("gcc (GCC) 14.1.0\n"), None);
+    }
+
+    #[test]
+    fn test_detect_capabilities() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let mut user_settings = crate::compiler::tests::test_user_settings();
+        user_settings.sysroot_location = Some(tmp.path().to_owned());
+
+        let capabilities = detect_capabilities(&user_settings, Some(20));
+        assert_eq!(
+            capabilities.to_line(),
+            "llvm=20 threads=no exceptions=none pic=no simd=yes"
+        );
+
+        let lib_path = tmp.path().join("lib").join(&user_settings.target);
+        std::fs::create_dir_all(&lib_path).unwrap();
+        std::fs::write(lib_path.join("libc.a"), "").unwrap();
+        std::fs::write(lib_path.join("scrt1.o"), "").unwrap();
+        assert_eq!(
+            detect_capabilities(&user_settings, Some(20)).to_line(),
+            "llvm=20 threads=yes exceptions=sjlj pic=yes simd=yes"
+        );
+
+        std::fs::write(lib_path.join("libunwind.a"), "").unwrap();
+        assert_eq!(
+            detect_capabilities(&user_settings, None).to_line(),
+            "llvm=unknown threads=yes exceptions=wasm pic=yes simd=no"
+        );
+    }
+}
@@ -6,11 +6,19 @@ use std::{
     sync::LazyLock,
 };
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
-use crate::compiler::ModuleKind;
+use crate::compiler::{ModuleKind, OptLevel};
 
+mod ar;
+mod batch;
+mod check_link;
 mod compiler;
+mod config;
+mod diagnostics;
+mod doctor;
+mod features;
+mod version_script;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum LlvmLocation {
@@ -30,20 +38,231 @@ impl LlvmLocation {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LinkerMap {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnalyzeMode {
+    Report,
+    // Analyzer findings fail the build
+    Strict,
+}
+
+/// How exceptions and setjmp/longjmp are implemented. Both use wasm exception
+/// handling instructions, so a runtime without them can only run `None` modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExceptionsMode {
+    /// C++ exceptions and setjmp/longjmp
+    Wasm,
+    /// setjmp/longjmp only; C++ is built with -fno-exceptions
+    Sjlj,
+    None,
+}
+
+impl ExceptionsMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wasm" => Some(ExceptionsMode::Wasm),
+            "sjlj" => Some(ExceptionsMode::Sjlj),
+            "none" => Some(ExceptionsMode::None),
+            _ => None,
+        }
+    }
+
+    pub fn uses_wasm_eh(&self) -> bool {
+        matches!(self, ExceptionsMode::Wasm | ExceptionsMode::Sjlj)
+    }
+}
+
+/// Sanitizers that work on WASIX. AddressSanitizer needs runtime support for
+/// shadow memory that wasm32-wasi clang doesn't have, so only UBSan is here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sanitizer {
+    Undefined,
+}
+
+impl Sanitizer {
+    pub fn compile_flag(&self) -> &'static str {
+        match self {
+            Sanitizer::Undefined => "-fsanitize=undefined",
+        }
+    }
+
+    /// The runtime library in the sysroot's lib/<target>, without lib and .a
+    pub fn runtime_library(&self) -> &'static str {
+        match self {
+            Sanitizer::Undefined => "clang_rt.ubsan_standalone",
+        }
+    }
+}
+
+/// A bundle of wasm features to build for; the threads part of it is kept in
+/// `UserSettings::threads`, since individual flags can still turn that off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AbiProfile {
+    /// Plain MVP wasm, for maximum compatibility with other runtimes
+    Mvp,
+    Wasix,
+    WasixThreads,
+}
+
+impl AbiProfile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "mvp" => Some(AbiProfile::Mvp),
+            "wasix" => Some(AbiProfile::Wasix),
+            "wasix-threads" => Some(AbiProfile::WasixThreads),
+            _ => None,
+        }
+    }
+
+    pub fn threads(&self) -> bool {
+        matches!(self, AbiProfile::WasixThreads)
+    }
+
+    /// Features enabled on top of MVP, not counting atomics which come with threads.
+    pub fn base_features(&self) -> &'static [&'static str] {
+        match self {
+            AbiProfile::Mvp => &[],
+            AbiProfile::Wasix | AbiProfile::WasixThreads => &["bulk-memory", "mutable-globals"],
+        }
+    }
+}
+
+/// wasm-ld's `--unresolved-symbols` policies, for IMPORT_UNDEFINED.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnresolvedSymbols {
+    ReportAll,
+    IgnoreAll,
+    /// Undefined symbols become imports, resolved when the module is loaded
+    ImportDynamic,
+}
+
+impl UnresolvedSymbols {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "report-all" => Some(UnresolvedSymbols::ReportAll),
+            "ignore-all" => Some(UnresolvedSymbols::IgnoreAll),
+            "import-dynamic" => Some(UnresolvedSymbols::ImportDynamic),
+            _ => None,
+        }
+    }
+
+    pub fn linker_flag(&self) -> &'static str {
+        match self {
+            UnresolvedSymbols::ReportAll => "--unresolved-symbols=report-all",
+            UnresolvedSymbols::IgnoreAll => "--unresolved-symbols=ignore-all",
+            UnresolvedSymbols::ImportDynamic => "--unresolved-symbols=import-dynamic",
+        }
+    }
+}
+
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+
+/// The maximum memory size for wasm32 modules, which we always link with.
+const MAX_MEMORY: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Feature names wasm-ld accepts in `--features`.
+static WASM_FEATURES: LazyLock<HashSet<&str>> = LazyLock::new(|| {
+    [
+        "atomics",
+        "bulk-memory",
+        "bulk-memory-opt",
+        "call-indirect-overlong",
+        "exception-handling",
+        "extended-const",
+        "fp16",
+        "gc",
+        "memory64",
+        "multimemory",
+        "multivalue",
+        "mutable-globals",
+        "nontrapping-fptoint",
+        "reference-types",
+        "relaxed-simd",
+        "sign-ext",
+        "simd128",
+        "tail-call",
+        "wide-arithmetic",
+    ]
+    .into()
+});
+
 /// Settings provided by user through env vars or -s flags. Some can be overridden by
 /// compiler flags; e.g. `-fno-wasm-exceptions` takes priority over `-sWASM_EXCEPTIONS=1`.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct UserSettings {
     // TODO: implement automatic detection of sysroot kind, e.g. eh+pic vs eh
-    sysroot_location: Option<PathBuf>, // key name: SYSROOT
-    llvm_location: LlvmLocation,       // key name: LLVM_LOCATION
-    extra_compiler_flags: Vec<String>, // key name: COMPILER_FLAGS
-    extra_linker_flags: Vec<String>,   // key name: LINKER_FLAGS
-    run_wasm_opt: Option<bool>,        // key name: RUN_WASM_OPT
-    wasm_opt_flags: Vec<String>,       // key name: WASM_OPT_FLAGS
-    module_kind: Option<ModuleKind>,   // key name: MODULE_KIND
-    wasm_exceptions: bool,             // key name: WASM_EXCEPTIONS
-    pic: bool,                         // key name: PIC
+    sysroot_location: Option<PathBuf>,    // key name: SYSROOT
+    llvm_location: LlvmLocation,          // key name: LLVM_LOCATION
+    extra_compiler_flags: Vec<String>,    // key name: COMPILER_FLAGS
+    extra_linker_flags: Vec<String>,      // key name: LINKER_FLAGS
+    run_wasm_opt: Option<bool>,           // key name: RUN_WASM_OPT
+    wasm_opt_flags: Vec<String>,          // key name: WASM_OPT_FLAGS
+    module_kind: Option<ModuleKind>,      // key name: MODULE_KIND or OUTPUT_FORMAT
+    exceptions_mode: ExceptionsMode,      // key name: EXCEPTIONS_MODE or WASM_EXCEPTIONS
+    setjmp: bool,                         // key name: SETJMP
+    pic: bool,                            // key name: PIC
+    initial_memory: Option<u64>,          // key name: INITIAL_MEMORY
+    abi_profile: AbiProfile,              // key name: ABI_PROFILE
+    threads: bool,                        // key name: THREADS
+    exported_functions: Vec<String>,      // key name: EXPORTED_FUNCTIONS
+    export_all: bool,                     // key name: EXPORT_ALL
+    object_suffix: String,                // key name: OBJECT_SUFFIX
+    thread_stack_size: Option<u64>,       // key name: THREAD_STACK_SIZE
+    repro_script: Option<PathBuf>,        // key name: REPRO_SCRIPT
+    link_features: Option<Vec<String>>,   // key name: LINK_FEATURES
+    ar_deterministic: bool,               // key name: AR_DETERMINISTIC
+    linker_map: Option<LinkerMap>,        // key name: MAP
+    lto_cache: Option<PathBuf>,           // key name: LTO_CACHE_DIR or LTO_CACHE
+    lto_cache_policy: Option<String>,     // key name: LTO_CACHE_POLICY
+    emit_relocs: bool,                    // key name: EMIT_RELOCS
+    assertions: Option<bool>,             // key name: ASSERTIONS
+    exports_from_objects: Vec<PathBuf>,   // key name: EXPORTS_FROM_OBJECT
+    print_config: bool,                   // key name: PRINT_CONFIG
+    strict_aliasing: bool,                // key name: STRICT_ALIASING
+    minimal_exports: bool,                // key name: MINIMAL_EXPORTS
+    analyze: Option<AnalyzeMode>,         // key name: ANALYZE
+    gc_sections: bool,                    // key name: GC_SECTIONS
+    reproducible: bool,                   // key name: REPRODUCIBLE
+    verbose: bool,                        // key name: VERBOSE
+    emit_wat: bool,                       // key name: EMIT_WAT
+    wasm_opt_level: Option<OptLevel>,     // key name: WASM_OPT_LEVEL
+    target: String,                       // key name: TARGET
+    target_features: Option<Vec<String>>, // key name: TARGET_FEATURES
+    standalone: bool,                     // key name: STANDALONE
+    json_diagnostics: bool,               // key name: DIAGNOSTICS
+    save_temps: Option<PathBuf>,          // key name: SAVE_TEMPS
+    sanitizer: Option<Sanitizer>,         // key name: SANITIZER
+    coverage: bool,                       // key name: COVERAGE
+    cxx_stdlib: bool,                     // key name: CXX_STDLIB
+    soname: Option<String>,               // key name: SONAME
+    pgo_generate: bool,                   // key name: PGO_GEN
+    pgo_use: Option<PathBuf>,             // key name: PGO_USE
+    temp_dir: Option<PathBuf>,            // key name: TEMP_DIR
+    simd: bool,                           // key name: SIMD
+    timings: bool,                        // key name: TIMINGS
+    crt1: Option<PathBuf>,                // key name: CRT1
+    no_crt1: bool,                        // key name: NO_CRT1
+    export_table: bool,                   // key name: EXPORT_TABLE
+    import_table: bool,                   // key name: IMPORT_TABLE
+    no_default_flags: bool,               // key name: NO_DEFAULT_FLAGS
+    jobs: usize,                          // key name: JOBS
+    version_script: Option<PathBuf>,      // key name: VERSION_SCRIPT
+    keep_names: bool,                     // key name: KEEP_NAMES
+    extra_lib_dirs: Vec<PathBuf>,         // key name: EXTRA_LIB_DIRS
+    entry: Option<String>,                // key name: ENTRY
+    import_undefined: Option<UnresolvedSymbols>, // key name: IMPORT_UNDEFINED
+    component: bool,                      // key name: COMPONENT
+    adapter: Option<PathBuf>,             // key name: ADAPTER
+    feature_bulk_memory: Option<bool>,    // key name: FEATURE_BULK_MEMORY
+    feature_mutable_globals: Option<bool>, // key name: FEATURE_MUTABLE_GLOBALS
+    lto_jobs: Option<usize>,              // key name: LTO_JOBS
+    // Tool name -> path, read from CC/CXX/LD/WASM_TOOLS; key name: TOOLS_FROM_ENV
+    tool_overrides: HashMap<&'static str, PathBuf>,
 }
 
 impl UserSettings {
@@ -54,24 +273,104 @@ impl UserSettings {
         )
     }
 
+    /// COVERAGE and PGO_GEN both instrument the code and link the profile
+    /// runtime.
+    fn uses_profile_runtime(&self) -> bool {
+        self.coverage || self.pgo_generate
+    }
+
     pub fn module_kind(&self) -> ModuleKind {
         self.module_kind.unwrap_or(ModuleKind::StaticMain)
     }
+
+    pub fn is_memory64(&self) -> bool {
+        self.target.starts_with("wasm64-")
+    }
+
+    /// The ABI profile's features, with any FEATURE_* settings turning them on
+    /// or off individually.
+    pub fn base_features(&self) -> Vec<&'static str> {
+        let mut features = self.abi_profile.base_features().to_vec();
+        for (feature, enabled) in [
+            ("bulk-memory", self.feature_bulk_memory),
+            ("mutable-globals", self.feature_mutable_globals),
+        ] {
+            match enabled {
+                Some(true) if !features.contains(&feature) => features.push(feature),
+                Some(false) => features.retain(|f| *f != feature),
+                _ => (),
+            }
+        }
+        features
+    }
+
+    /// The features to compile with: TARGET_FEATURES if given, otherwise the
+    /// base features plus atomics when building with threads. SIMD=1 adds
+    /// simd128 to either.
+    pub fn compile_features(&self) -> Vec<&str> {
+        let mut features = match &self.target_features {
+            Some(features) => features.iter().map(String::as_str).collect(),
+            None => {
+                let mut features = self.base_features();
+                if self.threads {
+                    features.push("atomics");
+                }
+                if self.is_memory64() {
+                    features.push("memory64");
+                }
+                features
+            }
+        };
+        if self.simd && !features.contains(&"simd128") {
+            features.push("simd128");
+        }
+        features
+    }
+
+    /// Tools overridden through CC/CXX/LD take precedence over LLVM_LOCATION.
+    pub fn get_tool_path(&self, tool: &str) -> PathBuf {
+        match self.tool_overrides.get(tool) {
+            Some(path) => path.clone(),
+            None => self.llvm_location.get_tool_path(tool),
+        }
+    }
+
+    /// Tools that don't come with LLVM, such as wasm-tools, are run from PATH
+    /// unless overridden.
+    pub fn get_external_tool_path(&self, tool: &str) -> PathBuf {
+        match self.tool_overrides.get(tool) {
+            Some(path) => path.clone(),
+            None => PathBuf::from(tool),
+        }
+    }
 }
 
-fn get_args_and_user_settings() -> Result<(Vec<String>, UserSettings)> {
-    let args: Vec<String> = std::env::args().skip(1).collect();
+/// Environment variables that may override individual tools, and the tool
+/// each one replaces.
+const TOOL_OVERRIDE_ENV_VARS: &[(&str, &str)] = &[
+    ("CC", "clang"),
+    ("CXX", "clang++"),
+    ("LD", "wasm-ld"),
+    ("WASM_TOOLS", "wasm-tools"),
+];
+
+fn split_args_and_user_settings(args: Vec<String>) -> Result<(Vec<String>, UserSettings)> {
     let (settings_args, args) = separate_user_settings_args(args);
     let user_settings = gather_user_settings(&settings_args)?;
     Ok((args, user_settings))
 }
 
-fn run_command(mut command: Command) -> Result<()> {
+/// The process arguments, without the executable name.
+fn process_args() -> Vec<String> {
+    std::env::args().skip(1).collect()
+}
+
+fn run_command(mut command: Command, user_settings: &UserSettings) -> Result<()> {
     tracing::info!("Executing build command: {command:?}");
 
     let status = command
         .status()
-        .with_context(|| format!("Failed to run command: {command:?}"))?;
+        .map_err(|error| spawn_error(&command, error, user_settings))?;
     if !status.success() {
         bail!("Command failed with status: {status}; the command was: {command:?}");
     }
@@ -79,111 +378,927 @@ fn run_command(mut command: Command) -> Result<()> {
     Ok(())
 }
 
+/// The error for a command that couldn't be started at all, as opposed to one
+/// that ran and failed. A missing tool gets a message saying where it was looked
+/// for, since the OS error alone doesn't name it.
+fn spawn_error(
+    command: &Command,
+    error: std::io::Error,
+    user_settings: &UserSettings,
+) -> anyhow::Error {
+    if error.kind() != std::io::ErrorKind::NotFound {
+        return anyhow::Error::new(error).context(format!("Failed to run command: {command:?}"));
+    }
+
+    let program = Path::new(command.get_program());
+    let overridden_by = TOOL_OVERRIDE_ENV_VARS.iter().find(|(_, tool)| {
+        user_settings
+            .tool_overrides
+            .get(tool)
+            .is_some_and(|path| path == program)
+    });
+    if let Some((var, _)) = overridden_by {
+        return anyhow!(
+            "Could not find {program:?}, which was set through the {var} environment variable"
+        );
+    }
+
+    // wasm-opt is part of binaryen rather than LLVM, and always comes from PATH
+    if command.get_program() == "wasm-opt" {
+        return anyhow!(
+            "Could not find wasm-opt on PATH; install binaryen, or set RUN_WASM_OPT=0 to \
+            skip running it"
+        );
+    }
+
+    let location = match &user_settings.llvm_location {
+        LlvmLocation::FromPath(path) => format!("in {path:?}"),
+        LlvmLocation::FromSystem(version) => format!("on PATH (as LLVM {version})"),
+    };
+    anyhow!(
+        "Could not find {program:?}; LLVM tools are looked up {location}. Install \
+        the missing tool, or set LLVM_LOCATION (e.g. -sLLVM_LOCATION=/path/to/llvm/bin \
+        or WASIXCC_LLVM_LOCATION) to the directory that contains it"
+    )
+}
+
+fn print_warning(message: &str) {
+    tracing::warn!("{message}");
+    eprintln!("Warning: {message}");
+    #[cfg(test)]
+    WARNINGS.with_borrow_mut(|warnings| warnings.push(message.to_owned()));
+}
+
+#[cfg(test)]
+thread_local! {
+    // Tests run on their own threads, so each only sees its own warnings
+    static WARNINGS: std::cell::RefCell<Vec<String>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// The warnings printed so far on this thread, for tests to check.
+#[cfg(test)]
+fn take_warnings() -> Vec<String> {
+    WARNINGS.take()
+}
+
 fn run_tool_with_passthrough_args(
     tool: &str,
     args: Vec<String>,
     user_settings: UserSettings,
 ) -> Result<()> {
-    let tool_path = user_settings.llvm_location.get_tool_path(tool);
+    let tool_path = user_settings.get_tool_path(tool);
     let mut command = Command::new(tool_path);
     command.args(args);
-    run_command(command)
+    run_command(command, &user_settings)
 }
 
 pub fn run_compiler(run_cxx: bool) -> Result<()> {
+    run_compiler_with_args(process_args(), run_cxx)
+}
+
+/// Like [`run_compiler`], but with the given arguments instead of the process's.
+/// `-s` settings may be mixed in with the compiler arguments.
+pub fn run_compiler_with_args(args: Vec<String>, run_cxx: bool) -> Result<()> {
     tracing::info!("Starting in compiler mode");
 
-    let (args, user_settings) = get_args_and_user_settings()?;
+    let (args, user_settings) = split_args_and_user_settings(args)?;
     compiler::run(args, user_settings, run_cxx)
 }
 
 pub fn run_linker() -> Result<()> {
+    run_linker_with_args(process_args())
+}
+
+pub fn run_linker_with_args(args: Vec<String>) -> Result<()> {
     tracing::info!("Starting in linker mode");
 
-    let (args, user_settings) = get_args_and_user_settings()?;
+    let (args, user_settings) = split_args_and_user_settings(args)?;
     compiler::link_only(args, user_settings)
 }
 
 pub fn run_ar() -> Result<()> {
+    run_ar_with_args(process_args())
+}
+
+pub fn run_ar_with_args(args: Vec<String>) -> Result<()> {
     tracing::info!("Starting in ar mode");
 
-    let (args, user_settings) = get_args_and_user_settings()?;
-    run_tool_with_passthrough_args("llvm-ar", args, user_settings)
+    let (args, user_settings) = split_args_and_user_settings(args)?;
+    ar::run(args, user_settings)
 }
 
 pub fn run_nm() -> Result<()> {
+    run_nm_with_args(process_args())
+}
+
+pub fn run_nm_with_args(args: Vec<String>) -> Result<()> {
     tracing::info!("Starting in nm mode");
 
-    let (args, user_settings) = get_args_and_user_settings()?;
+    let (args, user_settings) = split_args_and_user_settings(args)?;
     run_tool_with_passthrough_args("llvm-nm", args, user_settings)
 }
 
 pub fn run_ranlib() -> Result<()> {
+    run_ranlib_with_args(process_args())
+}
+
+pub fn run_ranlib_with_args(args: Vec<String>) -> Result<()> {
     tracing::info!("Starting in ranlib mode");
 
-    let (args, user_settings) = get_args_and_user_settings()?;
+    let (args, user_settings) = split_args_and_user_settings(args)?;
     run_tool_with_passthrough_args("llvm-ranlib", args, user_settings)
 }
 
+pub fn run_readobj() -> Result<()> {
+    run_readobj_with_args(process_args())
+}
+
+pub fn run_readobj_with_args(args: Vec<String>) -> Result<()> {
+    tracing::info!("Starting in readobj mode");
+
+    let (args, user_settings) = split_args_and_user_settings(args)?;
+    run_tool_with_passthrough_args("llvm-readobj", args, user_settings)
+}
+
+pub fn run_print_features() -> Result<()> {
+    run_print_features_with_args(
+        process_args()
+            .into_iter()
+            .filter(|arg| arg != "--print-features")
+            .collect(),
+    )
+}
+
+/// Takes only user settings, without --print-features itself.
+pub fn run_print_features_with_args(args: Vec<String>) -> Result<()> {
+    tracing::info!("Starting in print-features mode");
+
+    let (args, user_settings) = split_args_and_user_settings(args)?;
+    features::run(args, user_settings)
+}
+
+pub fn run_check_link() -> Result<()> {
+    // Skip the check-link subcommand itself
+    run_check_link_with_args(process_args().into_iter().skip(1).collect())
+}
+
+/// Takes the module paths, without the check-link subcommand.
+pub fn run_check_link_with_args(args: Vec<String>) -> Result<()> {
+    tracing::info!("Starting in check-link mode");
+
+    let (args, user_settings) = split_args_and_user_settings(args)?;
+    check_link::run(args, user_settings)
+}
+
+pub fn run_batch() -> Result<()> {
+    // Skip the batch subcommand itself
+    run_batch_with_args(process_args().into_iter().skip(1).collect())
+}
+
+/// Takes the manifest path and `-s` settings, without the batch subcommand.
+pub fn run_batch_with_args(args: Vec<String>) -> Result<()> {
+    tracing::info!("Starting in batch mode");
+
+    let (settings_args, args) = separate_user_settings_args(args);
+    batch::run(args, settings_args)
+}
+
+pub fn run_doctor() -> Result<()> {
+    // Skip the doctor subcommand itself
+    run_doctor_with_args(process_args().into_iter().skip(1).collect())
+}
+
+/// Takes only user settings, without the doctor subcommand.
+pub fn run_doctor_with_args(args: Vec<String>) -> Result<()> {
+    tracing::info!("Starting in doctor mode");
+
+    let (args, user_settings) = split_args_and_user_settings(args)?;
+    doctor::run(args, user_settings)
+}
+
+/// A symbol as listed by `llvm-nm`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// The nm type letter, e.g. `T` for a global function or `U` for undefined;
+    /// lowercase letters are local symbols.
+    pub kind: char,
+    pub defined: bool,
+}
+
+/// Lists the symbols of an object, archive or module using the llvm-nm from
+/// the settings in the environment and config file.
+pub fn list_symbols(object: &Path) -> Result<Vec<Symbol>> {
+    let user_settings = gather_user_settings(&[])?;
+    list_symbols_with_settings(object, &user_settings)
+}
+
+fn list_symbols_with_settings(object: &Path, user_settings: &UserSettings) -> Result<Vec<Symbol>> {
+    let mut command = Command::new(user_settings.get_tool_path("llvm-nm"));
+    command.arg("--format=posix");
+    command.arg(object);
+
+    let output = command
+        .output()
+        .map_err(|error| spawn_error(&command, error, user_settings))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to read symbols from {object:?}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(parse_nm_symbols(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses `llvm-nm --format=posix` output, where each line is
+/// `name type [value [size]]`. Archive member headers such as `a.o:` are skipped.
+fn parse_nm_symbols(output: &str) -> Vec<Symbol> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?;
+            let mut kind_chars = parts.next()?.chars();
+            let kind = kind_chars.next()?;
+            if kind_chars.next().is_some() {
+                return None;
+            }
+            Some(Symbol {
+                name: name.to_owned(),
+                kind,
+                // w and v are weak undefined symbols
+                defined: !matches!(kind, 'U' | 'w' | 'v'),
+            })
+        })
+        .collect()
+}
+
 fn separate_user_settings_args(args: Vec<String>) -> (Vec<String>, Vec<String>) {
     args.into_iter()
         .partition(|arg| arg.starts_with("-s") && arg.contains('='))
 }
 
 fn gather_user_settings(args: &[String]) -> Result<UserSettings> {
-    let llvm_location = match try_get_user_setting_value("LLVM_LOCATION", args)? {
+    let config = config::load_config_file()?;
+    gather_user_settings_with_config(args, &config)
+}
+
+fn gather_user_settings_with_config(
+    args: &[String],
+    config: &HashMap<String, String>,
+) -> Result<UserSettings> {
+    let llvm_location = match try_get_user_setting_value("LLVM_LOCATION", args, config)? {
         Some(path) => LlvmLocation::FromPath(path.into()),
         None => LlvmLocation::FromSystem(20),
     };
 
-    let sysroot_location = try_get_user_setting_value("SYSROOT", args)?;
+    let sysroot_location = try_get_user_setting_value("SYSROOT", args, config)?;
+
+    let extra_compiler_flags = match try_get_user_setting_value("COMPILER_FLAGS", args, config)? {
+        Some(flags) => read_string_list_user_setting(&flags),
+        None => vec![],
+    };
+
+    let extra_linker_flags = match try_get_user_setting_value("LINKER_FLAGS", args, config)? {
+        Some(flags) => read_string_list_user_setting(&flags),
+        None => vec![],
+    };
+
+    let run_wasm_opt = match try_get_user_setting_value("RUN_WASM_OPT", args, config)? {
+        Some(value) => Some(
+            read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for RUN_WASM_OPT"))?,
+        ),
+        None => None,
+    };
 
-    let extra_compiler_flags = match try_get_user_setting_value("COMPILER_FLAGS", args)? {
+    let wasm_opt_flags = match try_get_user_setting_value("WASM_OPT_FLAGS", args, config)? {
         Some(flags) => read_string_list_user_setting(&flags),
         None => vec![],
     };
 
-    let extra_linker_flags = match try_get_user_setting_value("LINKER_FLAGS", args)? {
-        Some(flags) => read_string_list_user_setting(&flags),
-        None => vec![],
+    let module_kind = match try_get_user_setting_value("MODULE_KIND", args, config)? {
+        Some(kind) => Some(match kind.as_str() {
+            "static-main" => ModuleKind::StaticMain,
+            "dynamic-main" => ModuleKind::DynamicMain,
+            "shared-library" => ModuleKind::SharedLibrary,
+            "object-file" => ModuleKind::ObjectFile,
+            "archive" => ModuleKind::Archive,
+            "relocatable" => ModuleKind::Relocatable,
+            "auto" => ModuleKind::Auto,
+            _ => bail!("Unknown module kind: {}", kind),
+        }),
+        None => None, // Default to static main
+    };
+
+    // A friendlier way to say what to produce; the exact kind of wasm module
+    // can still come from MODULE_KIND or -shared etc.
+    let module_kind = match try_get_user_setting_value("OUTPUT_FORMAT", args, config)? {
+        Some(format) => match (format.as_str(), module_kind) {
+            ("object", None | Some(ModuleKind::ObjectFile)) => Some(ModuleKind::ObjectFile),
+            ("object", Some(ModuleKind::Relocatable)) => Some(ModuleKind::Relocatable),
+            ("wasm", None) => Some(ModuleKind::StaticMain),
+            ("wasm", Some(kind)) if kind.is_binary() => Some(kind),
+            ("archive", None | Some(ModuleKind::Archive)) => Some(ModuleKind::Archive),
+            ("object" | "wasm" | "archive", Some(kind)) => {
+                bail!("OUTPUT_FORMAT={format} conflicts with MODULE_KIND={kind:?}")
+            }
+            _ => bail!("Unknown output format: {format}; expected object, archive or wasm"),
+        },
+        None => module_kind,
+    };
+
+    let exceptions_mode = match try_get_user_setting_value("EXCEPTIONS_MODE", args, config)? {
+        Some(value) => Some(ExceptionsMode::from_name(&value).with_context(|| {
+            format!("Invalid value {value} for EXCEPTIONS_MODE; expected wasm, sjlj or none")
+        })?),
+        None => None,
+    };
+
+    let wasm_exceptions = match try_get_user_setting_value("WASM_EXCEPTIONS", args, config)? {
+        Some(value) => {
+            let wasm_exceptions = read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for WASM_EXCEPTIONS"))?;
+            print_warning(
+                "WASM_EXCEPTIONS is deprecated; use EXCEPTIONS_MODE=wasm or none instead",
+            );
+            Some(wasm_exceptions)
+        }
+        None => None,
+    };
+
+    // C++ exceptions are implemented on top of wasm exceptions, so this is
+    // just a more discoverable way of turning those on
+    let cxx_exceptions = match try_get_user_setting_value("CXX_EXCEPTIONS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for CXX_EXCEPTIONS"))?,
+        None => false,
+    };
+
+    let wasm_exceptions = match (wasm_exceptions, cxx_exceptions) {
+        (Some(false), true) => {
+            bail!("CXX_EXCEPTIONS=1 requires wasm exceptions, but WASM_EXCEPTIONS=0 was given")
+        }
+        (_, true) => Some(true),
+        (wasm_exceptions, false) => wasm_exceptions,
+    };
+
+    let exceptions_mode = match (exceptions_mode, wasm_exceptions) {
+        (Some(mode), None) => mode,
+        (Some(ExceptionsMode::Wasm), Some(true)) => ExceptionsMode::Wasm,
+        (Some(mode @ (ExceptionsMode::Sjlj | ExceptionsMode::None)), Some(false)) => mode,
+        (Some(mode), Some(_)) => bail!(
+            "EXCEPTIONS_MODE={mode:?} conflicts with the given WASM_EXCEPTIONS or \
+            CXX_EXCEPTIONS setting"
+        ),
+        (None, Some(true)) => ExceptionsMode::Wasm,
+        (None, Some(false) | None) => ExceptionsMode::None,
+    };
+
+    // setjmp/longjmp support can be on along with C++ exceptions; it's only
+    // implied by EXCEPTIONS_MODE=sjlj, since native EH doesn't need it
+    let setjmp = match try_get_user_setting_value("SETJMP", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for SETJMP"))?,
+        None => exceptions_mode == ExceptionsMode::Sjlj,
+    };
+    let exceptions_mode = match (exceptions_mode, setjmp) {
+        (ExceptionsMode::Sjlj, false) => bail!("EXCEPTIONS_MODE=sjlj conflicts with SETJMP=0"),
+        (ExceptionsMode::None, true) => ExceptionsMode::Sjlj,
+        (mode, _) => mode,
+    };
+
+    let pic = match try_get_user_setting_value("PIC", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for PIC"))?,
+        None => false,
+    };
+
+    let target = match try_get_user_setting_value("TARGET", args, config)? {
+        Some(target) => {
+            if !(target.starts_with("wasm32-") || target.starts_with("wasm64-")) {
+                bail!(
+                    "Invalid value {target} for TARGET; expected a wasm32 or wasm64 \
+                    triple, such as wasm32-wasi"
+                );
+            }
+            target
+        }
+        None => "wasm32-wasi".to_owned(),
+    };
+    let memory64 = target.starts_with("wasm64-");
+
+    let initial_memory = match try_get_user_setting_value("INITIAL_MEMORY", args, config)? {
+        Some(value) => {
+            let size = read_byte_size_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for INITIAL_MEMORY"))?;
+            if size % WASM_PAGE_SIZE != 0 {
+                bail!(
+                    "INITIAL_MEMORY must be a multiple of the wasm page size \
+                    ({WASM_PAGE_SIZE} bytes), given {size}"
+                );
+            }
+            if !memory64 && size > MAX_MEMORY {
+                bail!("INITIAL_MEMORY must be at most {MAX_MEMORY} bytes, given {size}");
+            }
+            Some(size)
+        }
+        None => None,
+    };
+
+    let threads = match try_get_user_setting_value("THREADS", args, config)? {
+        Some(value) => Some(
+            read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for THREADS"))?,
+        ),
+        None => None,
+    };
+
+    // Atomics are only used together with shared memory, so FEATURE_ATOMICS is
+    // the same switch as THREADS
+    let feature_atomics = match try_get_user_setting_value("FEATURE_ATOMICS", args, config)? {
+        Some(value) => Some(
+            read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for FEATURE_ATOMICS"))?,
+        ),
+        None => None,
+    };
+    let threads = match (threads, feature_atomics) {
+        (Some(threads), Some(atomics)) if threads != atomics => bail!(
+            "FEATURE_ATOMICS={} conflicts with THREADS={}, since shared memory needs atomics",
+            u8::from(atomics),
+            u8::from(threads)
+        ),
+        (threads, atomics) => threads.or(atomics),
+    };
+
+    let feature_bulk_memory = match try_get_user_setting_value("FEATURE_BULK_MEMORY", args, config)?
+    {
+        Some(value) => Some(
+            read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for FEATURE_BULK_MEMORY"))?,
+        ),
+        None => None,
+    };
+    let feature_mutable_globals =
+        match try_get_user_setting_value("FEATURE_MUTABLE_GLOBALS", args, config)? {
+            Some(value) => {
+                Some(read_bool_user_setting(&value).with_context(|| {
+                    format!("Invalid value {value} for FEATURE_MUTABLE_GLOBALS")
+                })?)
+            }
+            None => None,
+        };
+
+    let standalone = match try_get_user_setting_value("STANDALONE", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for STANDALONE"))?,
+        None => false,
+    };
+
+    let (abi_profile, threads) = match try_get_user_setting_value("ABI_PROFILE", args, config)? {
+        Some(name) => {
+            let profile = AbiProfile::from_name(&name).with_context(|| {
+                format!("Unknown ABI_PROFILE {name}; expected one of mvp, wasix or wasix-threads")
+            })?;
+            if threads.is_some_and(|threads| threads != profile.threads()) {
+                bail!(
+                    "THREADS={} conflicts with ABI_PROFILE={name}",
+                    u8::from(!profile.threads())
+                );
+            }
+            (profile, profile.threads())
+        }
+        // THREADS=0 on its own keeps the rest of the WASIX feature set
+        None => match threads {
+            Some(false) => (AbiProfile::Wasix, false),
+            None if standalone || feature_bulk_memory == Some(false) => (AbiProfile::Wasix, false),
+            _ => (AbiProfile::WasixThreads, true),
+        },
+    };
+
+    if standalone && threads {
+        bail!(
+            "STANDALONE=1 can't be used with threads, since a standalone module's \
+            memory isn't shared; set THREADS=0 or ABI_PROFILE=wasix"
+        );
+    }
+
+    if threads && feature_bulk_memory == Some(false) {
+        bail!(
+            "FEATURE_BULK_MEMORY=0 can't be used with threads, since shared memory \
+            requires bulk-memory; set THREADS=0 as well"
+        );
+    }
+
+    let target_features = match try_get_user_setting_value("TARGET_FEATURES", args, config)? {
+        Some(_) if feature_bulk_memory.is_some() || feature_mutable_globals.is_some() => bail!(
+            "FEATURE_BULK_MEMORY and FEATURE_MUTABLE_GLOBALS can't be combined with \
+            TARGET_FEATURES, which already lists every feature"
+        ),
+        Some(features) => {
+            let mut features = read_string_list_user_setting(&features);
+            if let Some(unknown) = features
+                .iter()
+                .find(|f| !WASM_FEATURES.contains(f.as_str()))
+            {
+                bail!("Unknown wasm feature {unknown} in TARGET_FEATURES");
+            }
+            let has_feature = |name: &str| features.iter().any(|f| f == name);
+            if threads && !(has_feature("atomics") && has_feature("bulk-memory")) {
+                bail!(
+                    "TARGET_FEATURES must include atomics and bulk-memory when THREADS \
+                    is enabled, since shared memory requires them"
+                );
+            }
+            if !threads && has_feature("atomics") {
+                bail!("TARGET_FEATURES includes atomics, which requires THREADS to be enabled");
+            }
+            if memory64 && !has_feature("memory64") {
+                features.push("memory64".to_owned());
+            }
+            Some(features)
+        }
+        None => None,
+    };
+
+    let exported_functions = match try_get_user_setting_value("EXPORTED_FUNCTIONS", args, config)? {
+        Some(names) => read_string_list_user_setting(&names),
+        None => vec![],
+    };
+
+    let export_all = match try_get_user_setting_value("EXPORT_ALL", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for EXPORT_ALL"))?,
+        None => false,
+    };
+
+    let object_suffix = match try_get_user_setting_value("OBJECT_SUFFIX", args, config)? {
+        Some(suffix) => {
+            let is_valid = suffix.strip_prefix('.').is_some_and(|rest| {
+                !rest.is_empty()
+                    && rest
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '.' | '_' | '-'))
+            });
+            if !is_valid {
+                bail!(
+                    "Invalid value {suffix} for OBJECT_SUFFIX; expected a file extension \
+                    starting with '.', such as .o or .wasm.o"
+                );
+            }
+            suffix
+        }
+        None => ".o".to_owned(),
+    };
+
+    let thread_stack_size = match try_get_user_setting_value("THREAD_STACK_SIZE", args, config)? {
+        Some(value) => {
+            let size = read_byte_size_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for THREAD_STACK_SIZE"))?;
+            if size == 0 || size % 16 != 0 {
+                bail!("THREAD_STACK_SIZE must be a non-zero multiple of 16, given {size}");
+            }
+            if size > MAX_MEMORY {
+                bail!("THREAD_STACK_SIZE must be at most {MAX_MEMORY} bytes, given {size}");
+            }
+            Some(size)
+        }
+        None => None,
+    };
+
+    let repro_script = try_get_user_setting_value("REPRO_SCRIPT", args, config)?.map(PathBuf::from);
+
+    let simd = match try_get_user_setting_value("SIMD", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for SIMD"))?,
+        None => false,
+    };
+
+    let timings = match try_get_user_setting_value("TIMINGS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for TIMINGS"))?,
+        None => false,
+    };
+
+    // Replaces crt1.o or scrt1.o, whichever the module would link
+    let crt1 = match try_get_user_setting_value("CRT1", args, config)? {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            if !path.is_file() {
+                bail!("The startfile {path:?} given in CRT1 doesn't exist");
+            }
+            if !is_object_file(&path) {
+                bail!("The startfile {path:?} given in CRT1 isn't a wasm object file");
+            }
+            Some(path)
+        }
+        None => None,
+    };
+
+    let no_crt1 = match try_get_user_setting_value("NO_CRT1", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for NO_CRT1"))?,
+        None => false,
+    };
+    if no_crt1 && crt1.is_some() {
+        bail!("CRT1 and NO_CRT1 can't be used together");
+    }
+
+    let export_table = match try_get_user_setting_value("EXPORT_TABLE", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for EXPORT_TABLE"))?,
+        None => false,
+    };
+
+    let import_table = match try_get_user_setting_value("IMPORT_TABLE", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for IMPORT_TABLE"))?,
+        None => false,
+    };
+    if export_table && import_table {
+        bail!("EXPORT_TABLE and IMPORT_TABLE can't be used together");
+    }
+
+    let no_default_flags = match try_get_user_setting_value("NO_DEFAULT_FLAGS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for NO_DEFAULT_FLAGS"))?,
+        None => false,
+    };
+
+    // Only used by `wasixcc batch`
+    let jobs = match try_get_user_setting_value("JOBS", args, config)? {
+        Some(value) => match value.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => jobs,
+            _ => bail!("Invalid value {value} for JOBS; expected a positive number"),
+        },
+        None => 1,
+    };
+
+    let version_script =
+        try_get_user_setting_value("VERSION_SCRIPT", args, config)?.map(PathBuf::from);
+
+    let keep_names = match try_get_user_setting_value("KEEP_NAMES", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for KEEP_NAMES"))?,
+        None => false,
+    };
+
+    let extra_lib_dirs = match try_get_user_setting_value("EXTRA_LIB_DIRS", args, config)? {
+        Some(dirs) => read_string_list_user_setting(&dirs)
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+        None => vec![],
+    };
+
+    let entry = match try_get_user_setting_value("ENTRY", args, config)? {
+        Some(value) if value.is_empty() || value.contains(char::is_whitespace) => {
+            bail!("Invalid value {value:?} for ENTRY; expected a symbol name")
+        }
+        value => value,
+    };
+
+    let import_undefined = match try_get_user_setting_value("IMPORT_UNDEFINED", args, config)? {
+        Some(name) => Some(UnresolvedSymbols::from_name(&name).with_context(|| {
+            format!(
+                "Unknown IMPORT_UNDEFINED {name}; expected one of report-all, ignore-all \
+                or import-dynamic"
+            )
+        })?),
+        None => None,
+    };
+
+    let component = match try_get_user_setting_value("COMPONENT", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for COMPONENT"))?,
+        None => false,
+    };
+    let adapter = try_get_user_setting_value("ADAPTER", args, config)?.map(PathBuf::from);
+    match (component, &adapter) {
+        (true, None) => bail!(
+            "COMPONENT=1 needs an adapter for the module's WASI imports; set it with \
+            -sADAPTER=path"
+        ),
+        (false, Some(_)) => bail!("ADAPTER only applies with COMPONENT=1"),
+        _ => (),
+    }
+
+    let lto_jobs = match try_get_user_setting_value("LTO_JOBS", args, config)? {
+        Some(value) => match value.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => Some(jobs),
+            _ => bail!("Invalid value {value} for LTO_JOBS; expected a positive number"),
+        },
+        None => None,
+    };
+
+    let link_features = match try_get_user_setting_value("LINK_FEATURES", args, config)? {
+        Some(features) => {
+            let features = read_string_list_user_setting(&features);
+            if let Some(unknown) = features
+                .iter()
+                .find(|f| !WASM_FEATURES.contains(f.as_str()))
+            {
+                bail!("Unknown wasm feature {unknown} in LINK_FEATURES");
+            }
+            if threads && !features.iter().any(|f| f == "atomics") {
+                bail!(
+                    "LINK_FEATURES must include atomics when THREADS is enabled, \
+                    since shared memory requires it"
+                );
+            }
+            if simd && !features.iter().any(|f| f == "simd128") {
+                bail!("LINK_FEATURES must include simd128 when SIMD is enabled");
+            }
+            Some(features)
+        }
+        None => None,
+    };
+
+    let reproducible = match try_get_user_setting_value("REPRODUCIBLE", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for REPRODUCIBLE"))?,
+        None => false,
+    };
+
+    let ar_deterministic = match try_get_user_setting_value("AR_DETERMINISTIC", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for AR_DETERMINISTIC"))?,
+        None => reproducible,
+    };
+
+    // MAP=1 prints the map to stdout, anything else is a path to write it to
+    let linker_map = match try_get_user_setting_value("MAP", args, config)? {
+        Some(value) => match read_bool_user_setting(&value) {
+            Some(true) => Some(LinkerMap::Stdout),
+            Some(false) => None,
+            None => Some(LinkerMap::File(PathBuf::from(value))),
+        },
+        None => None,
+    };
+
+    let lto_cache = match try_get_user_setting_value("LTO_CACHE_DIR", args, config)? {
+        Some(dir) => Some(dir),
+        None => try_get_user_setting_value("LTO_CACHE", args, config)?,
+    }
+    .map(PathBuf::from);
+
+    // Passed as-is to --thinlto-cache-policy, e.g. cache_size=10%:prune_interval=20m
+    let lto_cache_policy = try_get_user_setting_value("LTO_CACHE_POLICY", args, config)?;
+    if lto_cache_policy.is_some() && lto_cache.is_none() {
+        bail!("LTO_CACHE_POLICY requires LTO_CACHE_DIR to be set");
+    }
+
+    // Keeps wasm-ld's reloc.* sections in the module. wasm-opt is then skipped
+    // entirely, at any -O level, since every binaryen pass re-encodes the code
+    // section and drops the relocations; no pass is reloc-safe. The module is
+    // left as wasm-ld wrote it, with a warning if it would have been optimized.
+    let emit_relocs = match try_get_user_setting_value("EMIT_RELOCS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for EMIT_RELOCS"))?,
+        None => false,
+    };
+
+    let assertions = match try_get_user_setting_value("ASSERTIONS", args, config)? {
+        Some(value) => Some(
+            read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for ASSERTIONS"))?,
+        ),
+        None => None,
+    };
+
+    let exports_from_objects =
+        match try_get_user_setting_value("EXPORTS_FROM_OBJECT", args, config)? {
+            Some(paths) => read_string_list_user_setting(&paths)
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            None => vec![],
+        };
+
+    let print_config = match try_get_user_setting_value("PRINT_CONFIG", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for PRINT_CONFIG"))?,
+        None => false,
+    };
+
+    let strict_aliasing = match try_get_user_setting_value("STRICT_ALIASING", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for STRICT_ALIASING"))?,
+        None => true,
+    };
+
+    let minimal_exports = match try_get_user_setting_value("MINIMAL_EXPORTS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for MINIMAL_EXPORTS"))?,
+        None => false,
+    };
+
+    let analyze = match try_get_user_setting_value("ANALYZE", args, config)? {
+        Some(value) if value == "strict" => Some(AnalyzeMode::Strict),
+        Some(value) => match read_bool_user_setting(&value) {
+            Some(true) => Some(AnalyzeMode::Report),
+            Some(false) => None,
+            None => bail!("Invalid value {value} for ANALYZE; expected 0, 1 or strict"),
+        },
+        None => None,
+    };
+
+    let gc_sections = match try_get_user_setting_value("GC_SECTIONS", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for GC_SECTIONS"))?,
+        None => false,
+    };
+
+    let verbose = match try_get_user_setting_value("VERBOSE", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for VERBOSE"))?,
+        None => false,
+    };
+
+    let emit_wat = match try_get_user_setting_value("EMIT_WAT", args, config)? {
+        Some(value) => read_bool_user_setting(&value)
+            .with_context(|| format!("Invalid value {value} for EMIT_WAT"))?,
+        None => false,
     };
 
-    let run_wasm_opt = match try_get_user_setting_value("RUN_WASM_OPT", args)? {
-        Some(value) => Some(
-            read_bool_user_setting(&value)
-                .with_context(|| format!("Invalid value {value} for RUN_WASM_OPT"))?,
-        ),
-        None => None,
+    let json_diagnostics = match try_get_user_setting_value("DIAGNOSTICS", args, config)? {
+        Some(value) if value == "json" => true,
+        Some(value) if value == "text" => false,
+        Some(value) => bail!("Invalid value {value} for DIAGNOSTICS; expected json or text"),
+        None => false,
     };
 
-    let wasm_opt_flags = match try_get_user_setting_value("WASM_OPT_FLAGS", args)? {
-        Some(flags) => read_string_list_user_setting(&flags),
-        None => vec![],
-    };
+    let save_temps = try_get_user_setting_value("SAVE_TEMPS", args, config)?.map(PathBuf::from);
 
-    let module_kind = match try_get_user_setting_value("MODULE_KIND", args)? {
-        Some(kind) => Some(match kind.as_str() {
-            "static-main" => ModuleKind::StaticMain,
-            "dynamic-main" => ModuleKind::DynamicMain,
-            "shared-library" => ModuleKind::SharedLibrary,
-            "object-file" => ModuleKind::ObjectFile,
-            _ => bail!("Unknown module kind: {}", kind),
+    let sanitizer = match try_get_user_setting_value("SANITIZER", args, config)? {
+        Some(value) => Some(match value.as_str() {
+            "undefined" => Sanitizer::Undefined,
+            "address" => bail!("SANITIZER=address is not supported on WASIX; only undefined is"),
+            _ => bail!("Unknown sanitizer {value}; only undefined is supported"),
         }),
-        None => None, // Default to static main
+        None => None,
     };
 
-    let wasm_exceptions = match try_get_user_setting_value("WASM_EXCEPTIONS", args)? {
+    let coverage = match try_get_user_setting_value("COVERAGE", args, config)? {
         Some(value) => read_bool_user_setting(&value)
-            .with_context(|| format!("Invalid value {value} for WASM_EXCEPTIONS"))?,
+            .with_context(|| format!("Invalid value {value} for COVERAGE"))?,
         None => false,
     };
 
-    let pic = match try_get_user_setting_value("PIC", args)? {
+    let cxx_stdlib = match try_get_user_setting_value("CXX_STDLIB", args, config)? {
+        Some(value) if value == "libc++" => true,
+        Some(value) if value == "none" => false,
+        Some(value) => bail!("Invalid value {value} for CXX_STDLIB; expected libc++ or none"),
+        None => true,
+    };
+
+    let soname = match try_get_user_setting_value("SONAME", args, config)? {
+        Some(value) if value.is_empty() || value.contains('/') => {
+            bail!("Invalid value {value:?} for SONAME; expected a file name such as libfoo.so")
+        }
+        value => value,
+    };
+
+    let pgo_generate = match try_get_user_setting_value("PGO_GEN", args, config)? {
         Some(value) => read_bool_user_setting(&value)
-            .with_context(|| format!("Invalid value {value} for PIC"))?,
+            .with_context(|| format!("Invalid value {value} for PGO_GEN"))?,
         None => false,
     };
 
+    let pgo_use = try_get_user_setting_value("PGO_USE", args, config)?.map(PathBuf::from);
+    if pgo_generate && pgo_use.is_some() {
+        bail!("PGO_GEN and PGO_USE can't be used together");
+    }
+
+    let temp_dir = try_get_user_setting_value("TEMP_DIR", args, config)?.map(PathBuf::from);
+
+    // Accepts 2, O2 or -O2
+    let wasm_opt_level = match try_get_user_setting_value("WASM_OPT_LEVEL", args, config)? {
+        Some(value) => {
+            let suffix = value.trim_start_matches('-').trim_start_matches('O');
+            Some(OptLevel::from_suffix(suffix).with_context(|| {
+                format!("Invalid value {value} for WASM_OPT_LEVEL; expected one of 0-4, s or z")
+            })?)
+        }
+        None => None,
+    };
+
+    let tool_overrides = match try_get_user_setting_value("TOOLS_FROM_ENV", args, config)? {
+        Some(value) => {
+            let enabled = read_bool_user_setting(&value)
+                .with_context(|| format!("Invalid value {value} for TOOLS_FROM_ENV"))?;
+            if enabled {
+                gather_tool_overrides(|name| std::env::var(name).ok())
+            } else {
+                HashMap::new()
+            }
+        }
+        None => HashMap::new(),
+    };
+
     Ok(UserSettings {
         sysroot_location: sysroot_location.map(Into::into),
         llvm_location,
@@ -192,11 +1307,102 @@ fn gather_user_settings(args: &[String]) -> Result<UserSettings> {
         run_wasm_opt,
         wasm_opt_flags,
         module_kind,
-        wasm_exceptions,
+        exceptions_mode,
+        setjmp,
         pic,
+        initial_memory,
+        abi_profile,
+        threads,
+        exported_functions,
+        export_all,
+        object_suffix,
+        thread_stack_size,
+        repro_script,
+        link_features,
+        ar_deterministic,
+        linker_map,
+        lto_cache,
+        lto_cache_policy,
+        emit_relocs,
+        assertions,
+        exports_from_objects,
+        print_config,
+        strict_aliasing,
+        minimal_exports,
+        analyze,
+        gc_sections,
+        reproducible,
+        verbose,
+        emit_wat,
+        wasm_opt_level,
+        target,
+        target_features,
+        standalone,
+        json_diagnostics,
+        save_temps,
+        sanitizer,
+        coverage,
+        cxx_stdlib,
+        soname,
+        pgo_generate,
+        pgo_use,
+        temp_dir,
+        simd,
+        timings,
+        crt1,
+        no_crt1,
+        export_table,
+        import_table,
+        no_default_flags,
+        jobs,
+        version_script,
+        keep_names,
+        extra_lib_dirs,
+        entry,
+        import_undefined,
+        component,
+        adapter,
+        feature_bulk_memory,
+        feature_mutable_globals,
+        lto_jobs,
+        tool_overrides,
     })
 }
 
+fn gather_tool_overrides(
+    get_env: impl Fn(&str) -> Option<String>,
+) -> HashMap<&'static str, PathBuf> {
+    let mut result = HashMap::new();
+
+    for (env_name, tool) in TOOL_OVERRIDE_ENV_VARS {
+        let Some(value) = get_env(env_name).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let path = PathBuf::from(value);
+
+        // Build systems commonly set CC=wasixcc, in which case we'd end up
+        // invoking ourselves forever.
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("wasix"))
+        {
+            print_warning(&format!(
+                "Ignoring {env_name}={path:?} since it points to wasixcc itself"
+            ));
+            continue;
+        }
+
+        result.insert(*tool, path);
+    }
+
+    result
+}
+
+/// Splits a list setting such as COMPILER_FLAGS on `:`. Each entry is passed on
+/// as a single argument, spaces included, so `-DMSG=hello world` needs no
+/// quoting; only surrounding whitespace is trimmed. `\:` is a literal colon and
+/// `\\` a literal backslash; any other backslash is kept as is.
 fn read_string_list_user_setting(value: &str) -> Vec<String> {
     let mut result = Vec::new();
     let mut current = String::new();
@@ -214,6 +1420,7 @@ fn read_string_list_user_setting(value: &str) -> Vec<String> {
         match ch {
             '\\' => match chars.next() {
                 Some(':') => current.push(':'),
+                Some('\\') => current.push('\\'),
                 Some(ch) => {
                     current.push('\\');
                     current.push(ch);
@@ -232,6 +1439,14 @@ fn read_string_list_user_setting(value: &str) -> Vec<String> {
     result
 }
 
+/// Whether the file starts like a wasm module or LLVM bitcode (for LTO) does.
+fn is_object_file(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .is_ok_and(|()| magic == *b"\0asm" || magic == *b"BC\xC0\xDE")
+}
+
 fn read_bool_user_setting(value: &str) -> Option<bool> {
     match value.to_lowercase().as_str() {
         "1" | "true" | "yes" => Some(true),
@@ -240,10 +1455,27 @@ fn read_bool_user_setting(value: &str) -> Option<bool> {
     }
 }
 
-fn try_get_user_setting_value(name: &str, args: &[String]) -> Result<Option<String>> {
+/// Reads a byte count, optionally suffixed with K, M or G (powers of 1024).
+fn read_byte_size_user_setting(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Settings given as -s flags take priority over `WASIXCC_*` env vars, which
+/// take priority over the config file.
+fn try_get_user_setting_value(
+    name: &str,
+    args: &[String],
+    config: &HashMap<String, String>,
+) -> Result<Option<String>> {
     for arg in args {
-        if arg.starts_with(&format!("-s{}=", name)) {
-            let value = arg.split('=').nth(1).unwrap();
+        if let Some(value) = arg.strip_prefix(&format!("-s{}=", name)) {
             return Ok(Some(value.to_owned()));
         }
     }
@@ -253,7 +1485,7 @@ fn try_get_user_setting_value(name: &str, args: &[String]) -> Result<Option<Stri
         return Ok(Some(env_value));
     }
 
-    Ok(None)
+    Ok(config.get(name).cloned())
 }
 
 #[cfg(test)]
@@ -268,6 +1500,26 @@ mod tests {
         let value = "a:b\\:c:d";
         let list = read_string_list_user_setting(value);
         assert_eq!(list, vec!["a", "b:c", "d"]);
+
+        assert_eq!(read_string_list_user_setting("a\\\\b:c"), vec!["a\\b", "c"]);
+        // A trailing backslash, escaped or not, is kept
+        assert_eq!(
+            read_string_list_user_setting("a\\\\:b\\"),
+            vec!["a\\", "b\\"]
+        );
+        assert_eq!(read_string_list_user_setting("a\\\\\\:b"), vec!["a\\:b"]);
+
+        let list = read_string_list_user_setting("-DMSG=\"hello world\": -Wl,-rpath\\\\:/lib ");
+        assert_eq!(list, vec!["-DMSG=\"hello world\"", "-Wl,-rpath\\", "/lib"]);
+
+        // Every entry survives being escaped and joined back together
+        let entries = ["-DMSG=hello world", "C:\\dir\\", "-Wl,-rpath:/lib", "a\\:b"];
+        let joined = entries
+            .iter()
+            .map(|entry| entry.replace('\\', "\\\\").replace(':', "\\:"))
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(read_string_list_user_setting(&joined), entries);
     }
 
     #[test]
@@ -281,6 +1533,600 @@ mod tests {
         assert_eq!(read_bool_user_setting("invalid"), None);
     }
 
+    #[test]
+    fn test_read_byte_size_user_setting() {
+        assert_eq!(read_byte_size_user_setting("65536"), Some(65536));
+        assert_eq!(read_byte_size_user_setting("64K"), Some(65536));
+        assert_eq!(read_byte_size_user_setting("16m"), Some(16 * 1024 * 1024));
+        assert_eq!(read_byte_size_user_setting("4G"), Some(MAX_MEMORY));
+        assert_eq!(read_byte_size_user_setting("M"), None);
+        assert_eq!(read_byte_size_user_setting("12Q"), None);
+    }
+
+    #[test]
+    fn test_initial_memory_validation() {
+        env::remove_var("WASIXCC_INITIAL_MEMORY");
+        let settings = gather_user_settings(&["-sINITIAL_MEMORY=16M".to_string()]).unwrap();
+        assert_eq!(settings.initial_memory, Some(16 * 1024 * 1024));
+        assert!(gather_user_settings(&["-sINITIAL_MEMORY=1000".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sINITIAL_MEMORY=8G".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_object_suffix_validation() {
+        env::remove_var("WASIXCC_OBJECT_SUFFIX");
+        let settings = gather_user_settings(&[]).unwrap();
+        assert_eq!(settings.object_suffix, ".o");
+        let settings = gather_user_settings(&["-sOBJECT_SUFFIX=.wasm.o".to_string()]).unwrap();
+        assert_eq!(settings.object_suffix, ".wasm.o");
+        assert!(gather_user_settings(&["-sOBJECT_SUFFIX=o".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sOBJECT_SUFFIX=.".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sOBJECT_SUFFIX=./o".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_thread_stack_size_validation() {
+        env::remove_var("WASIXCC_THREAD_STACK_SIZE");
+        let settings = gather_user_settings(&["-sTHREAD_STACK_SIZE=1M".to_string()]).unwrap();
+        assert_eq!(settings.thread_stack_size, Some(1024 * 1024));
+        assert!(gather_user_settings(&["-sTHREAD_STACK_SIZE=1008".to_string()]).is_ok());
+        assert!(gather_user_settings(&["-sTHREAD_STACK_SIZE=1000".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sTHREAD_STACK_SIZE=0".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sTHREAD_STACK_SIZE=8G".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_link_features_validation() {
+        env::remove_var("WASIXCC_LINK_FEATURES");
+        env::remove_var("WASIXCC_THREADS");
+        let settings =
+            gather_user_settings(&["-sLINK_FEATURES=atomics:bulk-memory".to_string()]).unwrap();
+        assert_eq!(
+            settings.link_features,
+            Some(vec!["atomics".to_string(), "bulk-memory".to_string()])
+        );
+        assert!(gather_user_settings(&["-sLINK_FEATURES=atomics:bogus".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sLINK_FEATURES=bulk-memory".to_string()]).is_err());
+        assert!(gather_user_settings(&[
+            "-sLINK_FEATURES=bulk-memory".to_string(),
+            "-sTHREADS=0".to_string()
+        ])
+        .is_ok());
+
+        env::remove_var("WASIXCC_SIMD");
+        assert!(gather_user_settings(&[
+            "-sLINK_FEATURES=atomics:bulk-memory".to_string(),
+            "-sSIMD=1".to_string()
+        ])
+        .is_err());
+        assert!(gather_user_settings(&[
+            "-sLINK_FEATURES=atomics:simd128".to_string(),
+            "-sSIMD=1".to_string()
+        ])
+        .is_ok());
+    }
+
+    #[test]
+    fn test_abi_profile_setting() {
+        env::remove_var("WASIXCC_ABI_PROFILE");
+        env::remove_var("WASIXCC_THREADS");
+        let settings = gather_user_settings(&[]).unwrap();
+        assert_eq!(settings.abi_profile, AbiProfile::WasixThreads);
+        assert!(settings.threads);
+
+        let settings = gather_user_settings(&["-sABI_PROFILE=mvp".to_string()]).unwrap();
+        assert_eq!(settings.abi_profile, AbiProfile::Mvp);
+        assert!(!settings.threads);
+
+        let settings = gather_user_settings(&["-sTHREADS=0".to_string()]).unwrap();
+        assert_eq!(settings.abi_profile, AbiProfile::Wasix);
+
+        assert!(gather_user_settings(&["-sABI_PROFILE=wasm64".to_string()]).is_err());
+        assert!(gather_user_settings(&[
+            "-sABI_PROFILE=wasix-threads".to_string(),
+            "-sTHREADS=0".to_string()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_feature_settings() {
+        env::remove_var("WASIXCC_ABI_PROFILE");
+        env::remove_var("WASIXCC_THREADS");
+        env::remove_var("WASIXCC_FEATURE_ATOMICS");
+        env::remove_var("WASIXCC_FEATURE_BULK_MEMORY");
+        env::remove_var("WASIXCC_FEATURE_MUTABLE_GLOBALS");
+
+        let settings = gather_user_settings(&["-sFEATURE_ATOMICS=0".to_string()]).unwrap();
+        assert!(!settings.threads);
+        assert!(!settings.compile_features().contains(&"atomics"));
+        assert!(gather_user_settings(&[
+            "-sFEATURE_ATOMICS=0".to_string(),
+            "-sTHREADS=1".to_string()
+        ])
+        .is_err());
+
+        // Turns off threads too, unless they were asked for
+        let settings = gather_user_settings(&["-sFEATURE_BULK_MEMORY=0".to_string()]).unwrap();
+        assert!(!settings.threads);
+        assert_eq!(settings.compile_features(), ["mutable-globals"]);
+        assert!(gather_user_settings(&[
+            "-sFEATURE_BULK_MEMORY=0".to_string(),
+            "-sTHREADS=1".to_string()
+        ])
+        .is_err());
+
+        let settings = gather_user_settings(&[
+            "-sABI_PROFILE=mvp".to_string(),
+            "-sFEATURE_BULK_MEMORY=1".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(settings.compile_features(), ["bulk-memory"]);
+
+        assert!(gather_user_settings(&[
+            "-sFEATURE_MUTABLE_GLOBALS=0".to_string(),
+            "-sTARGET_FEATURES=bulk-memory".to_string()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_cxx_exceptions_setting() {
+        env::remove_var("WASIXCC_WASM_EXCEPTIONS");
+        env::remove_var("WASIXCC_CXX_EXCEPTIONS");
+        let settings = gather_user_settings(&["-sCXX_EXCEPTIONS=1".to_string()]).unwrap();
+        assert_eq!(settings.exceptions_mode, ExceptionsMode::Wasm);
+        let settings = gather_user_settings(&["-sCXX_EXCEPTIONS=0".to_string()]).unwrap();
+        assert_eq!(settings.exceptions_mode, ExceptionsMode::None);
+        assert!(gather_user_settings(&[
+            "-sCXX_EXCEPTIONS=1".to_string(),
+            "-sWASM_EXCEPTIONS=0".to_string()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_exceptions_mode_setting() {
+        env::remove_var("WASIXCC_EXCEPTIONS_MODE");
+        env::remove_var("WASIXCC_WASM_EXCEPTIONS");
+        env::remove_var("WASIXCC_CXX_EXCEPTIONS");
+        let exceptions_mode = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            gather_user_settings(&args).map(|settings| settings.exceptions_mode)
+        };
+
+        assert_eq!(exceptions_mode(&[]).unwrap(), ExceptionsMode::None);
+        for (name, mode) in [
+            ("wasm", ExceptionsMode::Wasm),
+            ("sjlj", ExceptionsMode::Sjlj),
+            ("none", ExceptionsMode::None),
+        ] {
+            let arg = format!("-sEXCEPTIONS_MODE={name}");
+            assert_eq!(exceptions_mode(&[&arg]).unwrap(), mode);
+        }
+        assert_eq!(
+            exceptions_mode(&["-sWASM_EXCEPTIONS=1"]).unwrap(),
+            ExceptionsMode::Wasm
+        );
+        assert_eq!(
+            exceptions_mode(&["-sEXCEPTIONS_MODE=sjlj", "-sWASM_EXCEPTIONS=0"]).unwrap(),
+            ExceptionsMode::Sjlj
+        );
+        assert!(exceptions_mode(&["-sEXCEPTIONS_MODE=sjlj", "-sCXX_EXCEPTIONS=1"]).is_err());
+        assert!(exceptions_mode(&["-sEXCEPTIONS_MODE=none", "-sWASM_EXCEPTIONS=1"]).is_err());
+        assert!(exceptions_mode(&["-sEXCEPTIONS_MODE=dwarf"]).is_err());
+    }
+
+    #[test]
+    fn test_setjmp_setting() {
+        env::remove_var("WASIXCC_SETJMP");
+        env::remove_var("WASIXCC_EXCEPTIONS_MODE");
+        env::remove_var("WASIXCC_WASM_EXCEPTIONS");
+        env::remove_var("WASIXCC_CXX_EXCEPTIONS");
+        let settings = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            gather_user_settings(&args).map(|settings| (settings.exceptions_mode, settings.setjmp))
+        };
+
+        assert_eq!(settings(&[]).unwrap(), (ExceptionsMode::None, false));
+        assert_eq!(
+            settings(&["-sSETJMP=1"]).unwrap(),
+            (ExceptionsMode::Sjlj, true)
+        );
+        assert_eq!(
+            settings(&["-sEXCEPTIONS_MODE=sjlj"]).unwrap(),
+            (ExceptionsMode::Sjlj, true)
+        );
+        assert_eq!(
+            settings(&["-sSETJMP=1", "-sCXX_EXCEPTIONS=1"]).unwrap(),
+            (ExceptionsMode::Wasm, true)
+        );
+        assert_eq!(
+            settings(&["-sEXCEPTIONS_MODE=wasm"]).unwrap(),
+            (ExceptionsMode::Wasm, false)
+        );
+        assert!(settings(&["-sSETJMP=0", "-sEXCEPTIONS_MODE=sjlj"]).is_err());
+    }
+
+    #[test]
+    fn test_pgo_settings() {
+        env::remove_var("WASIXCC_PGO_GEN");
+        env::remove_var("WASIXCC_PGO_USE");
+        let settings = gather_user_settings(&[]).unwrap();
+        assert!(!settings.pgo_generate);
+        assert_eq!(settings.pgo_use, None);
+        let settings = gather_user_settings(&["-sPGO_GEN=1".to_string()]).unwrap();
+        assert!(settings.pgo_generate);
+        let settings = gather_user_settings(&["-sPGO_USE=app.profdata".to_string()]).unwrap();
+        assert_eq!(settings.pgo_use, Some(PathBuf::from("app.profdata")));
+        assert!(gather_user_settings(&[
+            "-sPGO_GEN=1".to_string(),
+            "-sPGO_USE=app.profdata".to_string()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_crt1_settings() {
+        env::remove_var("WASIXCC_CRT1");
+        env::remove_var("WASIXCC_NO_CRT1");
+        let tmp = TempDir::new().unwrap();
+        let crt1 = tmp.path().join("mycrt1.o");
+        fs::write(&crt1, b"\0asm\x01\0\0\0").unwrap();
+        let crt1_arg = format!("-sCRT1={}", crt1.display());
+
+        let settings = gather_user_settings(std::slice::from_ref(&crt1_arg)).unwrap();
+        assert_eq!(settings.crt1, Some(crt1.clone()));
+        assert!(
+            gather_user_settings(&["-sNO_CRT1=1".to_string()])
+                .unwrap()
+                .no_crt1
+        );
+        assert!(gather_user_settings(&[crt1_arg, "-sNO_CRT1=1".to_string()]).is_err());
+
+        let missing = format!("-sCRT1={}", tmp.path().join("missing.o").display());
+        assert!(gather_user_settings(&[missing]).is_err());
+        fs::write(&crt1, "int main;").unwrap();
+        let err = gather_user_settings(&[format!("-sCRT1={}", crt1.display())]).unwrap_err();
+        assert!(err.to_string().contains("isn't a wasm object file"));
+    }
+
+    #[test]
+    fn test_table_settings() {
+        env::remove_var("WASIXCC_EXPORT_TABLE");
+        env::remove_var("WASIXCC_IMPORT_TABLE");
+        let settings = gather_user_settings(&[]).unwrap();
+        assert!(!settings.export_table && !settings.import_table);
+        assert!(
+            gather_user_settings(&["-sEXPORT_TABLE=1".to_string()])
+                .unwrap()
+                .export_table
+        );
+        assert!(
+            gather_user_settings(&["-sIMPORT_TABLE=1".to_string()])
+                .unwrap()
+                .import_table
+        );
+        assert!(gather_user_settings(&[
+            "-sEXPORT_TABLE=1".to_string(),
+            "-sIMPORT_TABLE=1".to_string()
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_import_undefined_setting() {
+        env::remove_var("WASIXCC_IMPORT_UNDEFINED");
+        assert_eq!(gather_user_settings(&[]).unwrap().import_undefined, None);
+        let settings =
+            gather_user_settings(&["-sIMPORT_UNDEFINED=ignore-all".to_string()]).unwrap();
+        assert_eq!(
+            settings.import_undefined,
+            Some(UnresolvedSymbols::IgnoreAll)
+        );
+        assert!(gather_user_settings(&["-sIMPORT_UNDEFINED=1".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_component_setting() {
+        env::remove_var("WASIXCC_COMPONENT");
+        env::remove_var("WASIXCC_ADAPTER");
+        let settings = gather_user_settings(&[
+            "-sCOMPONENT=1".to_string(),
+            "-sADAPTER=adapter.wasm".to_string(),
+        ])
+        .unwrap();
+        assert!(settings.component);
+        assert_eq!(settings.adapter, Some(PathBuf::from("adapter.wasm")));
+        assert!(gather_user_settings(&["-sCOMPONENT=1".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sADAPTER=adapter.wasm".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_lto_jobs_setting() {
+        env::remove_var("WASIXCC_LTO_JOBS");
+        assert_eq!(gather_user_settings(&[]).unwrap().lto_jobs, None);
+        let settings = gather_user_settings(&["-sLTO_JOBS=8".to_string()]).unwrap();
+        assert_eq!(settings.lto_jobs, Some(8));
+        assert!(gather_user_settings(&["-sLTO_JOBS=0".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_soname_setting() {
+        env::remove_var("WASIXCC_SONAME");
+        assert_eq!(gather_user_settings(&[]).unwrap().soname, None);
+        let settings = gather_user_settings(&["-sSONAME=libfoo.so.1".to_string()]).unwrap();
+        assert_eq!(settings.soname.as_deref(), Some("libfoo.so.1"));
+        assert!(gather_user_settings(&["-sSONAME=lib/libfoo.so".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_cxx_stdlib_setting() {
+        env::remove_var("WASIXCC_CXX_STDLIB");
+        assert!(gather_user_settings(&[]).unwrap().cxx_stdlib);
+        let settings = gather_user_settings(&["-sCXX_STDLIB=none".to_string()]).unwrap();
+        assert!(!settings.cxx_stdlib);
+        let settings = gather_user_settings(&["-sCXX_STDLIB=libc++".to_string()]).unwrap();
+        assert!(settings.cxx_stdlib);
+        assert!(gather_user_settings(&["-sCXX_STDLIB=libstdc++".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_sanitizer_setting() {
+        env::remove_var("WASIXCC_SANITIZER");
+        let settings = gather_user_settings(&["-sSANITIZER=undefined".to_string()]).unwrap();
+        assert_eq!(settings.sanitizer, Some(Sanitizer::Undefined));
+        let err = gather_user_settings(&["-sSANITIZER=address".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("not supported"), "{err}");
+        assert!(gather_user_settings(&["-sSANITIZER=thread".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_reproducible_setting() {
+        env::remove_var("WASIXCC_REPRODUCIBLE");
+        env::remove_var("WASIXCC_AR_DETERMINISTIC");
+        let settings = gather_user_settings(&["-sREPRODUCIBLE=1".to_string()]).unwrap();
+        assert!(settings.reproducible);
+        assert!(settings.ar_deterministic);
+
+        let settings = gather_user_settings(&[
+            "-sREPRODUCIBLE=1".to_string(),
+            "-sAR_DETERMINISTIC=0".to_string(),
+        ])
+        .unwrap();
+        assert!(!settings.ar_deterministic);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_compiler_with_args() {
+        use crate::compiler::tests::{fake_toolchain, FAKE_CLANG};
+        let tmp = fake_toolchain(&[("clang", FAKE_CLANG)]);
+
+        let output = tmp.path().join("a.o");
+        run_compiler_with_args(
+            vec![
+                format!("-sLLVM_LOCATION={}", tmp.path().display()),
+                "-c".to_owned(),
+                "a.c".to_owned(),
+                format!("-sSYSROOT={}", tmp.path().display()),
+                "-o".to_owned(),
+                output.to_str().unwrap().to_owned(),
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "object\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_compiler_builds_archive() {
+        use crate::compiler::tests::{fake_toolchain, FAKE_CLANG};
+        // llvm-ar lists the names of the members instead of archiving them
+        let tmp = fake_toolchain(&[
+            ("clang", FAKE_CLANG),
+            (
+                "llvm-ar",
+                "out=\"$2\"\n\
+                shift 2\n\
+                for member; do basename \"$member\"; done > \"$out\"\n",
+            ),
+        ]);
+
+        let output = tmp.path().join("libfoo.a");
+        let build = |inputs: &[&str]| {
+            let mut args = vec![
+                format!("-sLLVM_LOCATION={}", tmp.path().display()),
+                format!("-sSYSROOT={}", tmp.path().display()),
+                "-o".to_owned(),
+                output.to_str().unwrap().to_owned(),
+            ];
+            args.extend(inputs.iter().map(|input| input.to_string()));
+            run_compiler_with_args(args, false).unwrap();
+            fs::read_to_string(&output).unwrap()
+        };
+        assert_eq!(build(&["a.c", "b.c"]), "a.o\nb.o\n");
+        assert_eq!(
+            build(&["a.c", "sub/a.c", "-sOBJECT_SUFFIX=.obj"]),
+            "a.obj\na.1.obj\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_nm_symbols() {
+        let symbols = parse_nm_symbols(
+            "lib.a[a.o]:\n\
+            main T 0 0\n\
+            printf U\n\
+            hook w\n\
+            helper t 10 4\n",
+        );
+        let symbol = |name: &str, kind, defined| Symbol {
+            name: name.to_owned(),
+            kind,
+            defined,
+        };
+        assert_eq!(
+            symbols,
+            vec![
+                symbol("main", 'T', true),
+                symbol("printf", 'U', false),
+                symbol("hook", 'w', false),
+                symbol("helper", 't', true),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_symbols_with_settings() {
+        let tmp =
+            crate::compiler::tests::fake_toolchain(&[("llvm-nm", "printf 'api_init T 0 0\\n'")]);
+
+        let mut user_settings = crate::compiler::tests::test_user_settings();
+        user_settings.llvm_location = LlvmLocation::FromPath(tmp.path().to_owned());
+        let symbols = list_symbols_with_settings(Path::new("a.o"), &user_settings).unwrap();
+        assert_eq!(
+            symbols,
+            vec![Symbol {
+                name: "api_init".to_owned(),
+                kind: 'T',
+                defined: true
+            }]
+        );
+    }
+
+    #[test]
+    fn test_output_format_setting() {
+        env::remove_var("WASIXCC_OUTPUT_FORMAT");
+        env::remove_var("WASIXCC_MODULE_KIND");
+        let module_kind = |args: &[&str]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+            gather_user_settings(&args).map(|settings| settings.module_kind)
+        };
+
+        assert_eq!(
+            module_kind(&["-sOUTPUT_FORMAT=object"]).unwrap(),
+            Some(ModuleKind::ObjectFile)
+        );
+        assert_eq!(
+            module_kind(&["-sOUTPUT_FORMAT=wasm"]).unwrap(),
+            Some(ModuleKind::StaticMain)
+        );
+        assert_eq!(
+            module_kind(&["-sOUTPUT_FORMAT=wasm", "-sMODULE_KIND=shared-library"]).unwrap(),
+            Some(ModuleKind::SharedLibrary)
+        );
+        assert_eq!(
+            module_kind(&["-sOUTPUT_FORMAT=object", "-sMODULE_KIND=object-file"]).unwrap(),
+            Some(ModuleKind::ObjectFile)
+        );
+        assert!(module_kind(&["-sOUTPUT_FORMAT=object", "-sMODULE_KIND=static-main"]).is_err());
+        assert!(module_kind(&["-sOUTPUT_FORMAT=wasm", "-sMODULE_KIND=object-file"]).is_err());
+        assert_eq!(
+            module_kind(&["-sOUTPUT_FORMAT=archive"]).unwrap(),
+            Some(ModuleKind::Archive)
+        );
+        assert!(module_kind(&["-sOUTPUT_FORMAT=archive", "-sMODULE_KIND=object-file"]).is_err());
+        assert!(module_kind(&["-sOUTPUT_FORMAT=elf"]).is_err());
+    }
+
+    #[test]
+    fn test_standalone_setting() {
+        env::remove_var("WASIXCC_STANDALONE");
+        env::remove_var("WASIXCC_THREADS");
+        env::remove_var("WASIXCC_ABI_PROFILE");
+        let settings = gather_user_settings(&["-sSTANDALONE=1".to_string()]).unwrap();
+        assert!(settings.standalone);
+        assert!(!settings.threads);
+        assert_eq!(settings.abi_profile, AbiProfile::Wasix);
+
+        let settings = gather_user_settings(&[
+            "-sSTANDALONE=1".to_string(),
+            "-sABI_PROFILE=mvp".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(settings.abi_profile, AbiProfile::Mvp);
+
+        assert!(
+            gather_user_settings(&["-sSTANDALONE=1".to_string(), "-sTHREADS=1".to_string()])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_target_settings() {
+        env::remove_var("WASIXCC_TARGET");
+        env::remove_var("WASIXCC_TARGET_FEATURES");
+        env::remove_var("WASIXCC_THREADS");
+        env::remove_var("WASIXCC_ABI_PROFILE");
+        let settings = gather_user_settings(&[]).unwrap();
+        assert_eq!(settings.target, "wasm32-wasi");
+        assert_eq!(
+            settings.compile_features(),
+            ["bulk-memory", "mutable-globals", "atomics"]
+        );
+
+        let settings = gather_user_settings(&[
+            "-sTARGET=wasm64-wasi".to_string(),
+            "-sTARGET_FEATURES=atomics:bulk-memory:simd128".to_string(),
+        ])
+        .unwrap();
+        assert!(settings.is_memory64());
+        assert_eq!(
+            settings.compile_features(),
+            ["atomics", "bulk-memory", "simd128", "memory64"]
+        );
+
+        assert!(gather_user_settings(&["-sTARGET=x86_64-linux".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sTARGET_FEATURES=bulk-memory".to_string()]).is_err());
+        assert!(gather_user_settings(&["-sTARGET_FEATURES=atomics".to_string()]).is_err());
+        assert!(gather_user_settings(&[
+            "-sTARGET_FEATURES=atomics:bulk-memory".to_string(),
+            "-sTHREADS=0".to_string()
+        ])
+        .is_err());
+        let settings = gather_user_settings(&[
+            "-sTARGET_FEATURES=sign-ext".to_string(),
+            "-sTHREADS=0".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(settings.compile_features(), ["sign-ext"]);
+    }
+
+    #[test]
+    fn test_wasm_opt_level_setting() {
+        env::remove_var("WASIXCC_WASM_OPT_LEVEL");
+        for value in ["z", "Oz", "-Oz"] {
+            let settings = gather_user_settings(&[format!("-sWASM_OPT_LEVEL={value}")]).unwrap();
+            assert_eq!(settings.wasm_opt_level, Some(OptLevel::Oz));
+        }
+        assert!(gather_user_settings(&["-sWASM_OPT_LEVEL=fast".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_lto_cache_settings() {
+        env::remove_var("WASIXCC_LTO_CACHE");
+        env::remove_var("WASIXCC_LTO_CACHE_DIR");
+        env::remove_var("WASIXCC_LTO_CACHE_POLICY");
+        let settings = gather_user_settings(&[
+            "-sLTO_CACHE_DIR=/cache".to_string(),
+            "-sLTO_CACHE_POLICY=cache_size=10%:prune_interval=20m".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(settings.lto_cache, Some(PathBuf::from("/cache")));
+        assert_eq!(
+            settings.lto_cache_policy,
+            Some("cache_size=10%:prune_interval=20m".to_string())
+        );
+
+        let settings = gather_user_settings(&["-sLTO_CACHE=/other".to_string()]).unwrap();
+        assert_eq!(settings.lto_cache, Some(PathBuf::from("/other")));
+
+        assert!(gather_user_settings(&["-sLTO_CACHE_POLICY=prune_after=1h".to_string()]).is_err());
+    }
+
     #[test]
     fn test_separate_user_settings_args() {
         let args = vec![
@@ -298,15 +2144,42 @@ mod tests {
     fn test_try_get_user_setting_value_arg_and_env() {
         let args = vec!["-sFOO=bar".to_string()];
         env::remove_var("WASIXCC_FOO");
-        let got = try_get_user_setting_value("FOO", &args).unwrap();
+        let got = try_get_user_setting_value("FOO", &args, &HashMap::new()).unwrap();
         assert_eq!(got, Some("bar".to_string()));
         // fallback to env
         let args2: Vec<String> = Vec::new();
         env::set_var("WASIXCC_FOO", "baz");
-        let got2 = try_get_user_setting_value("FOO", &args2).unwrap();
+        let got2 = try_get_user_setting_value("FOO", &args2, &HashMap::new()).unwrap();
         assert_eq!(got2, Some("baz".to_string()));
     }
 
+    #[test]
+    fn test_user_setting_precedence() {
+        let config = HashMap::from([
+            ("PRECEDENCE_A".to_string(), "config".to_string()),
+            ("PRECEDENCE_B".to_string(), "config".to_string()),
+            ("PRECEDENCE_C".to_string(), "config".to_string()),
+        ]);
+        env::set_var("WASIXCC_PRECEDENCE_A", "env");
+        env::set_var("WASIXCC_PRECEDENCE_B", "env");
+        env::remove_var("WASIXCC_PRECEDENCE_C");
+        env::remove_var("WASIXCC_PRECEDENCE_D");
+        let args = vec!["-sPRECEDENCE_A=arg".to_string()];
+
+        let get = |name| try_get_user_setting_value(name, &args, &config).unwrap();
+        assert_eq!(get("PRECEDENCE_A"), Some("arg".to_string()));
+        assert_eq!(get("PRECEDENCE_B"), Some("env".to_string()));
+        assert_eq!(get("PRECEDENCE_C"), Some("config".to_string()));
+        assert_eq!(get("PRECEDENCE_D"), None);
+
+        env::remove_var("WASIXCC_PIC");
+        let config = HashMap::from([("PIC".to_string(), "true".to_string())]);
+        let settings = gather_user_settings_with_config(&[], &config).unwrap();
+        assert!(settings.pic);
+        let settings = gather_user_settings_with_config(&["-sPIC=0".to_string()], &config).unwrap();
+        assert!(!settings.pic);
+    }
+
     #[test]
     fn test_gather_user_settings() {
         let args = vec![
@@ -336,31 +2209,48 @@ mod tests {
             vec!["m".to_string(), "n".to_string()]
         );
         assert_eq!(settings.module_kind, Some(ModuleKind::SharedLibrary));
-        assert!(settings.wasm_exceptions);
+        assert_eq!(settings.exceptions_mode, ExceptionsMode::Wasm);
         assert!(!settings.pic);
     }
 
     #[test]
     fn test_run_command_success_and_failure() {
         // assume 'true' and 'false' are available on PATH
-        run_command(Command::new("true")).unwrap();
-        let err = run_command(Command::new("false")).unwrap_err();
+        let user_settings = crate::compiler::tests::test_user_settings();
+        run_command(Command::new("true"), &user_settings).unwrap();
+        let err = run_command(Command::new("false"), &user_settings).unwrap_err();
         let msg = format!("{:?}", err);
         assert!(msg.contains("Command failed"));
     }
 
+    #[test]
+    fn test_run_command_missing_tool() {
+        let mut user_settings = crate::compiler::tests::test_user_settings();
+        user_settings.llvm_location = LlvmLocation::FromPath(PathBuf::from("/nonexistent/llvm"));
+        let command = Command::new(user_settings.get_tool_path("wasm-ld"));
+        let msg = run_command(command, &user_settings)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("Could not find \"/nonexistent/llvm/wasm-ld\""));
+        assert!(msg.contains("in \"/nonexistent/llvm\""));
+        assert!(msg.contains("LLVM_LOCATION"));
+        assert!(!msg.contains("Command failed"));
+
+        user_settings
+            .tool_overrides
+            .insert("clang", PathBuf::from("/nonexistent/cc"));
+        let command = Command::new(user_settings.get_tool_path("clang"));
+        let msg = run_command(command, &user_settings)
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("set through the CC environment variable"));
+    }
+
     #[cfg(unix)]
     #[test]
     fn test_run_tool_with_passthrough_args() {
-        use std::os::unix::fs::PermissionsExt;
-        let tmp = TempDir::new().unwrap();
-        let bin = tmp.path().join("bin");
-        fs::create_dir_all(&bin).unwrap();
-        let tool_path = bin.join("dummytool");
-        fs::write(&tool_path, "#!/bin/sh\nexit 0").unwrap();
-        let mut perm = fs::metadata(&tool_path).unwrap().permissions();
-        perm.set_mode(0o755);
-        fs::set_permissions(&tool_path, perm).unwrap();
+        let tmp = crate::compiler::tests::fake_toolchain(&[("dummytool", "exit 0")]);
+        let bin = tmp.path().to_owned();
         let user_settings = UserSettings {
             sysroot_location: None,
             llvm_location: LlvmLocation::FromPath(bin.clone()),
@@ -369,10 +2259,162 @@ mod tests {
             run_wasm_opt: None,
             wasm_opt_flags: vec![],
             module_kind: None,
-            wasm_exceptions: false,
+            exceptions_mode: ExceptionsMode::None,
+            setjmp: false,
             pic: false,
+            initial_memory: None,
+            abi_profile: AbiProfile::WasixThreads,
+            threads: true,
+            exported_functions: vec![],
+            export_all: false,
+            object_suffix: ".o".to_owned(),
+            thread_stack_size: None,
+            repro_script: None,
+            link_features: None,
+            ar_deterministic: false,
+            linker_map: None,
+            lto_cache: None,
+            lto_cache_policy: None,
+            emit_relocs: false,
+            assertions: None,
+            exports_from_objects: vec![],
+            print_config: false,
+            strict_aliasing: true,
+            minimal_exports: false,
+            analyze: None,
+            gc_sections: false,
+            reproducible: false,
+            verbose: false,
+            emit_wat: false,
+            wasm_opt_level: None,
+            target: "wasm32-wasi".to_owned(),
+            target_features: None,
+            standalone: false,
+            json_diagnostics: false,
+            save_temps: None,
+            sanitizer: None,
+            coverage: false,
+            cxx_stdlib: true,
+            soname: None,
+            pgo_generate: false,
+            pgo_use: None,
+            temp_dir: None,
+            simd: false,
+            timings: false,
+            crt1: None,
+            no_crt1: false,
+            export_table: false,
+            import_table: false,
+            no_default_flags: false,
+            jobs: 1,
+            version_script: None,
+            keep_names: false,
+            extra_lib_dirs: vec![],
+            entry: None,
+            import_undefined: None,
+            component: false,
+            adapter: None,
+            feature_bulk_memory: None,
+            feature_mutable_globals: None,
+            lto_jobs: None,
+            tool_overrides: HashMap::new(),
         };
         run_tool_with_passthrough_args("dummytool", vec!["X".into(), "Y".into()], user_settings)
             .unwrap();
     }
+
+    #[test]
+    fn test_gather_tool_overrides() {
+        let env = HashMap::from([
+            ("CC", "/opt/llvm/bin/clang"),
+            ("CXX", "/usr/local/bin/wasixcc++"),
+            ("LD", ""),
+        ]);
+        let overrides = gather_tool_overrides(|name| env.get(name).map(|v| v.to_string()));
+        assert_eq!(
+            overrides,
+            HashMap::from([("clang", PathBuf::from("/opt/llvm/bin/clang"))])
+        );
+
+        let user_settings = UserSettings {
+            sysroot_location: None,
+            llvm_location: LlvmLocation::FromPath(PathBuf::from("/llvm/bin")),
+            extra_compiler_flags: vec![],
+            extra_linker_flags: vec![],
+            run_wasm_opt: None,
+            wasm_opt_flags: vec![],
+            module_kind: None,
+            exceptions_mode: ExceptionsMode::None,
+            setjmp: false,
+            pic: false,
+            initial_memory: None,
+            abi_profile: AbiProfile::WasixThreads,
+            threads: true,
+            exported_functions: vec![],
+            export_all: false,
+            object_suffix: ".o".to_owned(),
+            thread_stack_size: None,
+            repro_script: None,
+            link_features: None,
+            ar_deterministic: false,
+            linker_map: None,
+            lto_cache: None,
+            lto_cache_policy: None,
+            emit_relocs: false,
+            assertions: None,
+            exports_from_objects: vec![],
+            print_config: false,
+            strict_aliasing: true,
+            minimal_exports: false,
+            analyze: None,
+            gc_sections: false,
+            reproducible: false,
+            verbose: false,
+            emit_wat: false,
+            wasm_opt_level: None,
+            target: "wasm32-wasi".to_owned(),
+            target_features: None,
+            standalone: false,
+            json_diagnostics: false,
+            save_temps: None,
+            sanitizer: None,
+            coverage: false,
+            cxx_stdlib: true,
+            soname: None,
+            pgo_generate: false,
+            pgo_use: None,
+            temp_dir: None,
+            simd: false,
+            timings: false,
+            crt1: None,
+            no_crt1: false,
+            export_table: false,
+            import_table: false,
+            no_default_flags: false,
+            jobs: 1,
+            version_script: None,
+            keep_names: false,
+            extra_lib_dirs: vec![],
+            entry: None,
+            import_undefined: None,
+            component: false,
+            adapter: None,
+            feature_bulk_memory: None,
+            feature_mutable_globals: None,
+            lto_jobs: None,
+            tool_overrides: overrides,
+        };
+        assert_eq!(
+            user_settings.get_tool_path("clang"),
+            PathBuf::from("/opt/llvm/bin/clang")
+        );
+        assert_eq!(
+            user_settings.get_tool_path("clang++"),
+            PathBuf::from("/llvm/bin/clang++")
+        );
+        assert_eq!(
+            user_settings.get_tool_path("wasm-ld"),
+            PathBuf::from("/llvm/bin/wasm-ld")
+        );
+    }
 }
@@ -1,28 +1,59 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-const COMMANDS: &[&str] = &["cc", "++", "cc++", "ar", "nm", "ranlib", "ld"];
+const COMMANDS: &[&str] = &["cc", "++", "cc++", "ar", "nm", "ranlib", "readobj", "ld"];
 
-fn setup_tracing() {
-    let fmt_layer = fmt::layer()
-        .with_target(true)
-        .with_ansi(true)
-        .with_thread_ids(true)
-        .with_span_events(fmt::format::FmtSpan::CLOSE)
-        .with_writer(std::io::stderr)
-        .compact();
+/// Tool names build systems look for after a `<triple>-` prefix, e.g.
+/// wasm32-wasi-gcc for `./configure --host=wasm32-wasi`.
+const TRIPLE_COMMANDS: &[&str] = &[
+    "cc", "gcc", "clang", "c++", "g++", "clang++", "ar", "nm", "ranlib", "readobj", "ld",
+];
 
+/// Logs go to stderr in the compact human format, or as one JSON object per line
+/// with WASIXCC_LOG_FORMAT=json, e.g. for log aggregators. Both include span
+/// close events, which carry each span's busy and idle time.
+fn setup_tracing() {
     let filter_layer = EnvFilter::builder()
         .with_default_directive(LevelFilter::OFF.into())
         .from_env_lossy();
+    let registry = tracing_subscriber::registry().with(filter_layer);
+
+    let json = match std::env::var("WASIXCC_LOG_FORMAT") {
+        Ok(format) if format == "json" => true,
+        Ok(format) if format == "compact" => false,
+        Ok(format) => {
+            eprintln!(
+                "Warning: Unknown WASIXCC_LOG_FORMAT {format}; expected json or compact, \
+                using compact"
+            );
+            false
+        }
+        Err(_) => false,
+    };
 
-    tracing_subscriber::registry()
-        .with(filter_layer)
-        .with(fmt_layer)
-        .init();
+    if json {
+        let fmt_layer = fmt::layer()
+            .json()
+            .with_target(true)
+            .with_thread_ids(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_span_events(fmt::format::FmtSpan::CLOSE)
+            .with_writer(std::io::stderr);
+        registry.with(fmt_layer).init();
+    } else {
+        let fmt_layer = fmt::layer()
+            .with_target(true)
+            .with_ansi(true)
+            .with_thread_ids(true)
+            .with_span_events(fmt::format::FmtSpan::CLOSE)
+            .with_writer(std::io::stderr)
+            .compact();
+        registry.with(fmt_layer).init();
+    }
 }
 
 fn get_command() -> Result<String> {
@@ -34,42 +65,203 @@ fn get_command() -> Result<String> {
         .to_str()
         .context("Non-UTF8 characters in executable name")?;
 
+    match parse_command_name(executable_name) {
+        Some(command_name) => Ok(command_name),
+        None => bail!(
+            "Failed to get command name; this binary must be run with a name in \
+            the form 'wasix-<command-name>', 'wasix<command-name>` or \
+            '<wasm-triple>-<tool>', such as wasix-cc or wasm32-wasi-cc; given \
+            {executable_name}",
+        ),
+    }
+}
+
+fn parse_command_name(executable_name: &str) -> Option<String> {
     if let Some(command_name) = executable_name.strip_prefix("wasix-") {
-        Ok(command_name.to_owned())
+        return Some(command_name.to_owned());
     } else if let Some(command_name) = executable_name.strip_prefix("wasix") {
-        Ok(command_name.to_owned())
-    } else {
-        bail!(
-            "Failed to get command name; this binary must be run with a name in \
-            the form 'wasix-<command-name>' or 'wasix<command-name>`, such as \
-            wasix-cc; given {executable_name}",
-        )
+        return Some(command_name.to_owned());
+    }
+
+    // wasm32-wasi-cc, wasm32-wasmer-wasi-wasixcc and so on
+    if !(executable_name.starts_with("wasm32-") || executable_name.starts_with("wasm64-")) {
+        return None;
     }
+    let (_, tool) = executable_name.rsplit_once('-')?;
+    let tool = tool.strip_prefix("wasix").unwrap_or(tool);
+    let command_name = match tool {
+        "cc" | "gcc" | "clang" => "cc",
+        "++" | "cc++" | "c++" | "g++" | "clang++" => "++",
+        "ar" | "nm" | "ranlib" | "readobj" | "ld" => tool,
+        _ => return None,
+    };
+    Some(command_name.to_owned())
 }
 
-fn install_executables() -> Result<()> {
-    let path = PathBuf::from(
-        std::env::args()
-            .nth(2)
-            .context("Usage: wasixcc install-executables <PATH>")?,
-    );
+fn run_install_executables() -> Result<()> {
+    const USAGE: &str = "Usage: wasixcc install-executables [--dry-run] [--force] [--verbose] \
+        [--triple <TRIPLE>] <PATH>";
 
-    std::fs::create_dir_all(&path)
-        .with_context(|| format!("Failed to create directory at {path:?}"))?;
+    let mut dry_run = false;
+    let mut options = InstallOptions::default();
+    let mut triple = None;
+    let mut path = None;
+    let mut args = std::env::args().skip(2);
+    while let Some(arg) = args.next() {
+        if arg == "--dry-run" {
+            dry_run = true;
+        } else if arg == "--force" {
+            options.force = true;
+        } else if arg == "--verbose" {
+            options.verbose = true;
+        } else if arg == "--triple" {
+            let value = args.next().context(USAGE)?;
+            if !(value.starts_with("wasm32-") || value.starts_with("wasm64-")) {
+                bail!("Invalid triple {value}; expected a wasm32 or wasm64 triple");
+            }
+            triple = Some(value);
+        } else if path.is_none() {
+            path = Some(PathBuf::from(arg));
+        } else {
+            bail!("{USAGE}");
+        }
+    }
+    let path = path.context(USAGE)?;
 
     let exe_path = std::env::current_exe().context("Failed to get current executable path")?;
 
-    for command in COMMANDS {
-        let target = path.join(format!("wasix{}", command));
+    if dry_run {
+        print_install_targets(&path, &exe_path, triple.as_deref(), options.force);
+    } else {
+        let summary = install_executables(&path, &exe_path, triple.as_deref(), options)?;
+        println!(
+            "Installed commands in {path:?}: {} created, {} updated, {} unchanged",
+            summary.created, summary.updated, summary.unchanged
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct InstallOptions {
+    // Replace files and symlinks to something other than wasixcc
+    force: bool,
+    // Print what happened to each command, not just the summary
+    verbose: bool,
+}
 
-        if std::fs::metadata(&target).is_ok() {
-            std::fs::remove_file(&target)
-                .with_context(|| format!("Failed to remove existing file at {target:?}"))?;
+#[derive(Debug, Default, PartialEq, Eq)]
+struct InstallSummary {
+    created: usize,
+    updated: usize,
+    unchanged: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum InstallAction {
+    Create,
+    // Replaces whatever is there, with --force
+    Update,
+    Unchanged,
+    // Something else is in the way and --force wasn't given
+    Collision(String),
+}
+
+/// The wasix-prefixed commands, plus `<triple>-<tool>` names if a triple is
+/// given.
+fn install_targets(path: &Path, triple: Option<&str>) -> Vec<PathBuf> {
+    let mut targets = COMMANDS
+        .iter()
+        .map(|command| path.join(format!("wasix{}", command)))
+        .collect::<Vec<_>>();
+    if let Some(triple) = triple {
+        targets.extend(
+            TRIPLE_COMMANDS
+                .iter()
+                .map(|tool| path.join(format!("{triple}-{tool}"))),
+        );
+    }
+    targets
+}
+
+fn install_action(target: &Path, exe_path: &Path, force: bool) -> InstallAction {
+    let existing = match std::fs::symlink_metadata(target) {
+        Ok(metadata) if metadata.is_symlink() => match std::fs::read_link(target) {
+            Ok(link_target) if link_target == exe_path => return InstallAction::Unchanged,
+            Ok(link_target) => format!("is a symlink to {link_target:?}"),
+            Err(_) => "is an unreadable symlink".to_owned(),
+        },
+        Ok(_) => "exists and is not a symlink".to_owned(),
+        Err(_) => return InstallAction::Create,
+    };
+
+    if force {
+        InstallAction::Update
+    } else {
+        InstallAction::Collision(existing)
+    }
+}
+
+fn print_install_targets(path: &Path, exe_path: &Path, triple: Option<&str>, force: bool) {
+    for target in install_targets(path, triple) {
+        match install_action(&target, exe_path, force) {
+            InstallAction::Create => println!("{target:?}: does not exist, will be created"),
+            InstallAction::Update => println!("{target:?}: exists, will be replaced"),
+            InstallAction::Unchanged => println!("{target:?}: already installed, unchanged"),
+            InstallAction::Collision(existing) => {
+                println!("{target:?}: {existing}, needs --force to be replaced")
+            }
+        }
+    }
+}
+
+/// Nothing is touched if any of the targets collides with something that
+/// isn't ours, so a failed install doesn't leave a partial set of commands.
+fn install_executables(
+    path: &Path,
+    exe_path: &Path,
+    triple: Option<&str>,
+    options: InstallOptions,
+) -> Result<InstallSummary> {
+    let actions = install_targets(path, triple)
+        .into_iter()
+        .map(|target| {
+            let action = install_action(&target, exe_path, options.force);
+            (target, action)
+        })
+        .collect::<Vec<_>>();
+    if let Some((target, InstallAction::Collision(existing))) = actions
+        .iter()
+        .find(|(_, action)| matches!(action, InstallAction::Collision(_)))
+    {
+        bail!("{target:?} {existing}; use --force to replace it");
+    }
+
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("Failed to create directory at {path:?}"))?;
+
+    let mut summary = InstallSummary::default();
+    for (target, action) in actions {
+        match action {
+            InstallAction::Unchanged => {
+                summary.unchanged += 1;
+                if options.verbose {
+                    println!("Unchanged command {target:?}");
+                }
+                continue;
+            }
+            InstallAction::Update => {
+                std::fs::remove_file(&target)
+                    .with_context(|| format!("Failed to remove existing file at {target:?}"))?;
+                summary.updated += 1;
+            }
+            InstallAction::Create => summary.created += 1,
+            InstallAction::Collision(_) => unreachable!(),
         }
 
         #[cfg(unix)]
         {
-            std::os::unix::fs::symlink(&exe_path, &target)
+            std::os::unix::fs::symlink(exe_path, &target)
                 .with_context(|| format!("Failed create symlink at {target:?}"))?;
             let permissions = std::os::unix::fs::PermissionsExt::from_mode(0o755);
             std::fs::set_permissions(&target, permissions)
@@ -80,12 +272,87 @@ fn install_executables() -> Result<()> {
             bail!("wasixcc only supports installation on unix systems at this time");
         }
 
-        println!("Created command {target:?}");
+        if options.verbose {
+            println!("Installed command {target:?}");
+        }
+    }
+
+    Ok(summary)
+}
+
+fn run_uninstall_executables() -> Result<()> {
+    const USAGE: &str = "Usage: wasixcc uninstall-executables <PATH>";
+
+    let mut args = std::env::args().skip(2);
+    let path = PathBuf::from(args.next().context(USAGE)?);
+    if args.next().is_some() {
+        bail!("{USAGE}");
     }
 
+    let exe_path = std::env::current_exe().context("Failed to get current executable path")?;
+    let removed = uninstall_executables(&path, &exe_path)?;
+    if removed.is_empty() {
+        println!("No wasixcc commands found in {path:?}; nothing to remove");
+    }
+    for target in removed {
+        println!("Removed command {target:?}");
+    }
     Ok(())
 }
 
+/// Removes the symlinks in `path` that install-executables could have created:
+/// those with a command name we answer to, pointing at this binary or at
+/// another one named wasixcc, e.g. from before the toolchain moved. Anything
+/// else is left alone, including real files that happen to have those names.
+/// Only the names install_executables creates, for any triple, so other
+/// symlinks to wasixcc (e.g. a hand-made wasix-run) are left alone.
+fn is_installed_name(name: &str) -> bool {
+    if let Some(command) = name.strip_prefix("wasix") {
+        return COMMANDS.contains(&command);
+    }
+    if !(name.starts_with("wasm32-") || name.starts_with("wasm64-")) {
+        return false;
+    }
+    name.rsplit_once('-')
+        .is_some_and(|(_, tool)| TRIPLE_COMMANDS.contains(&tool))
+}
+
+fn uninstall_executables(path: &Path, exe_path: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => {
+            return Err(error).with_context(|| format!("Failed to read directory {path:?}"))
+        }
+    };
+
+    let mut removed = vec![];
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to read directory {path:?}"))?;
+        let target = entry.path();
+        let is_command = entry.file_name().to_str().is_some_and(is_installed_name);
+        if !is_command {
+            continue;
+        }
+
+        let Ok(link_target) = std::fs::read_link(&target) else {
+            continue;
+        };
+        let points_at_wasixcc = link_target == exe_path
+            || link_target.file_name() == Some(std::ffi::OsStr::new("wasixcc"));
+        if !points_at_wasixcc {
+            continue;
+        }
+
+        std::fs::remove_file(&target)
+            .with_context(|| format!("Failed to remove symlink at {target:?}"))?;
+        removed.push(target);
+    }
+
+    removed.sort();
+    Ok(removed)
+}
+
 fn print_version() {
     let version = env!("CARGO_PKG_VERSION");
 
@@ -94,10 +361,31 @@ fn print_version() {
 
 fn run() -> Result<()> {
     if matches!(std::env::args().nth(1), Some(x) if x == "install-executables") {
-        return install_executables();
+        return run_install_executables();
+    }
+
+    if matches!(std::env::args().nth(1), Some(x) if x == "uninstall-executables") {
+        return run_uninstall_executables();
+    }
+
+    if matches!(std::env::args().nth(1), Some(x) if x == "check-link") {
+        return wasixcc::run_check_link();
+    }
+
+    if matches!(std::env::args().nth(1), Some(x) if x == "batch") {
+        return wasixcc::run_batch();
+    }
+
+    if matches!(std::env::args().nth(1), Some(x) if x == "doctor") {
+        return wasixcc::run_doctor();
+    }
+
+    if std::env::args().any(|arg| arg == "--print-features") {
+        return wasixcc::run_print_features();
     }
 
-    if std::env::args().any(|arg| arg == "--version" || arg == "-v") {
+    // -v is clang's verbose flag, so it's passed on rather than handled here
+    if std::env::args().any(|arg| arg == "--version") {
         print_version();
         return Ok(());
     }
@@ -110,6 +398,7 @@ fn run() -> Result<()> {
         "ar" => wasixcc::run_ar(),
         "nm" => wasixcc::run_nm(),
         "ranlib" => wasixcc::run_ranlib(),
+        "readobj" => wasixcc::run_readobj(),
         cmd => bail!("Unknown command {cmd}"),
     }
 }
@@ -125,3 +414,154 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_install_targets_creates_nothing() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("bin");
+        let exe_path = tmp.path().join("wasixcc");
+        print_install_targets(&path, &exe_path, None, false);
+        assert!(!path.exists());
+
+        std::fs::create_dir(&path).unwrap();
+        std::fs::write(path.join("wasixcc"), "").unwrap();
+        print_install_targets(&path, &exe_path, Some("wasm32-wasi"), true);
+        let entries: Vec<_> = std::fs::read_dir(&path).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_executables_creates_all_commands() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("bin");
+        let exe_path = tmp.path().join("wasixcc");
+        std::fs::write(&exe_path, "").unwrap();
+        let options = InstallOptions::default();
+        install_executables(&path, &exe_path, None, options).unwrap();
+
+        for command in ["cc", "++", "ar", "nm", "ranlib", "readobj", "ld"] {
+            let target = path.join(format!("wasix{command}"));
+            assert_eq!(std::fs::read_link(&target).unwrap(), exe_path);
+        }
+        assert!(!path.join("wasm32-wasi-cc").exists());
+
+        install_executables(&path, &exe_path, Some("wasm32-wasi"), options).unwrap();
+        for tool in ["cc", "gcc", "c++", "ar", "ranlib"] {
+            let target = path.join(format!("wasm32-wasi-{tool}"));
+            assert_eq!(std::fs::read_link(&target).unwrap(), exe_path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_install_executables_is_idempotent() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("bin");
+        let exe_path = tmp.path().join("wasixcc");
+        std::fs::write(&exe_path, "").unwrap();
+        let options = InstallOptions::default();
+
+        let summary = install_executables(&path, &exe_path, None, options).unwrap();
+        assert_eq!(summary.created, COMMANDS.len());
+        let summary = install_executables(&path, &exe_path, None, options).unwrap();
+        assert_eq!(
+            summary,
+            InstallSummary {
+                created: 0,
+                updated: 0,
+                unchanged: COMMANDS.len(),
+            }
+        );
+
+        // Neither a symlink elsewhere nor a real file is replaced without --force
+        let other = tmp.path().join("other");
+        std::fs::remove_file(path.join("wasixar")).unwrap();
+        std::os::unix::fs::symlink(&other, path.join("wasixar")).unwrap();
+        std::fs::remove_file(path.join("wasixnm")).unwrap();
+        std::fs::write(path.join("wasixnm"), "real").unwrap();
+        let err = install_executables(&path, &exe_path, None, options).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{err}");
+        assert_eq!(std::fs::read_link(path.join("wasixar")).unwrap(), other);
+        assert_eq!(std::fs::read(path.join("wasixnm")).unwrap(), b"real");
+
+        let options = InstallOptions {
+            force: true,
+            ..options
+        };
+        let summary = install_executables(&path, &exe_path, None, options).unwrap();
+        assert_eq!(summary.updated, 2);
+        assert_eq!(summary.unchanged, COMMANDS.len() - 2);
+        assert_eq!(std::fs::read_link(path.join("wasixnm")).unwrap(), exe_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_uninstall_executables() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        let path = tmp.path().join("bin");
+        let exe_path = tmp.path().join("wasixcc");
+        std::fs::write(&exe_path, "").unwrap();
+        assert!(uninstall_executables(&path, &exe_path).unwrap().is_empty());
+
+        let options = InstallOptions::default();
+        install_executables(&path, &exe_path, Some("wasm32-wasi"), options).unwrap();
+        // From an older install elsewhere
+        std::os::unix::fs::symlink("/old/bin/wasixcc", path.join("wasm64-wasi-cc")).unwrap();
+        // Not ours, even though they look like it
+        std::os::unix::fs::symlink("/usr/bin/clang", path.join("wasm32-wasip1-clang")).unwrap();
+        // Ours, but not names we install
+        for name in ["wasix-run", "wasixfoo", "wasm32-wasi-wasixcc"] {
+            std::os::unix::fs::symlink(&exe_path, path.join(name)).unwrap();
+        }
+        for name in ["wasixld", "wasixnm"] {
+            std::fs::remove_file(path.join(name)).unwrap();
+            std::fs::write(path.join(name), "real").unwrap();
+        }
+
+        let removed = uninstall_executables(&path, &exe_path).unwrap();
+        assert_eq!(
+            removed.len(),
+            COMMANDS.len() + TRIPLE_COMMANDS.len() - 2 + 1
+        );
+        assert!(removed.contains(&path.join("wasm64-wasi-cc")));
+        let mut left = std::fs::read_dir(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(
+            left,
+            [
+                "wasix-run",
+                "wasixfoo",
+                "wasixld",
+                "wasixnm",
+                "wasm32-wasi-wasixcc",
+                "wasm32-wasip1-clang"
+            ]
+        );
+
+        assert!(uninstall_executables(&path, &exe_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_command_name() {
+        let command = |name| parse_command_name(name);
+        assert_eq!(command("wasixcc").as_deref(), Some("cc"));
+        assert_eq!(command("wasix-ar").as_deref(), Some("ar"));
+        assert_eq!(command("wasm32-wasi-wasixcc").as_deref(), Some("cc"));
+        assert_eq!(command("wasm32-wasi-cc").as_deref(), Some("cc"));
+        assert_eq!(command("wasm32-wasi-gcc").as_deref(), Some("cc"));
+        assert_eq!(command("wasm32-wasmer-wasi-clang++").as_deref(), Some("++"));
+        assert_eq!(command("wasm32-wasi-wasixcc++").as_deref(), Some("++"));
+        assert_eq!(command("wasm64-wasi-ranlib").as_deref(), Some("ranlib"));
+        assert_eq!(command("wasm32-wasi-strip"), None);
+        assert_eq!(command("x86_64-linux-gnu-gcc"), None);
+        assert_eq!(command("cc"), None);
+    }
+}
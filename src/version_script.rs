@@ -0,0 +1,149 @@
+//! Linker version scripts, which wasm-ld doesn't read itself. Only the part
+//! that decides what a module exports is supported, e.g.:
+//!
+//! ```text
+//! LIBFOO_1.0 {
+//!     global: foo_init; foo_run;
+//!     local: *;
+//! };
+//! ```
+//!
+//! The global symbols become `--export`s, and `local: *` turns off
+//! `--export-dynamic` so nothing else gets exported.
+
+use super::*;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct VersionScript {
+    pub(crate) global: Vec<String>,
+    // Whether every symbol not listed as global is hidden, from `local: *;`
+    pub(crate) local_rest: bool,
+}
+
+pub(crate) fn read_version_script(path: &Path) -> Result<VersionScript> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read version script {path:?}"))?;
+    parse_version_script(&contents).with_context(|| format!("Invalid version script {path:?}"))
+}
+
+fn parse_version_script(contents: &str) -> Result<VersionScript> {
+    let mut result = VersionScript::default();
+
+    let contents = strip_comments(contents)?;
+    let spaced = contents
+        .replace('{', " { ")
+        .replace('}', " } ")
+        .replace(';', " ; ")
+        .replace(':', " : ");
+    let mut tokens = spaced.split_whitespace().peekable();
+
+    let mut depth = 0;
+    // Symbols before any global: or local: label are global
+    let mut global = true;
+    while let Some(token) = tokens.next() {
+        match token {
+            "{" => {
+                depth += 1;
+                if depth > 1 {
+                    bail!("Nested blocks such as extern \"C++\" are not supported");
+                }
+                global = true;
+            }
+            "}" => {
+                if depth == 0 {
+                    bail!("Unexpected }}");
+                }
+                depth -= 1;
+            }
+            ";" => (),
+            // Version names and the versions they depend on
+            _ if depth == 0 => (),
+            "global" | "local" if tokens.peek() == Some(&":") => {
+                tokens.next();
+                global = token == "global";
+            }
+            "extern" => bail!("extern blocks are not supported"),
+            "*" if !global => result.local_rest = true,
+            _ if token.contains(['*', '?', '[']) => {
+                bail!("Patterns such as {token} are not supported; list the symbols instead")
+            }
+            _ if global => result.global.push(token.to_owned()),
+            _ => bail!(
+                "Hiding individual symbols ({token}) is not supported; use local: *; \
+                to hide everything that's not global"
+            ),
+        }
+    }
+
+    if depth != 0 {
+        bail!("Unterminated block");
+    }
+    Ok(result)
+}
+
+/// Removes `#` line comments and C-style block comments.
+fn strip_comments(contents: &str) -> Result<String> {
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+    loop {
+        let line_comment = rest.find('#');
+        let block_comment = rest.find("/*");
+        match (line_comment, block_comment) {
+            (Some(line), block) if block.is_none_or(|block| line < block) => {
+                result.push_str(&rest[..line]);
+                rest = rest[line..].split_once('\n').map_or("", |(_, rest)| rest);
+                result.push('\n');
+            }
+            (_, Some(block)) => {
+                result.push_str(&rest[..block]);
+                let Some((_, after)) = rest[block + 2..].split_once("*/") else {
+                    bail!("Unterminated comment");
+                };
+                rest = after;
+                result.push(' ');
+            }
+            (None, None) => {
+                result.push_str(rest);
+                return Ok(result);
+            }
+            (Some(_), None) => unreachable!(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_script() {
+        let script = parse_version_script(
+            r#"
+            # The public ABI
+            LIBFOO_1.0 {
+                global:
+                    foo_init; /* set up */ foo_run;
+                local: *;
+            };
+            LIBFOO_1.1 { foo_stop; } LIBFOO_1.0;
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            script,
+            VersionScript {
+                global: vec!["foo_init".into(), "foo_run".into(), "foo_stop".into()],
+                local_rest: true,
+            }
+        );
+
+        let script = parse_version_script("{ global: foo; };").unwrap();
+        assert!(!script.local_rest);
+
+        assert!(parse_version_script("{ global: foo_*; };").is_err());
+        assert!(parse_version_script("{ local: bar; };").is_err());
+        assert!(parse_version_script("{ extern \"C++\" { ns::*; }; };").is_err());
+        assert!(parse_version_script("{ global: foo;").is_err());
+        assert!(parse_version_script("{ /* unterminated };").is_err());
+    }
+}